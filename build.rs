@@ -0,0 +1,30 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Captures the git commit and build time for the `/version` endpoint
+fn main() {
+    let commit = std::env::var("GIT_COMMIT")
+        .ok()
+        .filter(|c| !c.is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()?;
+            let commit = String::from_utf8(output.stdout).ok()?;
+            Some(String::from(commit.trim())).filter(|c| output.status.success() && !c.is_empty())
+        });
+    let built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    println!(
+        "cargo:rustc-env=GIT_COMMIT={}",
+        commit.unwrap_or_else(|| String::from("unknown"))
+    );
+    println!("cargo:rustc-env=BUILT_AT={}", built_at);
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,255 @@
+use serde_json::{json, Map, Value};
+use tide::http::headers::AUTHORIZATION;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+use crate::config::{Config, CONFIG};
+use crate::proxy::{self, Cidr};
+use crate::{api, content, linkcheck, post, preview, registry, routes};
+
+/// Registers the `/admin/*` routes, which are only served when `ADMIN_TOKEN` is set
+pub fn configure(app: &mut tide::Server<()>, config: &Config) {
+    let token = match &config.admin_token {
+        Some(token) => token.clone(),
+        None => return,
+    };
+    app.with(AdminGuard {
+        token,
+        allow: proxy::parse_cidrs(&config.admin_allow),
+    });
+    app.at("/admin/reload")
+        .post(reload)
+        .options(routes::options("POST"));
+    app.at("/admin/export")
+        .get(export)
+        .options(routes::options(routes::READ));
+    app.at("/admin/drafts")
+        .get(drafts)
+        .options(routes::options(routes::READ));
+    app.at("/admin/linkcheck")
+        .get(linkcheck)
+        .options(routes::options(routes::READ));
+}
+
+/// Reloads the templates from disk
+async fn reload(req: Request<()>) -> tide::Result<Response> {
+    let generation = registry::reload()?;
+    tracing::info!("reloaded templates, generation {}", generation);
+    api::json(&req, &json!({ "generation": generation }))
+}
+
+/// Exports every post as its raw frontmatter and unrendered body, for backups and
+/// migrating the content elsewhere
+async fn export(req: Request<()>) -> tide::Result<Response> {
+    let (content_dir, posts_dir) = CONFIG.with(|c| (c.content_dir.clone(), c.posts_dir()));
+    let mut posts = Vec::new();
+    for path in content::enumerate(&posts_dir).await {
+        let raw = async_std::fs::read_to_string(&path).await?;
+        let is_html = CONFIG.with(|c| c.is_html(&path));
+        let (vars, body) = post::split_frontmatter(&raw, is_html);
+        let frontmatter: Map<String, Value> = post::frontmatter_vars(vars.unwrap_or_default())
+            .map(|(k, v)| (String::from(k), json!(v)))
+            .collect();
+        let file = path.strip_prefix(&content_dir).unwrap_or(&path);
+        posts.push(json!({
+            "path": file.to_string_lossy(),
+            "format": if is_html { "html" } else { "markdown" },
+            "frontmatter": frontmatter,
+            "body": body,
+        }));
+    }
+    tracing::info!("exported {} posts", posts.len());
+    api::json(&req, &json!({ "posts": posts }))
+}
+
+/// Lists the drafts with their shareable preview urls
+async fn drafts(req: Request<()>) -> tide::Result<Response> {
+    let (posts_dir, secret) = CONFIG.with(|c| (c.posts_dir(), c.preview_secret.clone()));
+    let drafts: Vec<Value> = content::drafts(&posts_dir)
+        .await
+        .iter()
+        .map(|draft| {
+            let preview_url = secret.as_ref().map(|secret| {
+                let url = CONFIG.with(|c| c.absolute_url(draft.url()));
+                format!("{}?preview={}", url, preview::token(secret, draft.slug()))
+            });
+            json!({
+                "title": draft.title(),
+                "url": format!("/{}", draft.url()),
+                "preview_url": preview_url,
+            })
+        })
+        .collect();
+    api::json(&req, &drafts)
+}
+
+/// Checks the internal links of every page and post, reporting the broken ones
+async fn linkcheck(req: Request<()>) -> tide::Result<Response> {
+    let config = CONFIG.with(|c| c.clone());
+    let broken = linkcheck::check(&config).await;
+    tracing::info!("link check found {} broken links", broken.len());
+    api::json(&req, &json!({ "count": broken.len(), "broken": broken }))
+}
+
+/// Guards `/admin/*`: requests from addresses outside `ADMIN_ALLOW` get a 403, then
+/// requests without `Authorization: Bearer <ADMIN_TOKEN>` get a 401.
+struct AdminGuard {
+    token: String,
+    allow: Vec<Cidr>,
+}
+
+impl AdminGuard {
+    fn is_allowed(&self, req: &Request<()>) -> bool {
+        if self.allow.is_empty() {
+            return true;
+        }
+        match proxy::client_ip(req) {
+            Some(ip) => self.allow.iter().any(|cidr| cidr.contains(ip)),
+            None => false,
+        }
+    }
+
+    fn is_authorized(&self, req: &Request<()>) -> bool {
+        let header = req.header(AUTHORIZATION).map(|h| h.last().as_str());
+        match header.and_then(|h| h.strip_prefix("Bearer ")) {
+            Some(token) => constant_time_eq(token.trim().as_bytes(), self.token.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for AdminGuard {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        if !req.url().path().starts_with("/admin/") {
+            return Ok(next.run(req).await);
+        }
+        if !self.is_allowed(&req) {
+            tracing::warn!("refusing admin request from {:?}", proxy::client_ip(&req));
+            return Ok(Response::new(StatusCode::Forbidden));
+        }
+        if !self.is_authorized(&req) {
+            return Ok(Response::new(StatusCode::Unauthorized));
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+/// Compares two byte strings without short-circuiting on the first difference
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    const TOKEN: &str = "secret";
+
+    fn app() -> tide::Server<()> {
+        let config = Config {
+            admin_token: Some(String::from(TOKEN)),
+            admin_allow: vec![String::from("127.0.0.1")],
+            ..Config::default()
+        };
+        let mut app = tide::new();
+        configure(&mut app, &config);
+        app
+    }
+
+    async fn request(
+        app: &tide::Server<()>,
+        method: Method,
+        path: &str,
+        peer: &str,
+        token: Option<&str>,
+    ) -> HttpResponse {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(method, url);
+        req.set_peer_addr(Some(peer));
+        if let Some(token) = token {
+            req.insert_header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn admin_routes_need_an_allowed_address_and_the_token() {
+        let app = app();
+        let reload = |peer, token| request(&app, Method::Post, "/admin/reload", peer, token);
+        let res = reload("203.0.113.9:4000", Some(TOKEN)).await;
+        assert_eq!(res.status(), StatusCode::Forbidden);
+        assert_eq!(
+            reload("127.0.0.1:4000", None).await.status(),
+            StatusCode::Unauthorized
+        );
+        let res = reload("127.0.0.1:4000", Some("guess")).await;
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+
+        let mut res = reload("127.0.0.1:4000", Some(TOKEN)).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let body: Value = res.body_json().await.unwrap();
+        assert!(body["generation"].as_u64().unwrap() > 0);
+    }
+
+    #[async_std::test]
+    async fn admin_routes_are_absent_without_a_token() {
+        let mut app = tide::new();
+        configure(&mut app, &Config::default());
+        let res = request(&app, Method::Post, "/admin/reload", "127.0.0.1:4000", None).await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+
+    #[async_std::test]
+    async fn export_has_the_raw_frontmatter_and_body_of_every_post() {
+        let app = app();
+        let mut res = request(
+            &app,
+            Method::Get,
+            "/admin/export",
+            "127.0.0.1:4000",
+            Some(TOKEN),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let body: Value = res.body_json().await.unwrap();
+        let posts = body["posts"].as_array().unwrap();
+        let files = content::enumerate(&CONFIG.with(|c| c.posts_dir())).await;
+        assert_eq!(posts.len(), files.len());
+
+        let tide = posts
+            .iter()
+            .find(|post| post["path"] == "posts/2022-06-26-tide.md")
+            .expect("the tide post");
+        assert_eq!(tide["format"], "markdown");
+        assert_eq!(
+            tide["frontmatter"]["title"],
+            "Markdown Blog in Rust with Tide"
+        );
+        assert_eq!(tide["frontmatter"]["tags"], "[rust]");
+        let markdown = tide["body"].as_str().unwrap();
+        assert!(!markdown.starts_with("---"));
+        assert!(markdown.contains("[rust-lang](https://rust-lang.org)"));
+    }
+
+    #[async_std::test]
+    async fn the_link_check_reports_a_count_and_the_broken_links() {
+        let app = app();
+        let path = "/admin/linkcheck";
+        let mut res = request(&app, Method::Get, path, "127.0.0.1:4000", Some(TOKEN)).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let body: Value = res.body_json().await.unwrap();
+        let broken = body["broken"].as_array().unwrap();
+        assert_eq!(body["count"], broken.len());
+        let expected = linkcheck::check(&CONFIG.with(|c| c.clone())).await;
+        assert_eq!(broken.len(), expected.len());
+        for (link, expected) in broken.iter().zip(&expected) {
+            assert_eq!(link["source"], expected.source);
+            assert_eq!(link["link"], expected.link);
+            assert_eq!(link["reason"], expected.reason);
+        }
+
+        let res = request(&app, Method::Get, path, "127.0.0.1:4000", None).await;
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+    }
+}
@@ -0,0 +1,232 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use tide::{Body, Request, Response, StatusCode};
+
+use crate::config::CONFIG;
+use crate::content;
+use crate::markdown::slugify;
+use crate::post::{Post, Summary};
+use crate::{body, preview, routes};
+
+/// Bytes of the body of a batch request allowed per post asked for, enough for a long url
+const BATCH_ITEM_SIZE: usize = 2048;
+
+/// Lists every post in the `LISTING_SORT` order
+pub async fn posts(req: Request<()>) -> tide::Result<Response> {
+    let (posts_dir, order) = CONFIG.with(|c| (c.posts_dir(), c.listing_sort));
+    let mut posts: Vec<Post> = content::posts(&posts_dir).await;
+    content::sort(&mut posts, order);
+    let summaries: Vec<Summary> = posts.iter().map(Summary::from).collect();
+    json(&req, &summaries)
+}
+
+/// A tag and the number of posts carrying it
+#[derive(Serialize)]
+struct TagCount {
+    tag: String,
+    slug: String,
+    count: usize,
+}
+
+/// Lists every tag with its post count, most used first
+pub async fn tags(req: Request<()>) -> tide::Result<Response> {
+    let posts_dir = CONFIG.with(|c| c.posts_dir());
+    let mut tags: HashMap<String, TagCount> = HashMap::new();
+    for post in content::posts(&posts_dir).await {
+        // tags differing only in case or punctuation share a slug, count them once
+        let slugs: HashSet<String> = post.tags().iter().map(|tag| slugify(tag)).collect();
+        for slug in slugs {
+            let tag = post.tags().iter().find(|tag| slugify(tag) == slug);
+            let entry = tags.entry(slug.clone()).or_insert_with(|| TagCount {
+                tag: tag.cloned().unwrap_or_default(),
+                slug,
+                count: 0,
+            });
+            entry.count += 1;
+        }
+    }
+    let mut tags: Vec<TagCount> = tags.into_values().collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.slug.cmp(&b.slug)));
+    json(&req, &tags)
+}
+
+/// Returns a single post, including its rendered content
+pub async fn post(req: Request<()>) -> tide::Result<Response> {
+    let slug = routes::post_slug(&req)?;
+    let posts_dir = CONFIG.with(|c| c.posts_dir());
+    let path = match content::find(&posts_dir, &slug).await {
+        Some(path) => path,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    let post = Post::from_file(&path.to_string_lossy()).await?;
+    let preview = preview::query(req.url());
+    if post.draft() && !preview::allows(post.slug(), preview.as_deref()) {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+    if post.expired() {
+        return Ok(Response::new(CONFIG.with(|c| c.expired_status)));
+    }
+    json(&req, &post)
+}
+
+/// Returns the posts of a json array of slugs or urls, in the order asked for, with an
+/// error entry in place of each one that doesn't resolve. Asking for more than
+/// `BATCH_LIMIT` posts is a 400, and a body over `BATCH_ITEM_SIZE` bytes per post a 413.
+pub async fn batch(mut req: Request<()>) -> tide::Result<Response> {
+    let max_body = CONFIG.with(|c| c.batch_limit.max(1) * BATCH_ITEM_SIZE);
+    body::limit(&mut req, max_body).await?;
+    let items: Vec<String> = req.body_json().await.map_err(|err| {
+        tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("expected a json array of slugs: {}", err),
+        )
+    })?;
+    let (posts_dir, limit, base_url) =
+        CONFIG.with(|c| (c.posts_dir(), c.batch_limit, c.base_url.clone()));
+    if items.len() > limit {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("at most {} posts can be fetched at once", limit),
+        ));
+    }
+    let posts = content::posts(&posts_dir).await;
+    let results: Vec<Value> = items
+        .iter()
+        .map(|item| {
+            let url = item.strip_prefix(base_url.as_str()).unwrap_or(item);
+            let url = url.trim_matches('/');
+            match posts
+                .iter()
+                .find(|post| post.slug() == item || post.url() == url)
+            {
+                Some(post) => json!(post),
+                None => json!({ "slug": item, "error": "not found" }),
+            }
+        })
+        .collect();
+    json(&req, &results)
+}
+
+/// Serializes a json response, pretty-printed when requested with `?pretty=1`
+/// and compact otherwise
+pub fn json<T: Serialize>(req: &Request<()>, data: &T) -> tide::Result<Response> {
+    let pretty = req
+        .url()
+        .query_pairs()
+        .any(|(key, value)| key == "pretty" && (value == "1" || value == "true"));
+    let body = if pretty {
+        serde_json::to_string_pretty(data)?
+    } else {
+        serde_json::to_string(data)?
+    };
+    let mut body = Body::from_string(body);
+    body.set_mime("application/json");
+    let mut res = Response::new(200);
+    res.set_body(body);
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        routes::configure(&mut app);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    async fn batch(body: &str) -> HttpResponse {
+        let mut app = tide::new();
+        routes::configure(&mut app);
+        let url = Url::parse("http://localhost/api/posts/batch").unwrap();
+        let mut req = HttpRequest::new(Method::Post, url);
+        req.set_body(body);
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn posts_are_listed_compact_unless_pretty() {
+        let mut res = get("/api/posts").await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.content_type().unwrap().essence(), "application/json");
+        let compact = res.body_string().await.unwrap();
+        assert!(!compact.contains('\n'));
+        let posts: Vec<Value> = serde_json::from_str(&compact).unwrap();
+        assert!(posts.iter().any(|post| post["url"] == "/2022/06/26/tide"));
+
+        let pretty = get("/api/posts?pretty=1")
+            .await
+            .body_string()
+            .await
+            .unwrap();
+        assert!(pretty.starts_with("[\n  {"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&pretty).unwrap(),
+            json!(posts)
+        );
+    }
+
+    #[async_std::test]
+    async fn tags_are_counted_once_per_post_most_used_first() {
+        let tags: Vec<Value> = get("/api/tags").await.body_json().await.unwrap();
+        assert!(!tags.is_empty());
+        let counts: Vec<u64> = tags
+            .iter()
+            .map(|tag| tag["count"].as_u64().unwrap())
+            .collect();
+        assert!(counts.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        let posts = content::posts(&CONFIG.with(|c| c.posts_dir())).await;
+        for tag in &tags {
+            let slug = tag["slug"].as_str().unwrap();
+            assert_eq!(slug, slugify(tag["tag"].as_str().unwrap()));
+            let tagged = posts
+                .iter()
+                .filter(|post| post.tags().iter().any(|t| slugify(t) == slug))
+                .count();
+            assert_eq!(tag["count"], tagged, "{}", slug);
+        }
+    }
+
+    #[async_std::test]
+    async fn batches_answer_in_order_with_errors_for_unknown_posts() {
+        let base_url = CONFIG.with(|c| c.base_url.clone());
+        let body = json!([
+            "2022-06-26-tide",
+            "nope",
+            format!("{}/2022/06/26/tide/", base_url),
+        ]);
+        let mut res = batch(&body.to_string()).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let posts: Vec<Value> = res.body_json().await.unwrap();
+        assert_eq!(posts.len(), 3);
+        assert_eq!(posts[0]["title"], "Markdown Blog in Rust with Tide");
+        assert_eq!(posts[1], json!({ "slug": "nope", "error": "not found" }));
+        assert_eq!(posts[2], posts[0]);
+    }
+
+    #[async_std::test]
+    async fn oversized_or_malformed_batches_are_refused() {
+        let limit = CONFIG.with(|c| c.batch_limit);
+        let too_many = json!(vec!["2022-06-26-tide"; limit + 1]);
+        assert_eq!(
+            batch(&too_many.to_string()).await.status(),
+            StatusCode::BadRequest
+        );
+        assert_eq!(
+            batch("{\"slug\": 1}").await.status(),
+            StatusCode::BadRequest
+        );
+        let huge = json!(["x".repeat((limit + 1) * BATCH_ITEM_SIZE)]);
+        assert_eq!(
+            batch(&huge.to_string()).await.status(),
+            StatusCode::PayloadTooLarge
+        );
+    }
+}
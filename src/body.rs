@@ -0,0 +1,67 @@
+use async_std::io::ReadExt;
+use tide::{Body, Request, StatusCode};
+
+/// Reads the body of a request up front, refusing with a 413 bodies over `limit` bytes,
+/// whether their `Content-Length` says so or they turn out longer, then puts it back so
+/// it can be parsed as usual
+pub async fn limit(req: &mut Request<()>, limit: usize) -> tide::Result<()> {
+    let too_large = || {
+        tide::Error::from_str(
+            StatusCode::PayloadTooLarge,
+            format!("request bodies are limited to {} bytes", limit),
+        )
+    };
+    if matches!(req.len(), Some(len) if len > limit) {
+        return Err(too_large());
+    }
+    let mime = req.content_type();
+    let mut bytes = Vec::new();
+    req.take_body()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut bytes)
+        .await?;
+    if bytes.len() > limit {
+        return Err(too_large());
+    }
+    let mut body = Body::from_bytes(bytes);
+    if let Some(mime) = mime {
+        body.set_mime(mime);
+    }
+    req.set_body(body);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn post(body: Body) -> HttpResponse {
+        let mut app = tide::new();
+        app.at("/").post(|mut req: Request<()>| async move {
+            limit(&mut req, 8).await?;
+            req.body_string().await
+        });
+        let mut req = HttpRequest::new(Method::Post, Url::parse("http://localhost/").unwrap());
+        req.set_body(body);
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn bodies_within_the_limit_are_read_back() {
+        let mut res = post(Body::from_string(String::from("12345678"))).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), "12345678");
+    }
+
+    #[async_std::test]
+    async fn larger_bodies_are_refused() {
+        let res = post(Body::from_string(String::from("123456789"))).await;
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+
+        // without a length up front, the body is cut short while reading
+        let reader = async_std::io::Cursor::new(vec![b'x'; 64]);
+        let res = post(Body::from_reader(reader, None)).await;
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+    }
+}
@@ -0,0 +1,110 @@
+use std::cell::Cell;
+use tide::http::headers::USER_AGENT;
+use tide::{Middleware, Next, Request};
+
+use crate::headers;
+
+async_std::task_local! {
+    /// Whether the request currently being handled comes from a known crawler
+    static CURRENT: Cell<bool> = Cell::new(false);
+}
+
+/// Whether the request being handled comes from a known crawler, `false` outside of a request
+pub fn current() -> bool {
+    CURRENT.try_with(Cell::get).unwrap_or(false)
+}
+
+/// Whether a `User-Agent` contains one of the patterns, ignoring case
+pub fn matches(user_agent: &str, patterns: &[String]) -> bool {
+    let user_agent = user_agent.to_lowercase();
+    patterns
+        .iter()
+        .any(|pattern| user_agent.contains(&pattern.to_lowercase()))
+}
+
+/// Flags requests whose `User-Agent` matches one of `BOT_USER_AGENTS`, so pages can leave out
+/// the scripts crawlers don't run. Requests without a `User-Agent`, or one that matches
+/// nothing, get the full page.
+pub struct BotDetector {
+    patterns: Vec<String>,
+}
+
+impl BotDetector {
+    pub fn new(patterns: &[String]) -> Self {
+        BotDetector {
+            patterns: patterns.to_vec(),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for BotDetector {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let user_agent = req.header(USER_AGENT).map(|h| h.last().as_str());
+        let is_bot = matches!(user_agent, Some(ua) if matches(ua, &self.patterns));
+        CURRENT.with(|current| current.set(is_bot));
+        let mut res = next.run(req).await;
+        if !self.patterns.is_empty() {
+            // crawlers get a lighter page, shared caches must key on who asked
+            headers::vary(&mut res, "User-Agent");
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns
+            .iter()
+            .map(|pattern| String::from(*pattern))
+            .collect()
+    }
+
+    #[test]
+    fn user_agents_match_patterns_ignoring_case() {
+        let bots = patterns(&["Googlebot", "bingbot"]);
+        assert!(matches("Mozilla/5.0 (compatible; googlebot/2.1)", &bots));
+        assert!(matches("Mozilla/5.0 (compatible; BingBot/2.0)", &bots));
+        assert!(!matches(
+            "Mozilla/5.0 (X11; Linux x86_64) Firefox/101.0",
+            &bots
+        ));
+        assert!(!matches("Googlebot", &[]));
+    }
+
+    async fn get(app: &tide::Server<()>, user_agent: Option<&str>) -> HttpResponse {
+        let url = Url::parse("http://localhost/").unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if let Some(user_agent) = user_agent {
+            req.insert_header(USER_AGENT, user_agent);
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn crawlers_are_flagged_and_responses_vary_on_the_user_agent() {
+        let mut app = tide::new();
+        app.with(BotDetector::new(&patterns(&["Googlebot"])));
+        app.at("/").get(|_| async { Ok(current().to_string()) });
+
+        let mut res = get(&app, Some("Googlebot/2.1")).await;
+        assert_eq!(res.body_string().await.unwrap(), "true");
+        assert_eq!(res["Vary"].as_str(), "User-Agent");
+        let mut res = get(&app, Some("Firefox/101.0")).await;
+        assert_eq!(res.body_string().await.unwrap(), "false");
+        let mut res = get(&app, None).await;
+        assert_eq!(res.body_string().await.unwrap(), "false");
+
+        let mut app = tide::new();
+        app.with(BotDetector::new(&[]));
+        app.at("/").get(|_| async { Ok("page") });
+        assert!(get(&app, Some("Googlebot/2.1"))
+            .await
+            .header("Vary")
+            .is_none());
+    }
+}
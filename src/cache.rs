@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tide::http::headers::{HeaderName, HeaderValues, AUTHORIZATION, CACHE_CONTROL, SET_COOKIE};
+use tide::http::{Method, Mime};
+use tide::{Body, Middleware, Next, Request, Response, StatusCode};
+
+use crate::{bots, locale, registry, theme};
+
+/// Entries kept at most, so arbitrary query strings can't grow the cache without bound
+const MAX_ENTRIES: usize = 1024;
+
+/// Header telling whether a response was served from the cache
+const X_CACHE: &str = "X-Cache";
+
+/// A rendered response kept until it expires or the templates are reloaded
+struct Entry {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValues)>,
+    mime: Option<Mime>,
+    body: Vec<u8>,
+    expires: Instant,
+    generation: usize,
+}
+
+impl Entry {
+    fn is_fresh(&self) -> bool {
+        self.expires > Instant::now() && self.generation == registry::generation()
+    }
+
+    fn response(&self) -> Response {
+        let mut res = Response::new(self.status);
+        for (name, values) in &self.headers {
+            res.insert_header(name, values);
+        }
+        let mut body = Body::from_bytes(self.body.clone());
+        if let Some(mime) = &self.mime {
+            body.set_mime(mime.clone());
+        }
+        res.set_body(body);
+        res
+    }
+}
+
+/// Caches successful GET responses in memory for the ttl of the longest matching path
+/// prefix (`CACHE_TTL`), keyed by path, query, theme, locale and whether a crawler asked.
+/// An admin reload invalidates every entry, and requests sent with `Cache-Control: no-cache`
+/// skip the cache. Responses carry `X-Cache: hit` or `miss`.
+pub struct ResponseCache {
+    ttls: Vec<(String, Duration)>,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttls: &[String]) -> Self {
+        let ttls = ttls
+            .iter()
+            .filter_map(|rule| {
+                let parsed = rule
+                    .split_once('=')
+                    .and_then(|(prefix, secs)| Some((prefix.trim(), secs.trim().parse().ok()?)));
+                match parsed {
+                    Some((prefix, secs)) if prefix.starts_with('/') => {
+                        Some((String::from(prefix), Duration::from_secs(secs)))
+                    }
+                    _ => {
+                        tracing::warn!("ignoring cache rule {}, expected /path=seconds", rule);
+                        None
+                    }
+                }
+            })
+            .collect();
+        ResponseCache {
+            ttls,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Time to live of responses for a path, if they are cached at all
+    fn ttl(&self, path: &str) -> Option<Duration> {
+        self.ttls
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, ttl)| *ttl)
+            .filter(|ttl| !ttl.is_zero())
+    }
+
+    fn is_cacheable(req: &Request<()>) -> bool {
+        let bypass = req
+            .header(CACHE_CONTROL)
+            .map(|h| h.iter().any(|v| v.as_str().contains("no-cache")));
+        req.method() == Method::Get
+            && req.header(AUTHORIZATION).is_none()
+            && !req.url().path().starts_with("/admin/")
+            && bypass != Some(true)
+    }
+
+    /// Stores a response when it is complete and safe to share
+    async fn store(&self, key: String, ttl: Duration, res: &mut Response) -> tide::Result<()> {
+        let private = res.header(CACHE_CONTROL).map(|h| {
+            h.iter().any(|v| {
+                ["private", "no-store"]
+                    .into_iter()
+                    .any(|d| v.as_str().contains(d))
+            })
+        });
+        if res.status() != StatusCode::Ok
+            || res.len().is_none()
+            || res.header(SET_COOKIE).is_some()
+            || private == Some(true)
+        {
+            return Ok(());
+        }
+        let mime = res.content_type();
+        let body = res.take_body().into_bytes().await?;
+        let entry = Entry {
+            status: res.status(),
+            headers: res
+                .iter()
+                .map(|(name, values)| (name.clone(), values.clone()))
+                .collect(),
+            mime,
+            body,
+            expires: Instant::now() + ttl,
+            generation: registry::generation(),
+        };
+        res.set_body(entry.response().take_body());
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| entry.is_fresh());
+        }
+        if entries.len() < MAX_ENTRIES {
+            entries.insert(key, entry);
+        }
+        Ok(())
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for ResponseCache {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let url = req.url();
+        let ttl = match self.ttl(url.path()) {
+            Some(ttl) if Self::is_cacheable(&req) => ttl,
+            _ => return Ok(next.run(req).await),
+        };
+        let key = format!(
+            "{} {} {} {}?{}",
+            theme::current().name,
+            locale::current().unwrap_or_default(),
+            bots::current(),
+            url.path(),
+            url.query().unwrap_or_default()
+        );
+
+        let cached = self.entries.lock().unwrap().get(&key).and_then(|entry| {
+            if entry.is_fresh() {
+                Some(entry.response())
+            } else {
+                None
+            }
+        });
+        if let Some(mut res) = cached {
+            tracing::debug!("serving {} from cache", key);
+            res.insert_header(X_CACHE, "hit");
+            return Ok(res);
+        }
+
+        let mut res = next.run(req).await;
+        self.store(key, ttl, &mut res).await?;
+        res.insert_header(X_CACHE, "miss");
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tide::http::{Request as HttpRequest, Response as HttpResponse, Url};
+
+    static RENDERS: AtomicUsize = AtomicUsize::new(0);
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(|rule| String::from(*rule)).collect()
+    }
+
+    #[test]
+    fn the_longest_matching_prefix_sets_the_ttl() {
+        let cache = ResponseCache::new(&rules(&["/=60", "/api=5", "/api/tags=0", "api=9", "/x=y"]));
+        assert_eq!(cache.ttl("/2022/06/26/tide"), Some(Duration::from_secs(60)));
+        assert_eq!(cache.ttl("/api/posts"), Some(Duration::from_secs(5)));
+        assert_eq!(cache.ttl("/api/tags"), None);
+        assert_eq!(ResponseCache::new(&[]).ttl("/"), None);
+    }
+
+    async fn get(app: &tide::Server<()>, path: &str, no_cache: bool) -> HttpResponse {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if no_cache {
+            req.insert_header(CACHE_CONTROL, "no-cache");
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn shareable_responses_are_served_from_the_cache() {
+        let mut app = tide::new();
+        app.with(ResponseCache::new(&rules(&["/=60"])));
+        let render = |_| async { Ok(RENDERS.fetch_add(1, Ordering::SeqCst).to_string()) };
+        app.at("/page").get(render);
+        app.at("/private").get(move |req| async move {
+            let mut res = Response::from(render(req).await?);
+            res.insert_header(CACHE_CONTROL, "private");
+            Ok(res)
+        });
+
+        let mut first = get(&app, "/page", false).await;
+        assert_eq!(first[X_CACHE].as_str(), "miss");
+        let mut second = get(&app, "/page", false).await;
+        assert_eq!(second[X_CACHE].as_str(), "hit");
+        let body = first.body_string().await.unwrap();
+        assert_eq!(second.body_string().await.unwrap(), body);
+
+        let mut other = get(&app, "/page?page=2", false).await;
+        assert_eq!(other[X_CACHE].as_str(), "miss");
+        assert_ne!(other.body_string().await.unwrap(), body);
+        let mut bypass = get(&app, "/page", true).await;
+        assert!(bypass.header(X_CACHE).is_none());
+        assert_ne!(bypass.body_string().await.unwrap(), body);
+
+        get(&app, "/private", false).await;
+        let res = get(&app, "/private", false).await;
+        assert_eq!(res[X_CACHE].as_str(), "miss");
+    }
+}
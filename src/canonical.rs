@@ -0,0 +1,93 @@
+use tide::http::headers::{HOST, LOCATION};
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+use crate::proxy;
+
+/// Permanently redirects requests made to any host other than the canonical one,
+/// keeping the scheme, path and query. The scheme is only taken from `X-Forwarded-Proto`
+/// or `Forwarded` when a trusted proxy sent them. Health checks are always let through since
+/// load balancers tend to probe by address.
+pub struct CanonicalHost {
+    host: String,
+}
+
+impl CanonicalHost {
+    pub fn new(host: &str) -> Self {
+        CanonicalHost {
+            host: host.to_lowercase(),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for CanonicalHost {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let host = req.header(HOST).map(|h| h.as_str().to_lowercase());
+        let is_canonical = host.is_none() || host.as_deref() == Some(self.host.as_str());
+        if is_canonical || req.url().path() == "/health_check" {
+            return Ok(next.run(req).await);
+        }
+
+        let url = req.url();
+        let scheme = proxy::scheme(&req);
+        let mut location = format!("{}://{}{}", scheme, self.host, url.path());
+        if let Some(query) = url.query() {
+            location.push('?');
+            location.push_str(query);
+        }
+        tracing::info!("redirecting {:?} to canonical {}", host, location);
+        let mut res = Response::new(StatusCode::MovedPermanently);
+        res.insert_header(LOCATION, location);
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(url: &str, peer: &str, proto: Option<&str>) -> HttpResponse {
+        let mut app = tide::new();
+        app.with(CanonicalHost::new("nyxtom.dev"));
+        app.at("/*").get(|_| async { Ok("ok") });
+        app.at("/health_check").get(|_| async { Ok("ok") });
+        let url = Url::parse(url).unwrap();
+        let host = String::from(url.host_str().unwrap());
+        let mut req = HttpRequest::new(Method::Get, url);
+        req.insert_header(HOST, host);
+        req.set_peer_addr(Some(peer));
+        if let Some(proto) = proto {
+            req.insert_header("X-Forwarded-Proto", proto);
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn other_hosts_redirect_keeping_path_and_query() {
+        let res = get("http://www.nyxtom.dev/about?x=1", "203.0.113.9:1234", None).await;
+        assert_eq!(res.status(), StatusCode::MovedPermanently);
+        assert_eq!(res[LOCATION], "http://nyxtom.dev/about?x=1");
+
+        let res = get("http://nyxtom.dev/about", "203.0.113.9:1234", None).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let res = get("http://10.1.2.3/health_check", "203.0.113.9:1234", None).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+
+    #[async_std::test]
+    async fn forwarded_scheme_of_untrusted_peers_is_ignored() {
+        let res = get("http://www.nyxtom.dev/", "203.0.113.9:1234", Some("https")).await;
+        assert_eq!(res[LOCATION], "http://nyxtom.dev/");
+    }
+
+    #[async_std::test]
+    async fn forwarded_scheme_of_trusted_proxies_is_kept() {
+        // read when this test's thread first loads its config
+        std::env::set_var("TRUSTED_PROXIES", "10.0.0.0/8");
+        let res = get("http://www.nyxtom.dev/", "10.0.0.2:1234", Some("https")).await;
+        assert_eq!(res[LOCATION], "https://nyxtom.dev/");
+        let res = get("http://www.nyxtom.dev/", "10.0.0.2:1234", Some("gopher")).await;
+        assert_eq!(res[LOCATION], "http://nyxtom.dev/");
+    }
+}
@@ -0,0 +1,230 @@
+use async_lock::Mutex;
+use async_std::fs;
+use chrono::{SecondsFormat, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tide::http::headers::RETRY_AFTER;
+use tide::http::mime;
+use tide::{Redirect, Request, Response, StatusCode};
+
+use crate::config::{Config, CONFIG};
+use crate::{body, content, post::Post, proxy, routes};
+
+/// Longest name accepted from a commenter
+const MAX_NAME: usize = 80;
+
+/// Longest comment accepted
+const MAX_BODY: usize = 5000;
+
+/// Links a comment may hold before it's taken for spam
+const MAX_LINKS: usize = 3;
+
+/// Largest submission accepted, in bytes, leaving room for the encoding of a full comment
+const MAX_SUBMISSION: usize = 32 * 1024;
+
+/// A comment left on a post
+#[derive(Serialize, Deserialize)]
+pub struct Comment {
+    name: String,
+    body: String,
+    date: String,
+}
+
+/// What a commenter submits, as a form or json
+#[derive(Deserialize)]
+struct Submission {
+    name: String,
+    body: String,
+}
+
+/// Serializes the read-modify-write of the comment files
+static WRITE: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// When each client last commented, to throttle them
+static LAST_COMMENT: Lazy<std::sync::Mutex<HashMap<IpAddr, Instant>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Registers `POST /:year/:month/:day/:id/comments`, only served when `COMMENTS_DIR` is set
+pub fn configure(app: &mut tide::Server<()>, config: &Config) {
+    if config.comments_dir.is_none() {
+        return;
+    }
+    app.at("/:year/:month/:day/:id/comments")
+        .post(create)
+        .options(routes::options("POST"));
+}
+
+fn file(dir: &str, slug: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.json", slug))
+}
+
+/// The comments of a post, oldest first, or none when comments are off
+pub async fn list(slug: &str) -> Vec<Comment> {
+    let dir = match CONFIG.with(|c| c.comments_dir.clone()) {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    let raw = match fs::read_to_string(file(&dir, slug)).await {
+        Ok(raw) => raw,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_str(&raw).unwrap_or_else(|err| {
+        tracing::warn!("ignoring unreadable comments of {}: {}", slug, err);
+        Vec::new()
+    })
+}
+
+/// Appends a comment to a published post, then sends the commenter back to the comments.
+/// Each client may comment once per `COMMENT_INTERVAL`, and comments that are too long or
+/// carry too many links are refused.
+async fn create(mut req: Request<()>) -> tide::Result<Response> {
+    let slug = routes::post_slug(&req)?;
+    let (posts_dir, dir, interval) = CONFIG.with(|c| {
+        (
+            c.posts_dir(),
+            c.comments_dir.clone().unwrap_or_default(),
+            c.comment_interval,
+        )
+    });
+    let post = match content::find(&posts_dir, &slug).await {
+        Some(path) => Post::from_file(&path.to_string_lossy()).await?,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    if post.draft() || post.expired() {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+    // the url may differ in case from the file, comments go by the file
+    let slug = String::from(post.slug());
+
+    body::limit(&mut req, MAX_SUBMISSION).await?;
+    let submission: Submission = if req.content_type() == Some(mime::JSON) {
+        req.body_json().await
+    } else {
+        req.body_form().await
+    }
+    .map_err(|err| {
+        tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("expected a name and body: {}", err),
+        )
+    })?;
+    let name = submission.name.trim();
+    let body = submission.body.trim();
+    if name.is_empty() || body.is_empty() {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "a comment needs a name and a body",
+        ));
+    }
+    if name.chars().count() > MAX_NAME || body.chars().count() > MAX_BODY {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!(
+                "names are limited to {} and comments to {} characters",
+                MAX_NAME, MAX_BODY
+            ),
+        ));
+    }
+    if body.matches("http://").count() + body.matches("https://").count() > MAX_LINKS {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("comments are limited to {} links", MAX_LINKS),
+        ));
+    }
+
+    if let Some(ip) = proxy::client_ip(&req) {
+        let mut last = LAST_COMMENT.lock().unwrap();
+        let now = Instant::now();
+        if let Some(at) = last.get(&ip) {
+            let elapsed = now.duration_since(*at);
+            if elapsed < interval {
+                let wait = (interval - elapsed).as_secs_f64().ceil() as u64;
+                tracing::warn!("throttling comment from {} on {}", ip, slug);
+                let mut res = Response::new(StatusCode::TooManyRequests);
+                res.insert_header(RETRY_AFTER, wait.to_string());
+                return Ok(res);
+            }
+        }
+        last.retain(|_, at| now.duration_since(*at) < interval);
+        last.insert(ip, now);
+    }
+
+    let comment = Comment {
+        name: String::from(name),
+        body: String::from(body),
+        date: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    };
+    let _guard = WRITE.lock().await;
+    let mut comments = list(&slug).await;
+    comments.push(comment);
+    fs::create_dir_all(&dir).await?;
+    fs::write(file(&dir, &slug), serde_json::to_vec_pretty(&comments)?).await?;
+    tracing::info!("new comment on {}, {} in total", slug, comments.len());
+    Ok(Redirect::see_other(format!("/{}#comments", post.url())).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn post(path: &str, content_type: mime::Mime, body: &str) -> HttpResponse {
+        let config = Config {
+            comments_dir: Some(String::from("/nonexistent/comments")),
+            ..Config::default()
+        };
+        let mut app = tide::new();
+        configure(&mut app, &config);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Post, url);
+        req.set_peer_addr(Some("127.0.0.1:4000"));
+        req.set_body(body);
+        req.set_content_type(content_type);
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn comments_are_refused_unless_well_formed() {
+        let tide = "/2022/06/26/tide/comments";
+        let links = "https://a.example ".repeat(MAX_LINKS + 1);
+        let long = "x".repeat(MAX_BODY + 1);
+        let refused = [
+            (tide, mime::FORM, String::from("name=&body=hello")),
+            (tide, mime::FORM, String::from("name=Tom")),
+            (
+                tide,
+                mime::JSON,
+                String::from("{\"name\": \"Tom\", \"body\": \"  \"}"),
+            ),
+            (
+                tide,
+                mime::JSON,
+                format!("{{\"name\": \"Tom\", \"body\": \"{}\"}}", links),
+            ),
+            (tide, mime::FORM, format!("name=Tom&body={}", long)),
+        ];
+        for (path, content_type, body) in refused {
+            let res = post(path, content_type, &body).await;
+            assert_eq!(res.status(), StatusCode::BadRequest, "{}", body);
+        }
+        let res = post("/2022/06/26/nope/comments", mime::FORM, "name=Tom&body=hi").await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+
+    #[async_std::test]
+    async fn comments_are_off_without_a_directory() {
+        let mut app = tide::new();
+        configure(&mut app, &Config::default());
+        let url = Url::parse("http://localhost/2022/06/26/tide/comments").unwrap();
+        let res: HttpResponse = app
+            .respond(HttpRequest::new(Method::Post, url))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NotFound);
+        assert!(list("2022-06-26-tide").await.is_empty());
+    }
+}
@@ -0,0 +1,800 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Upper bound accepted for any of the listener timeouts
+const MAX_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// How post urls that don't resolve are answered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotFoundBehavior {
+    /// Answers with the themed 404 page
+    Themed404,
+    /// Redirects (302) to the `SEARCH_URL` search page pre-filled with the slug
+    RedirectSearch,
+}
+
+/// Format of the log lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines
+    Pretty,
+    /// One json object per line, for log aggregation
+    Json,
+}
+
+/// Whether internal links are checked at startup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkCheck {
+    Off,
+    /// Logs broken links
+    Warn,
+    /// Logs broken links and fails startup when there are any
+    Strict,
+}
+
+/// Order posts are listed in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListingSort {
+    /// Newest first, undated posts last
+    DateDesc,
+    /// Oldest first, undated posts last
+    DateAsc,
+    /// Alphabetically by title
+    Title,
+    /// By the `weight` frontmatter, lower first, then newest first
+    Weight,
+}
+
+/// Where the permalink anchor of a heading goes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnchorPlacement {
+    /// Ahead of the heading text
+    Before,
+    /// Following the heading text
+    After,
+}
+
+/// What the home page renders
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HomePage {
+    /// The `index.md` page, with the featured posts
+    Markdown,
+    /// The list of every post
+    Listing,
+}
+
+/// Runtime configuration read from the environment.
+///
+/// | env var                   | default                       | description                                                             |
+/// |---------------------------|-------------------------------|-------------------------------------------------------------------------|
+/// | `HOST`                    | `0.0.0.0`                     | address the listener binds to                                           |
+/// | `PORT`                    | `7000`                        | port the listener binds to                                              |
+/// | `KEEP_ALIVE_TIMEOUT`      | `60`                          | seconds an idle connection is kept open                                 |
+/// | `READ_TIMEOUT`            | unset                         | seconds a single socket read may block                                  |
+/// | `WRITE_TIMEOUT`           | unset                         | seconds a single socket write may block                                 |
+/// | `MAX_CONNECTIONS`         | unset                         | connections served at once, further ones wait to be accepted            |
+/// | `UPDATED_THRESHOLD`       | `1`                           | days after publishing an update is displayed                            |
+/// | `GONE_PATH`               | `$CONTENT_DIR/gone.json`      | json list of retired urls answered with 410                             |
+/// | `REDIRECTS_PATH`          | `$CONTENT_DIR/redirects.json` | json list of redirects from old urls                                    |
+/// | `APP_ENVIRONMENT`         | `production`                  | `development` enables live reload over SSE, not a websocket             |
+/// | `POST_FOOTER`             | unset                         | markdown (or a path to it) appended to every post                       |
+/// | `CANONICAL_HOST`          | unset                         | host other hosts are redirected to (e.g. `nyxtom.dev`)                  |
+/// | `FRESHNESS_WINDOW`        | `7`                           | days a post is badged as new or updated                                 |
+/// | `CONTENT_DIR`             | `content`                     | directory holding pages, posts and assets (may be a symlink)            |
+/// | `MAINTENANCE`             | unset                         | `1` turns maintenance mode on until the next restart                    |
+/// | `MAINTENANCE_FILE`        | `.maintenance`                | maintenance mode is on while this file exists                           |
+/// | `RETRY_AFTER`             | `300`                         | seconds clients are asked to wait during maintenance                    |
+/// | `BASE_URL`                | `http://localhost:7000`       | absolute url the site is served from, used in feeds and links           |
+/// | `FEED_MAX_AGE`            | `3600`                        | seconds feeds may be cached, sent as `Cache-Control: public, max-age`   |
+/// | `MARKDOWN_EXTENSIONS`     | `md,markdown`                 | comma separated extensions treated as markdown                          |
+/// | `CONTENT_SECURITY_POLICY` | unset                         | Content-Security-Policy header sent with every response                 |
+/// | `CSP_REPORT`              | unset                         | `1` adds a `report-uri` pointing at `/csp-report`                       |
+/// | `PARSE_CONCURRENCY`       | `8`                           | posts parsed concurrently when enumerating                              |
+/// | `SITE_NAME`               | `Tom's Notes`                 | name of the site used in feeds and the web manifest                     |
+/// | `THEME_COLOR`             | `#ffffff`                     | theme color advertised to browsers                                      |
+/// | `FAVICON`                 | `favicon.ico`                 | file served at `/favicon.ico`                                           |
+/// | `ICONS`                   | unset                         | comma separated icon files listed in `/site.webmanifest`                |
+/// | `NOT_FOUND_BEHAVIOR`      | `themed_404`                  | `redirect_search` sends unknown post urls to `SEARCH_URL`               |
+/// | `SEARCH_URL`              | unset                         | external search page given `q=<slug>`, required by `redirect_search`    |
+/// | `DEFAULT_THEME`           | `light`                       | theme used when a request selects none (`light` or `dark`)              |
+/// | `TEMPLATE_DIR`            | `client/dist`                 | handlebars templates, and the svg icons of their `icons` directory      |
+/// | `STATIC_DIR`              | `TEMPLATE_DIR`                | built client assets served from `/static`                               |
+/// | `ADMIN_TOKEN`             | unset                         | bearer token required by `/admin/*`, which is disabled when unset       |
+/// | `ADMIN_ALLOW`             | unset                         | comma separated ips or cidrs allowed to reach `/admin/*`                |
+/// | `TRUSTED_PROXIES`         | unset                         | proxies whose `Forwarded` or `X-Forwarded-For` header is honored        |
+/// | `EXCERPT_LENGTH`          | `160`                         | characters of automatic listing excerpts                                |
+/// | `DESCRIPTION_LENGTH`      | `160`                         | characters of meta descriptions derived from content                    |
+/// | `ELLIPSIS`                | `…`                           | appended to truncated excerpts and descriptions                         |
+/// | `THUMB_WIDTHS`            | `160,320,640`                 | comma separated widths png assets are resized to at `/assets/thumb/:w`  |
+/// | `LOCALE`                  | `en`                          | locale used when `Accept-Language` matches none available               |
+/// | `LOCALES`                 | `en`                          | comma separated locales negotiated from `Accept-Language`               |
+/// | `RENDER_CONCURRENCY`      | `16`                          | markdown renders allowed to run at once                                 |
+/// | `RENDER_QUEUE_TIMEOUT`    | `10`                          | seconds a render waits for a slot before answering 503                  |
+/// | `LOG_FORMAT`              | `pretty`                      | `json` writes one json object per log line                              |
+/// | `LOG_LEVEL`               | `error`                       | log filter used when `RUST_LOG` is unset (e.g. `info`)                  |
+/// | `LINK_CHECK`              | `off`                         | `warn` logs broken internal links at startup, `strict` refuses to start |
+/// | `FEED_LIMIT`              | `20`                          | most recent posts included in each feed                                 |
+/// | `FEATURED_LIMIT`          | `3`                           | featured posts listed on the home page                                  |
+/// | `INLINE_CSS_LIMIT`        | `0`                           | stylesheets up to this many bytes are inlined into `<head>`             |
+/// | `PREVIEW_SECRET`          | unset                         | secret signing the `?preview=` tokens of drafts, hidden when unset      |
+/// | `CACHE_TTL`               | unset                         | comma separated `path prefix=seconds` of responses cached in memory     |
+/// | `STRICT_FRONTMATTER`      | unset                         | `1` rejects posts with frontmatter keys outside the known ones          |
+/// | `FRONTMATTER_KEYS`        | `series,cover_image`          | comma separated extra frontmatter keys allowed in strict mode           |
+/// | `HOME_PAGE`               | `markdown`                    | `listing` renders the list of posts at `/` instead of `index.md`        |
+/// | `DEFAULT_ICON`            | `📝`                           | icon of listing rows for posts without an `icon` frontmatter            |
+/// | `HOME_POST_COUNT`         | `10`                          | recent posts listed at `/` with `HOME_PAGE=listing`                     |
+/// | `FOOTNOTES_HEADING`       | `Notes`                       | heading of the footnotes section closing a post                         |
+/// | `ANCHOR_LEVELS`           | `1,2,3,4,5,6`                 | heading levels given an id and a permalink anchor                       |
+/// | `ANCHOR_SYMBOL`           | `#`                           | text or html (such as an inline svg) of the permalink anchors           |
+/// | `ANCHOR_PLACEMENT`        | `after`                       | `before` puts permalink anchors ahead of the heading text               |
+/// | `LISTING_SORT`            | `date_desc`                   | order of listings: `date_desc`, `date_asc`, `title` or `weight`         |
+/// | `LOG_NOT_FOUND`           | `1`                           | `0` stops logging 404s with their path and `Referer` as warnings        |
+/// | `BATCH_LIMIT`             | `20`                          | posts a `POST /api/posts/batch` request may ask for at once             |
+/// | `RENDER_CACHE_DIR`        | unset                         | directory rendered posts are cached in across restarts                  |
+/// | `COMMENTS_DIR`            | unset                         | directory the comments of each post are stored in, off when unset       |
+/// | `COMMENT_INTERVAL`        | `60`                          | seconds a client waits between two comments                             |
+/// | `TEXT_DIR`                | `ltr`                         | text direction of pages whose frontmatter sets no `dir` (`rtl`, `auto`) |
+/// | `STRICT_TEMPLATES`        | unset                         | `1` fails renders referencing a missing template variable               |
+/// | `EXCERPT_PARAGRAPHS`      | `2`                           | paragraphs of listing excerpts, `0` uses `EXCERPT_LENGTH` of plain text |
+/// | `EXCERPT_HEADING_LEVEL`   | `3`                           | highest heading level kept in excerpts, larger headings are demoted     |
+/// | `FEED_EXCERPTS`           | unset                         | `1` puts excerpts in the feeds instead of the full content              |
+/// | `PATH_LOWERCASE`          | unset                         | `1` lowercases paths outside `/static` and `/assets` with a redirect    |
+/// | `HUMANS_AUTHOR`           | unset                         | author named in `/humans.txt`, which is only served when set            |
+/// | `HUMANS_CREDITS`          | unset                         | comma separated people thanked in `/humans.txt`                         |
+/// | `HUMANS_TOOLS`            | unset                         | comma separated tools listed in `/humans.txt`                           |
+/// | `TAGS_PATH`               | `$CONTENT_DIR/tags.toml`      | toml tables of tag titles and descriptions, keyed by tag slug           |
+/// | `SKIP_LINK`               | `1`                           | `0` leaves out the link skipping to the content of each page            |
+/// | `BOT_USER_AGENTS`         | unset                         | comma separated `User-Agent` parts of crawlers served pages without js  |
+/// | `EDIT_BASE_URL`           | unset                         | url content file paths are appended to for the "edit this page" links   |
+/// | `EXPIRED_STATUS`          | `410`                         | status of posts past their `expires` date, `410` or `404`               |
+/// | `VIEW_DEDUP_WINDOW`       | `1800`                        | seconds repeated views of a post by a client count once                 |
+/// | `IMAGE_CDN_BASE`          | unset                         | url `/assets/` images of posts are served from instead of the site      |
+/// | `LEGAL_PATH`              | `$CONTENT_DIR/legal.json`     | json list of legally restricted urls answered with 451                  |
+/// | `POST_IDS_PATH`           | `$CONTENT_DIR/ids.json`       | json map of the numeric ids of posts, linked from `/n/:id`              |
+/// | `BLOCKED_BY`              | unset                         | url of the authority named in the `Link: rel="blocked-by"` of 451s      |
+///
+/// The listener timeouts are clamped to between 1 second and 1 hour.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub host: String,
+    pub port: String,
+    pub keep_alive_timeout: Duration,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub max_connections: Option<usize>,
+    pub updated_threshold: i64,
+    pub gone_path: String,
+    pub redirects_path: String,
+    pub environment: String,
+    pub post_footer: Option<String>,
+    pub canonical_host: Option<String>,
+    pub freshness_window: i64,
+    pub content_dir: String,
+    pub maintenance: bool,
+    pub maintenance_file: String,
+    pub retry_after: u64,
+    pub base_url: String,
+    pub feed_max_age: u64,
+    pub markdown_extensions: Vec<String>,
+    pub content_security_policy: Option<String>,
+    pub csp_report: bool,
+    pub parse_concurrency: usize,
+    pub site_name: String,
+    pub theme_color: String,
+    pub favicon: String,
+    pub icons: Vec<String>,
+    pub not_found_behavior: NotFoundBehavior,
+    pub search_url: Option<String>,
+    pub default_theme: String,
+    pub template_dir: String,
+    pub admin_token: Option<String>,
+    pub admin_allow: Vec<String>,
+    pub trusted_proxies: Vec<String>,
+    pub excerpt_length: usize,
+    pub description_length: usize,
+    pub ellipsis: String,
+    pub thumb_widths: Vec<u32>,
+    pub locale: String,
+    pub locales: Vec<String>,
+    pub render_concurrency: usize,
+    pub render_queue_timeout: Duration,
+    pub log_format: LogFormat,
+    pub log_level: Option<String>,
+    pub link_check: LinkCheck,
+    pub feed_limit: usize,
+    pub featured_limit: usize,
+    pub inline_css_limit: u64,
+    pub preview_secret: Option<String>,
+    pub cache_ttl: Vec<String>,
+    pub strict_frontmatter: bool,
+    pub frontmatter_keys: Vec<String>,
+    pub home_page: HomePage,
+    pub default_icon: String,
+    pub home_post_count: usize,
+    pub footnotes_heading: String,
+    pub anchor_levels: Vec<u8>,
+    pub anchor_symbol: String,
+    pub anchor_placement: AnchorPlacement,
+    pub listing_sort: ListingSort,
+    pub log_not_found: bool,
+    pub batch_limit: usize,
+    pub render_cache_dir: Option<String>,
+    pub comments_dir: Option<String>,
+    pub comment_interval: Duration,
+    pub text_dir: String,
+    pub strict_templates: bool,
+    pub excerpt_paragraphs: usize,
+    pub excerpt_heading_level: u8,
+    pub feed_excerpts: bool,
+    pub path_lowercase: bool,
+    pub static_dir: String,
+    pub humans_author: Option<String>,
+    pub humans_credits: Vec<String>,
+    pub humans_tools: Vec<String>,
+    pub tags_path: String,
+    pub legal_path: String,
+    pub blocked_by: Option<String>,
+    pub post_ids_path: String,
+    pub image_cdn_base: Option<String>,
+    pub skip_link: bool,
+    pub bot_user_agents: Vec<String>,
+    pub edit_base_url: Option<String>,
+    pub expired_status: u16,
+    pub view_dedup_window: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: String::from("0.0.0.0"),
+            port: String::from("7000"),
+            keep_alive_timeout: Duration::from_secs(60),
+            read_timeout: None,
+            write_timeout: None,
+            max_connections: None,
+            updated_threshold: 1,
+            gone_path: String::from("content/gone.json"),
+            redirects_path: String::from("content/redirects.json"),
+            environment: String::from("production"),
+            post_footer: None,
+            canonical_host: None,
+            freshness_window: 7,
+            content_dir: String::from("content"),
+            maintenance: false,
+            maintenance_file: String::from(".maintenance"),
+            retry_after: 300,
+            base_url: String::from("http://localhost:7000"),
+            feed_max_age: 3600,
+            markdown_extensions: vec![String::from("md"), String::from("markdown")],
+            content_security_policy: None,
+            csp_report: false,
+            parse_concurrency: 8,
+            site_name: String::from("Tom's Notes"),
+            theme_color: String::from("#ffffff"),
+            favicon: String::from("favicon.ico"),
+            icons: Vec::new(),
+            not_found_behavior: NotFoundBehavior::Themed404,
+            search_url: None,
+            default_theme: String::from("light"),
+            template_dir: String::from("client/dist"),
+            admin_token: None,
+            admin_allow: Vec::new(),
+            trusted_proxies: Vec::new(),
+            excerpt_length: 160,
+            description_length: 160,
+            ellipsis: String::from("…"),
+            thumb_widths: vec![160, 320, 640],
+            locale: String::from("en"),
+            locales: vec![String::from("en")],
+            render_concurrency: 16,
+            render_queue_timeout: Duration::from_secs(10),
+            log_format: LogFormat::Pretty,
+            log_level: None,
+            link_check: LinkCheck::Off,
+            feed_limit: 20,
+            featured_limit: 3,
+            inline_css_limit: 0,
+            preview_secret: None,
+            cache_ttl: Vec::new(),
+            strict_frontmatter: false,
+            frontmatter_keys: vec![String::from("series"), String::from("cover_image")],
+            home_page: HomePage::Markdown,
+            default_icon: String::from("📝"),
+            home_post_count: 10,
+            footnotes_heading: String::from("Notes"),
+            anchor_levels: vec![1, 2, 3, 4, 5, 6],
+            anchor_symbol: String::from("#"),
+            anchor_placement: AnchorPlacement::After,
+            listing_sort: ListingSort::DateDesc,
+            log_not_found: true,
+            batch_limit: 20,
+            render_cache_dir: None,
+            comments_dir: None,
+            comment_interval: Duration::from_secs(60),
+            text_dir: String::from("ltr"),
+            strict_templates: false,
+            excerpt_paragraphs: 2,
+            excerpt_heading_level: 3,
+            feed_excerpts: false,
+            path_lowercase: false,
+            static_dir: String::from("client/dist"),
+            humans_author: None,
+            humans_credits: Vec::new(),
+            humans_tools: Vec::new(),
+            tags_path: String::from("content/tags.toml"),
+            legal_path: String::from("content/legal.json"),
+            blocked_by: None,
+            post_ids_path: String::from("content/ids.json"),
+            image_cdn_base: None,
+            skip_link: true,
+            bot_user_agents: Vec::new(),
+            edit_base_url: None,
+            expired_status: 410,
+            view_dedup_window: Duration::from_secs(1800),
+        }
+    }
+}
+
+impl Config {
+    /// Whether the app runs in local development (`APP_ENVIRONMENT=development`)
+    pub fn is_dev(&self) -> bool {
+        self.environment == "development"
+    }
+
+    /// Directory holding the dated blog posts
+    pub fn posts_dir(&self) -> String {
+        format!("{}/posts", self.content_dir)
+    }
+
+    /// Whether a path has one of the configured markdown extensions
+    pub fn is_markdown(&self, path: &Path) -> bool {
+        let ext = path.extension().map(|ext| ext.to_string_lossy());
+        matches!(ext, Some(ext) if self.markdown_extensions.iter().any(|m| *m == ext))
+    }
+
+    /// Whether a path is an html page, rendered without markdown conversion
+    pub fn is_html(&self, path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("html" | "htm")
+        )
+    }
+
+    /// Whether a path holds a page or post, in markdown or html
+    pub fn is_content(&self, path: &Path) -> bool {
+        self.is_markdown(path) || self.is_html(path)
+    }
+
+    /// Absolute url of a path on the site, joined to `base_url` with exactly one slash
+    pub fn absolute_url(&self, path: &str) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        let path = path.trim_start_matches('/');
+        if path.is_empty() {
+            format!("{}/", base)
+        } else {
+            format!("{}/{}", base, path)
+        }
+    }
+
+    /// Url editing a content file, its path relative to the content directory joined to
+    /// `edit_base_url` with exactly one slash, when that is set
+    pub fn edit_url(&self, path: &Path) -> Option<String> {
+        let base = self.edit_base_url.as_deref()?.trim_end_matches('/');
+        // pages are read from the resolved content directory
+        let resolved = std::fs::canonicalize(&self.content_dir).ok();
+        let path = path
+            .strip_prefix(&self.content_dir)
+            .ok()
+            .or_else(|| path.strip_prefix(resolved.as_ref()?).ok())
+            .unwrap_or(path);
+        let path = path.to_string_lossy();
+        Some(format!("{}/{}", base, path.trim_start_matches('/')))
+    }
+
+    /// Reads the configuration from environment variables, falling back to the defaults
+    pub fn from_env() -> Self {
+        let defaults = Config::default();
+        let content_dir = string_var("CONTENT_DIR").unwrap_or(defaults.content_dir);
+        let template_dir = string_var("TEMPLATE_DIR").unwrap_or(defaults.template_dir);
+        let search_url = string_var("SEARCH_URL");
+        Config {
+            host: string_var("HOST").unwrap_or(defaults.host),
+            port: string_var("PORT").unwrap_or(defaults.port),
+            keep_alive_timeout: timeout_var("KEEP_ALIVE_TIMEOUT")
+                .unwrap_or(defaults.keep_alive_timeout),
+            read_timeout: timeout_var("READ_TIMEOUT").or(defaults.read_timeout),
+            write_timeout: timeout_var("WRITE_TIMEOUT").or(defaults.write_timeout),
+            max_connections: number_var("MAX_CONNECTIONS")
+                .map(|max: usize| max.max(1))
+                .or(defaults.max_connections),
+            updated_threshold: number_var("UPDATED_THRESHOLD")
+                .unwrap_or(defaults.updated_threshold),
+            gone_path: string_var("GONE_PATH")
+                .unwrap_or_else(|| format!("{}/gone.json", content_dir)),
+            redirects_path: string_var("REDIRECTS_PATH")
+                .unwrap_or_else(|| format!("{}/redirects.json", content_dir)),
+            tags_path: string_var("TAGS_PATH")
+                .unwrap_or_else(|| format!("{}/tags.toml", content_dir)),
+            legal_path: string_var("LEGAL_PATH")
+                .unwrap_or_else(|| format!("{}/legal.json", content_dir)),
+            blocked_by: string_var("BLOCKED_BY"),
+            post_ids_path: string_var("POST_IDS_PATH")
+                .unwrap_or_else(|| format!("{}/ids.json", content_dir)),
+            image_cdn_base: string_var("IMAGE_CDN_BASE")
+                .map(|url| String::from(url.trim_end_matches('/'))),
+            skip_link: string_var("SKIP_LINK").as_deref() != Some("0"),
+            bot_user_agents: list_var("BOT_USER_AGENTS").unwrap_or(defaults.bot_user_agents),
+            edit_base_url: string_var("EDIT_BASE_URL"),
+            expired_status: number_var("EXPIRED_STATUS")
+                .filter(|status| {
+                    let valid = matches!(status, 404 | 410);
+                    if !valid {
+                        tracing::warn!("ignoring EXPIRED_STATUS={}, expected 410 or 404", status);
+                    }
+                    valid
+                })
+                .unwrap_or(defaults.expired_status),
+            view_dedup_window: duration_var("VIEW_DEDUP_WINDOW")
+                .unwrap_or(defaults.view_dedup_window),
+            environment: string_var("APP_ENVIRONMENT").unwrap_or(defaults.environment),
+            post_footer: string_var("POST_FOOTER"),
+            canonical_host: string_var("CANONICAL_HOST"),
+            freshness_window: number_var("FRESHNESS_WINDOW").unwrap_or(defaults.freshness_window),
+            content_dir,
+            maintenance: string_var("MAINTENANCE").as_deref() == Some("1"),
+            maintenance_file: string_var("MAINTENANCE_FILE").unwrap_or(defaults.maintenance_file),
+            retry_after: number_var("RETRY_AFTER").unwrap_or(defaults.retry_after),
+            feed_max_age: number_var("FEED_MAX_AGE").unwrap_or(defaults.feed_max_age),
+            base_url: string_var("BASE_URL")
+                .map(|url| String::from(url.trim_end_matches('/')))
+                .unwrap_or(defaults.base_url),
+            markdown_extensions: list_var("MARKDOWN_EXTENSIONS")
+                .unwrap_or(defaults.markdown_extensions),
+            content_security_policy: string_var("CONTENT_SECURITY_POLICY"),
+            csp_report: string_var("CSP_REPORT").as_deref() == Some("1"),
+            parse_concurrency: number_var("PARSE_CONCURRENCY")
+                .unwrap_or(defaults.parse_concurrency)
+                .max(1),
+            site_name: string_var("SITE_NAME").unwrap_or(defaults.site_name),
+            theme_color: string_var("THEME_COLOR").unwrap_or(defaults.theme_color),
+            favicon: string_var("FAVICON").unwrap_or(defaults.favicon),
+            icons: list_var("ICONS").unwrap_or(defaults.icons),
+            not_found_behavior: not_found_var("NOT_FOUND_BEHAVIOR")
+                .filter(|behavior| {
+                    let valid =
+                        *behavior != NotFoundBehavior::RedirectSearch || search_url.is_some();
+                    if !valid {
+                        tracing::warn!(
+                            "ignoring NOT_FOUND_BEHAVIOR=redirect_search without a SEARCH_URL"
+                        );
+                    }
+                    valid
+                })
+                .unwrap_or(defaults.not_found_behavior),
+            search_url,
+            default_theme: string_var("DEFAULT_THEME").unwrap_or(defaults.default_theme),
+            admin_token: string_var("ADMIN_TOKEN"),
+            admin_allow: list_var("ADMIN_ALLOW").unwrap_or(defaults.admin_allow),
+            trusted_proxies: list_var("TRUSTED_PROXIES").unwrap_or(defaults.trusted_proxies),
+            excerpt_length: number_var("EXCERPT_LENGTH").unwrap_or(defaults.excerpt_length),
+            description_length: number_var("DESCRIPTION_LENGTH")
+                .unwrap_or(defaults.description_length),
+            ellipsis: string_var("ELLIPSIS").unwrap_or(defaults.ellipsis),
+            thumb_widths: list_var("THUMB_WIDTHS")
+                .map(|widths| widths.iter().filter_map(|w| w.parse().ok()).collect())
+                .unwrap_or(defaults.thumb_widths),
+            locale: string_var("LOCALE").unwrap_or(defaults.locale),
+            locales: list_var("LOCALES").unwrap_or(defaults.locales),
+            render_concurrency: number_var("RENDER_CONCURRENCY")
+                .unwrap_or(defaults.render_concurrency)
+                .max(1),
+            render_queue_timeout: duration_var("RENDER_QUEUE_TIMEOUT")
+                .unwrap_or(defaults.render_queue_timeout),
+            log_format: log_format_var("LOG_FORMAT").unwrap_or(defaults.log_format),
+            log_level: string_var("LOG_LEVEL"),
+            link_check: link_check_var("LINK_CHECK").unwrap_or(defaults.link_check),
+            feed_limit: number_var("FEED_LIMIT")
+                .unwrap_or(defaults.feed_limit)
+                .max(1),
+            featured_limit: number_var("FEATURED_LIMIT").unwrap_or(defaults.featured_limit),
+            inline_css_limit: number_var("INLINE_CSS_LIMIT").unwrap_or(defaults.inline_css_limit),
+            preview_secret: string_var("PREVIEW_SECRET"),
+            cache_ttl: list_var("CACHE_TTL").unwrap_or(defaults.cache_ttl),
+            strict_frontmatter: string_var("STRICT_FRONTMATTER").as_deref() == Some("1"),
+            frontmatter_keys: list_var("FRONTMATTER_KEYS").unwrap_or(defaults.frontmatter_keys),
+            home_page: home_page_var("HOME_PAGE").unwrap_or(defaults.home_page),
+            default_icon: string_var("DEFAULT_ICON").unwrap_or(defaults.default_icon),
+            home_post_count: number_var("HOME_POST_COUNT").unwrap_or(defaults.home_post_count),
+            footnotes_heading: string_var("FOOTNOTES_HEADING")
+                .unwrap_or(defaults.footnotes_heading),
+            anchor_levels: list_var("ANCHOR_LEVELS")
+                .map(|levels| {
+                    levels
+                        .iter()
+                        .filter_map(|level| level.parse().ok())
+                        .collect()
+                })
+                .unwrap_or(defaults.anchor_levels),
+            anchor_symbol: string_var("ANCHOR_SYMBOL").unwrap_or(defaults.anchor_symbol),
+            anchor_placement: anchor_placement_var("ANCHOR_PLACEMENT")
+                .unwrap_or(defaults.anchor_placement),
+            listing_sort: listing_sort_var("LISTING_SORT").unwrap_or(defaults.listing_sort),
+            log_not_found: string_var("LOG_NOT_FOUND").as_deref() != Some("0"),
+            batch_limit: number_var("BATCH_LIMIT").unwrap_or(defaults.batch_limit),
+            render_cache_dir: string_var("RENDER_CACHE_DIR"),
+            comments_dir: string_var("COMMENTS_DIR"),
+            comment_interval: duration_var("COMMENT_INTERVAL").unwrap_or(defaults.comment_interval),
+            text_dir: string_var("TEXT_DIR")
+                .filter(|dir| text_dir(dir))
+                .unwrap_or(defaults.text_dir),
+            strict_templates: string_var("STRICT_TEMPLATES").as_deref() == Some("1"),
+            excerpt_paragraphs: number_var("EXCERPT_PARAGRAPHS")
+                .unwrap_or(defaults.excerpt_paragraphs),
+            excerpt_heading_level: number_var("EXCERPT_HEADING_LEVEL")
+                .map(|level: u8| level.clamp(1, 6))
+                .unwrap_or(defaults.excerpt_heading_level),
+            feed_excerpts: string_var("FEED_EXCERPTS").as_deref() == Some("1"),
+            path_lowercase: string_var("PATH_LOWERCASE").as_deref() == Some("1"),
+            static_dir: string_var("STATIC_DIR").unwrap_or_else(|| template_dir.clone()),
+            template_dir,
+            humans_author: string_var("HUMANS_AUTHOR"),
+            humans_credits: list_var("HUMANS_CREDITS").unwrap_or(defaults.humans_credits),
+            humans_tools: list_var("HUMANS_TOOLS").unwrap_or(defaults.humans_tools),
+        }
+    }
+}
+
+/// Reads a non-empty environment variable
+fn string_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+/// Reads a comma separated list from an environment variable
+fn list_var(name: &str) -> Option<Vec<String>> {
+    let value = string_var(name)?;
+    Some(
+        value
+            .split(',')
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Reads a number from an environment variable, ignoring invalid values
+fn number_var<T: FromStr>(name: &str) -> Option<T> {
+    let value = string_var(name)?;
+    let number = value.trim().parse().ok();
+    if number.is_none() {
+        tracing::warn!("ignoring {}={}, expected a number", name, value);
+    }
+    number
+}
+
+/// Reads a not found behavior from an environment variable, ignoring unknown values
+fn not_found_var(name: &str) -> Option<NotFoundBehavior> {
+    let value = string_var(name)?;
+    match value.trim() {
+        "themed_404" => Some(NotFoundBehavior::Themed404),
+        "redirect_search" => Some(NotFoundBehavior::RedirectSearch),
+        _ => {
+            tracing::warn!(
+                "ignoring {}={}, expected themed_404 or redirect_search",
+                name,
+                value
+            );
+            None
+        }
+    }
+}
+
+/// Reads a log format from an environment variable, ignoring unknown values
+fn log_format_var(name: &str) -> Option<LogFormat> {
+    let value = string_var(name)?;
+    match value.trim() {
+        "pretty" => Some(LogFormat::Pretty),
+        "json" => Some(LogFormat::Json),
+        _ => {
+            tracing::warn!("ignoring {}={}, expected pretty or json", name, value);
+            None
+        }
+    }
+}
+
+/// Reads a link check mode from an environment variable, ignoring unknown values
+fn link_check_var(name: &str) -> Option<LinkCheck> {
+    let value = string_var(name)?;
+    match value.trim() {
+        "off" => Some(LinkCheck::Off),
+        "warn" => Some(LinkCheck::Warn),
+        "strict" => Some(LinkCheck::Strict),
+        _ => {
+            tracing::warn!("ignoring {}={}, expected off, warn or strict", name, value);
+            None
+        }
+    }
+}
+
+/// Reads the home page strategy from an environment variable, ignoring unknown values
+fn home_page_var(name: &str) -> Option<HomePage> {
+    let value = string_var(name)?;
+    match value.trim() {
+        "markdown" => Some(HomePage::Markdown),
+        "listing" => Some(HomePage::Listing),
+        _ => {
+            tracing::warn!("ignoring {}={}, expected markdown or listing", name, value);
+            None
+        }
+    }
+}
+
+/// Reads the order of listings from an environment variable, ignoring unknown values
+fn listing_sort_var(name: &str) -> Option<ListingSort> {
+    let value = string_var(name)?;
+    match value.trim() {
+        "date_desc" => Some(ListingSort::DateDesc),
+        "date_asc" => Some(ListingSort::DateAsc),
+        "title" => Some(ListingSort::Title),
+        "weight" => Some(ListingSort::Weight),
+        _ => {
+            tracing::warn!(
+                "ignoring {}={}, expected date_desc, date_asc, title or weight",
+                name,
+                value
+            );
+            None
+        }
+    }
+}
+
+/// Reads where permalink anchors go from an environment variable, ignoring unknown values
+fn anchor_placement_var(name: &str) -> Option<AnchorPlacement> {
+    let value = string_var(name)?;
+    match value.trim() {
+        "before" => Some(AnchorPlacement::Before),
+        "after" => Some(AnchorPlacement::After),
+        _ => {
+            tracing::warn!("ignoring {}={}, expected before or after", name, value);
+            None
+        }
+    }
+}
+
+/// Whether a value is an html `dir` attribute, warning about the ones that aren't
+pub fn text_dir(value: &str) -> bool {
+    let valid = matches!(value, "ltr" | "rtl" | "auto");
+    if !valid {
+        tracing::warn!(
+            "ignoring text direction {}, expected ltr, rtl or auto",
+            value
+        );
+    }
+    valid
+}
+
+/// Reads a duration in seconds from an environment variable, ignoring invalid values
+fn duration_var(name: &str) -> Option<Duration> {
+    let value = string_var(name)?;
+    match value.trim().parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Some(Duration::from_secs_f64(secs)),
+        _ => {
+            tracing::warn!("ignoring {}={}, expected a number of seconds", name, value);
+            None
+        }
+    }
+}
+
+/// Reads a listener timeout in seconds from an environment variable, clamped to between
+/// 1 second and `MAX_TIMEOUT`
+fn timeout_var(name: &str) -> Option<Duration> {
+    let duration = duration_var(name)?;
+    let clamped = duration.clamp(Duration::from_secs(1), MAX_TIMEOUT);
+    if clamped != duration {
+        tracing::warn!(
+            "clamping {}={} to {} seconds",
+            name,
+            duration.as_secs_f64(),
+            clamped.as_secs_f64()
+        );
+    }
+    Some(clamped)
+}
+
+thread_local! {
+    pub static CONFIG: Config = Config::from_env();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_listener_timeouts_are_clamped() {
+        std::env::set_var("TEST_DAY_WINDOW", "86400");
+        assert_eq!(
+            duration_var("TEST_DAY_WINDOW"),
+            Some(Duration::from_secs(86400))
+        );
+        assert_eq!(timeout_var("TEST_DAY_WINDOW"), Some(MAX_TIMEOUT));
+        std::env::set_var("TEST_NEGATIVE_WINDOW", "-1");
+        assert_eq!(duration_var("TEST_NEGATIVE_WINDOW"), None);
+    }
+
+    #[test]
+    fn content_files_have_a_markdown_extension_or_are_html() {
+        std::env::set_var("TEST_MARKDOWN_EXTENSIONS", "md, mdx,");
+        let config = Config {
+            markdown_extensions: list_var("TEST_MARKDOWN_EXTENSIONS").unwrap(),
+            ..Config::default()
+        };
+        assert_eq!(config.markdown_extensions, vec!["md", "mdx"]);
+        for (file, content) in [
+            ("post.md", true),
+            ("post.mdx", true),
+            ("post.html", true),
+            ("post.markdown", false),
+            ("post.txt", false),
+            ("md", false),
+        ] {
+            assert_eq!(config.is_content(Path::new(file)), content, "{}", file);
+        }
+        assert!(!config.is_markdown(Path::new("post.html")));
+    }
+
+    #[test]
+    fn absolute_urls_join_with_exactly_one_slash() {
+        for base_url in ["https://nyxtom.dev", "https://nyxtom.dev/"] {
+            let config = Config {
+                base_url: String::from(base_url),
+                ..Config::default()
+            };
+            for path in ["feed.xml", "/feed.xml"] {
+                assert_eq!(config.absolute_url(path), "https://nyxtom.dev/feed.xml");
+            }
+            assert_eq!(config.absolute_url("/"), "https://nyxtom.dev/");
+            assert_eq!(config.absolute_url(""), "https://nyxtom.dev/");
+        }
+    }
+
+    #[test]
+    fn anchor_placement_is_before_or_after() {
+        std::env::set_var("TEST_ANCHOR_BEFORE", " before ");
+        assert_eq!(
+            anchor_placement_var("TEST_ANCHOR_BEFORE"),
+            Some(AnchorPlacement::Before)
+        );
+        std::env::set_var("TEST_ANCHOR_AFTER", "after");
+        assert_eq!(
+            anchor_placement_var("TEST_ANCHOR_AFTER"),
+            Some(AnchorPlacement::After)
+        );
+        std::env::set_var("TEST_ANCHOR_INSIDE", "inside");
+        assert_eq!(anchor_placement_var("TEST_ANCHOR_INSIDE"), None);
+    }
+
+    #[test]
+    fn edit_urls_join_the_content_path_to_the_base() {
+        let config = Config {
+            edit_base_url: Some(String::from(
+                "https://github.com/me/notes/edit/main/content/",
+            )),
+            ..Config::default()
+        };
+        let base = "https://github.com/me/notes/edit/main/content";
+        assert_eq!(
+            config.edit_url(Path::new("content/posts/2022-06-26-tide.md")),
+            Some(format!("{}/posts/2022-06-26-tide.md", base))
+        );
+        let resolved = std::fs::canonicalize("content").unwrap().join("about.md");
+        assert_eq!(
+            config.edit_url(&resolved),
+            Some(format!("{}/about.md", base))
+        );
+        assert_eq!(
+            Config::default().edit_url(Path::new("content/about.md")),
+            None
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use async_std::task;
+use chrono::{NaiveDate, Utc};
+use futures_util::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::{
+    config::{Config, ListingSort, CONFIG},
+    post::Post,
+    routes::PAGES,
+};
+
+/// Parsed posts by path, along with the day they were parsed on
+static INDEX: Lazy<Mutex<HashMap<PathBuf, (NaiveDate, Post)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Lists every markdown and html file below `dir`, sorted by path. Symlinked directories are
+/// followed so posts can live outside the crate, while directories that were already
+/// visited (symlink cycles) are skipped with a warning.
+pub async fn enumerate(dir: &str) -> Vec<PathBuf> {
+    let dir = PathBuf::from(dir);
+    task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        walk(&dir, &mut visited, &mut files);
+        files.sort();
+        files
+    })
+    .await
+}
+
+/// Reads every published post below `dir`, newest first. Drafts, posts scheduled for a
+/// later day and expired posts are left out.
+pub async fn posts(dir: &str) -> Vec<Post> {
+    let mut posts = parse_all(dir).await;
+    posts.retain(|post| !post.draft() && !post.expired());
+    posts
+}
+
+/// Reads the drafts and scheduled posts below `dir`, newest first
+pub async fn drafts(dir: &str) -> Vec<Post> {
+    let mut posts = parse_all(dir).await;
+    posts.retain(Post::draft);
+    posts
+}
+
+/// Orders posts for a listing according to `LISTING_SORT`. Undated posts go last when
+/// sorting by date, and posts without a weight go after the weighted ones.
+pub fn sort(posts: &mut [Post], order: ListingSort) {
+    let by_date = |a: &Post, b: &Post| b.date().cmp(&a.date());
+    posts.sort_by(|a, b| {
+        let order = match order {
+            ListingSort::DateDesc => by_date(a, b),
+            ListingSort::DateAsc => match (a.date(), b.date()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (a, b) => b.cmp(&a),
+            },
+            ListingSort::Title => a.title().to_lowercase().cmp(&b.title().to_lowercase()),
+            ListingSort::Weight => match (a.weight(), b.weight()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (a, b) => b.cmp(&a),
+            }
+            .then_with(|| by_date(a, b)),
+        };
+        order.then_with(|| a.url().cmp(b.url()))
+    });
+}
+
+/// Parses every post below `dir` into the index ahead of the first request, logging how
+/// long it took
+pub async fn index(dir: &str) {
+    let started = Instant::now();
+    let posts = parse_all(dir).await;
+    tracing::info!("indexed {} posts in {:?}", posts.len(), started.elapsed());
+}
+
+/// Reads every post below `dir`, newest first. Posts come from the index unless their
+/// file changed or the day turned since they were parsed, which changes whether they
+/// are scheduled, expired or fresh. The others are parsed concurrently, bounded by the
+/// configured concurrency, and replace their entries.
+async fn parse_all(dir: &str) -> Vec<Post> {
+    let files = enumerate(dir).await;
+    let today = Utc::today().naive_utc();
+    let mut posts = Vec::new();
+    let mut stale = Vec::new();
+    {
+        let mut index = INDEX.lock().unwrap();
+        // forget the posts that were removed
+        let listed: HashSet<&PathBuf> = files.iter().collect();
+        index.retain(|path, _| !path.starts_with(dir) || listed.contains(path));
+        for path in files.iter() {
+            let modified = std::fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .ok();
+            match index.get(path) {
+                Some((day, post)) if *day == today && post.modified() == modified => {
+                    posts.push(post.clone())
+                }
+                _ => stale.push(path.clone()),
+            }
+        }
+    }
+
+    let concurrency = CONFIG.with(|c| c.parse_concurrency);
+    let parsed = parse(stale, concurrency).await;
+    let mut index = INDEX.lock().unwrap();
+    for (path, post) in parsed {
+        index.insert(path, (today, post.clone()));
+        posts.push(post);
+    }
+    drop(index);
+    posts.sort_by(newest_first);
+    posts
+}
+
+/// Parses post files with up to `concurrency` of them at once, newest first. Posts that
+/// fail to parse are logged and skipped.
+async fn parse(files: Vec<PathBuf>, concurrency: usize) -> Vec<(PathBuf, Post)> {
+    let parsed: Vec<_> = stream::iter(files)
+        .map(|path| async move {
+            let post = Post::from_file(&path.to_string_lossy()).await;
+            (path, post)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut posts = Vec::new();
+    for (path, post) in parsed {
+        match post {
+            Ok(post) => posts.push((path, post)),
+            Err(err) => tracing::warn!("skipping post {:?}: {}", path, err),
+        }
+    }
+    // parsing finishes in any order, so sort deterministically
+    posts.sort_by(|(_, a), (_, b)| newest_first(a, b));
+    posts
+}
+
+/// Orders posts newest first, by url when they share a date
+fn newest_first(a: &Post, b: &Post) -> std::cmp::Ordering {
+    b.date().cmp(&a.date()).then_with(|| a.url().cmp(b.url()))
+}
+
+/// Checks every page and post parses when `STRICT_FRONTMATTER` is on, failing startup
+/// on the ones with unknown frontmatter keys
+pub async fn validate(config: &Config) -> std::io::Result<()> {
+    if !config.strict_frontmatter {
+        return Ok(());
+    }
+    let pages = PAGES
+        .iter()
+        .map(|(_, file)| Path::new(&config.content_dir).join(file));
+    let files: Vec<PathBuf> = pages.chain(enumerate(&config.posts_dir()).await).collect();
+    let mut invalid = 0;
+    for file in files {
+        match Post::from_file(&file.to_string_lossy()).await {
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                tracing::error!("{}", err);
+                invalid += 1;
+            }
+            _ => {}
+        }
+    }
+    if invalid > 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} posts with invalid frontmatter (STRICT_FRONTMATTER=1)",
+                invalid
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves `name` within `base`, failing when the file doesn't exist or resolves
+/// (through `..` or symlinks) to somewhere outside of `base`
+pub fn safe_content_path(base: &Path, name: &str) -> std::io::Result<PathBuf> {
+    let root = base.canonicalize()?;
+    let path = base.join(name).canonicalize()?;
+    if !path.starts_with(&root) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "outside of the content directory",
+        ));
+    }
+    Ok(path)
+}
+
+/// Finds the markdown or html file for a slug anywhere below `dir`. Slugs match file names
+/// regardless of case, so the lowercased urls of `PATH_LOWERCASE` resolve too, though an
+/// exact match wins.
+pub async fn find(dir: &str, slug: &str) -> Option<PathBuf> {
+    let files = enumerate(dir).await;
+    files
+        .iter()
+        .find(|path| path.file_stem() == Some(OsStr::new(slug)))
+        .or_else(|| files.iter().find(|path| stem_matches(path, slug)))
+        .cloned()
+}
+
+/// Whether the file name of a path, without its extension, is a slug ignoring case
+fn stem_matches(path: &Path, slug: &str) -> bool {
+    let stem = path.file_stem().unwrap_or_default();
+    stem.to_string_lossy().eq_ignore_ascii_case(slug)
+}
+
+fn walk(dir: &Path, visited: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) {
+    let canonical = match dir.canonicalize() {
+        Ok(canonical) => canonical,
+        Err(err) => {
+            tracing::warn!("unable to read content directory {:?}: {}", dir, err);
+            return;
+        }
+    };
+    if !visited.insert(canonical) {
+        tracing::warn!("skipping {:?}, already visited (symlink cycle?)", dir);
+        return;
+    }
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            tracing::warn!("unable to read content directory {:?}: {}", dir, err);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // metadata follows symlinks, unlike the entry's file type
+        match std::fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => walk(&path, visited, files),
+            Ok(_) if CONFIG.with(|c| c.is_content(&path)) => files.push(path),
+            Ok(_) => {}
+            Err(err) => tracing::warn!("skipping broken content path {:?}: {}", path, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn content_paths_stay_within_the_base() {
+        let base = std::env::temp_dir().join(format!("content-{}", std::process::id()));
+        std::fs::create_dir_all(base.join("posts")).unwrap();
+        std::fs::write(base.join("about.md"), "about").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/etc", base.join("etc")).unwrap();
+
+        let root = base.canonicalize().unwrap();
+        let within = safe_content_path(&base, "posts/../about.md");
+        let parent = safe_content_path(&base, "../../../../../etc/passwd");
+        let absolute = safe_content_path(&base, "/etc/passwd");
+        let missing = safe_content_path(&base, "nope.md");
+        #[cfg(unix)]
+        let linked = safe_content_path(&base, "etc/passwd");
+        std::fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(within.unwrap(), root.join("about.md"));
+        assert_eq!(parent.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(absolute.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(missing.unwrap_err().kind(), ErrorKind::NotFound);
+        #[cfg(unix)]
+        assert_eq!(linked.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[async_std::test]
+    async fn listings_sort_by_the_configured_order() {
+        let dir = std::env::temp_dir().join(format!("sort-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            ("2022-06-01-b.md", "title: beta\nweight: 2"),
+            ("2022-06-03-a.md", "title: Alpha"),
+            ("2022-06-02-d.md", "title: delta\nweight: 2"),
+            ("notes.md", "title: gamma\nweight: 1"),
+        ];
+        for (file, frontmatter) in files {
+            let markdown = format!("---\n{}\n---\n\ntext\n", frontmatter);
+            std::fs::write(dir.join(file), markdown).unwrap();
+        }
+        let mut posts = posts(&dir.to_string_lossy()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut sorted = |order| -> Vec<String> {
+            sort(&mut posts, order);
+            posts
+                .iter()
+                .map(|post| String::from(post.title()))
+                .collect()
+        };
+        assert_eq!(
+            sorted(ListingSort::DateDesc),
+            ["Alpha", "delta", "beta", "gamma"]
+        );
+        assert_eq!(
+            sorted(ListingSort::DateAsc),
+            ["beta", "delta", "Alpha", "gamma"]
+        );
+        assert_eq!(
+            sorted(ListingSort::Title),
+            ["Alpha", "beta", "delta", "gamma"]
+        );
+        assert_eq!(
+            sorted(ListingSort::Weight),
+            ["gamma", "delta", "beta", "Alpha"]
+        );
+    }
+
+    #[async_std::test]
+    async fn posts_dated_after_today_wait_with_the_drafts() {
+        let dir = std::env::temp_dir().join(format!("scheduled-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let today = Utc::today().naive_utc();
+        for (name, date) in [
+            ("past", today.pred()),
+            ("today", today),
+            ("later", today.succ()),
+        ] {
+            let file = dir.join(format!("{}-{}.md", date.format("%Y-%m-%d"), name));
+            std::fs::write(file, format!("---\ntitle: {}\n---\n\ntext\n", name)).unwrap();
+        }
+        let dir_name = dir.to_string_lossy();
+        let published = posts(&dir_name).await;
+        let waiting = drafts(&dir_name).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let titles = |posts: &[Post]| -> Vec<String> {
+            posts
+                .iter()
+                .map(|post| String::from(post.title()))
+                .collect()
+        };
+        assert_eq!(titles(&published), ["today", "past"]);
+        assert_eq!(titles(&waiting), ["later"]);
+    }
+
+    #[async_std::test]
+    async fn concurrent_parsing_orders_posts_like_serial_parsing() {
+        let dir = std::env::temp_dir().join(format!("parse-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // all on the same day, so only the url tiebreak orders them
+        for n in (0..12).rev() {
+            let markdown = format!("---\ntitle: Post {}\n---\n\nbody {}\n", n, n);
+            std::fs::write(dir.join(format!("2024-03-01-post-{:02}.md", n)), markdown).unwrap();
+        }
+        let dir_name = dir.to_string_lossy().into_owned();
+        let files = enumerate(&dir_name).await;
+
+        let urls = |posts: Vec<(PathBuf, Post)>| -> Vec<String> {
+            posts
+                .iter()
+                .map(|(_, post)| String::from(post.url()))
+                .collect()
+        };
+        let serial = urls(parse(files.clone(), 1).await);
+        let concurrent = urls(parse(files, 8).await);
+        let indexed: Vec<String> = parse_all(&dir_name)
+            .await
+            .iter()
+            .map(|post| String::from(post.url()))
+            .collect();
+        let reindexed: Vec<String> = parse_all(&dir_name)
+            .await
+            .iter()
+            .map(|post| String::from(post.url()))
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let expected: Vec<String> = (0..12)
+            .map(|n| format!("2024/03/01/post-{:02}", n))
+            .collect();
+        assert_eq!(serial, expected);
+        assert_eq!(concurrent, serial);
+        assert_eq!(indexed, serial);
+        assert_eq!(reindexed, serial);
+    }
+}
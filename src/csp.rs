@@ -0,0 +1,60 @@
+use serde_json::Value;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+use crate::{body, markdown::truncate};
+
+/// Path violation reports are posted to
+pub const REPORT_PATH: &str = "/csp-report";
+
+/// Largest violation report accepted, in bytes
+const MAX_REPORT: usize = 16 * 1024;
+
+/// Characters of each reported value, or of a malformed report, that get logged
+const MAX_LOGGED: usize = 200;
+
+/// Sends the configured Content-Security-Policy, optionally asking browsers to report
+/// violations to [`REPORT_PATH`]
+pub struct ContentSecurityPolicy {
+    policy: String,
+}
+
+impl ContentSecurityPolicy {
+    pub fn new(policy: &str, report: bool) -> Self {
+        let mut policy = String::from(policy.trim().trim_end_matches(';'));
+        if report {
+            policy.push_str(&format!("; report-uri {}", REPORT_PATH));
+        }
+        ContentSecurityPolicy { policy }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for ContentSecurityPolicy {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let mut res = next.run(req).await;
+        res.insert_header("Content-Security-Policy", self.policy.as_str());
+        Ok(res)
+    }
+}
+
+/// Logs a posted `application/csp-report`, truncating what it logs. Answers 204 so
+/// browsers don't retry, even when the report is malformed, but refuses reports over
+/// `MAX_REPORT` bytes with a 413.
+pub async fn report(mut req: Request<()>) -> tide::Result<Response> {
+    body::limit(&mut req, MAX_REPORT).await?;
+    let body = req.body_string().await.unwrap_or_default();
+    let clip = |value: &str| truncate(value, MAX_LOGGED, "…");
+    match serde_json::from_str::<Value>(&body) {
+        Ok(report) => {
+            let report = report.get("csp-report").unwrap_or(&report);
+            tracing::warn!(
+                document = %clip(&report["document-uri"].to_string()),
+                directive = %clip(&report["violated-directive"].to_string()),
+                blocked = %clip(&report["blocked-uri"].to_string()),
+                "content security policy violation"
+            );
+        }
+        Err(err) => tracing::warn!("malformed csp report {:?}: {}", clip(&body), err),
+    }
+    Ok(Response::new(StatusCode::NoContent))
+}
@@ -0,0 +1,171 @@
+use async_std::fs;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::post::Complexity;
+
+/// Numbers the temporary files entries are written to, so concurrent writes don't collide
+static NEXT_TMP: AtomicUsize = AtomicUsize::new(0);
+
+/// The parts of a post derived by parsing and rendering its markdown
+#[derive(Serialize, Deserialize)]
+pub struct Rendered {
+    pub content: String,
+    pub excerpt: Option<String>,
+    pub description: String,
+    pub reading_time: Option<u32>,
+    pub code_blocks: usize,
+    pub complexity: Option<Complexity>,
+    pub first_heading: Option<String>,
+}
+
+/// A cache entry, only valid for the source it was rendered from
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    hash: String,
+    rendered: Rendered,
+}
+
+/// Keeps rendered posts in `RENDER_CACHE_DIR` so restarts don't re-render every post.
+/// Each post has one `<slug>.json` file holding the hash of the source it was rendered
+/// from, so an entry is replaced as soon as the source changes.
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(dir: &Path) -> Self {
+        DiskCache {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Hash of everything a render depends on: the source with its includes expanded
+    /// and the options it is rendered with
+    pub fn hash(source: &str, options: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update([0]);
+        hasher.update(options);
+        hasher.update([0]);
+        hasher.update(source);
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn path(&self, slug: &str) -> PathBuf {
+        // frontmatter can set any slug, keep it to a plain file name within the directory
+        let name: String = slug
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// The cached render of a post, when it was rendered from a source with this hash
+    pub async fn get(&self, slug: &str, hash: &str) -> Option<Rendered> {
+        let raw = fs::read_to_string(self.path(slug)).await.ok()?;
+        match serde_json::from_str::<Entry>(&raw) {
+            Ok(entry) if entry.hash == hash => {
+                tracing::debug!("using cached render of {}", slug);
+                Some(entry.rendered)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                tracing::warn!("ignoring unreadable cached render of {}: {}", slug, err);
+                None
+            }
+        }
+    }
+
+    /// Stores the render of a post, logging failures since the cache is only an optimization
+    pub async fn put(&self, slug: &str, hash: &str, rendered: Rendered) {
+        let entry = Entry {
+            hash: String::from(hash),
+            rendered,
+        };
+        if let Err(err) = self.write(slug, &entry).await {
+            tracing::warn!(
+                "unable to cache render of {} in {:?}: {}",
+                slug,
+                self.dir,
+                err
+            );
+        }
+    }
+
+    async fn write(&self, slug: &str, entry: &Entry) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.path(slug);
+        // write then rename, so concurrent readers never see a partial entry
+        let n = NEXT_TMP.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("json.{}.{}", std::process::id(), n));
+        fs::write(&tmp, serde_json::to_vec(entry)?).await?;
+        fs::rename(&tmp, &path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(content: &str) -> Rendered {
+        Rendered {
+            content: String::from(content),
+            excerpt: None,
+            description: String::from("description"),
+            reading_time: Some(3),
+            code_blocks: 2,
+            complexity: Some(Complexity::Moderate),
+            first_heading: Some(String::from("Setup")),
+        }
+    }
+
+    #[async_std::test]
+    async fn renders_survive_a_restart_until_the_source_changes() {
+        let dir = std::env::temp_dir().join(format!("diskcache-{}", std::process::id()));
+        let hash = DiskCache::hash("# Setup", "options");
+        DiskCache::new(&dir)
+            .put("a-post", &hash, rendered("<h1>Setup</h1>"))
+            .await;
+
+        // a fresh cache over the same directory, as after a restart
+        let cache = DiskCache::new(&dir);
+        let cached = cache.get("a-post", &hash).await;
+        let changed = cache
+            .get("a-post", &DiskCache::hash("# Setup!", "options"))
+            .await;
+        let other_options = cache
+            .get("a-post", &DiskCache::hash("# Setup", "other"))
+            .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let cached = cached.expect("the cached render");
+        assert_eq!(cached.content, "<h1>Setup</h1>");
+        assert_eq!(cached.code_blocks, 2);
+        assert_eq!(cached.complexity, Some(Complexity::Moderate));
+        assert_eq!(cached.first_heading.as_deref(), Some("Setup"));
+        assert!(changed.is_none());
+        assert!(other_options.is_none());
+    }
+
+    #[test]
+    fn slugs_stay_within_the_directory() {
+        let cache = DiskCache::new(Path::new("/cache"));
+        assert_eq!(
+            cache.path("../etc/passwd"),
+            Path::new("/cache/___etc_passwd.json")
+        );
+    }
+}
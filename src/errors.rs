@@ -1,24 +1,171 @@
 use serde_json::json;
 use std::io::ErrorKind;
-use tide::{Response, StatusCode};
+use tide::http::headers::{ACCEPT, REFERER};
+use tide::{Body, Middleware, Next, Request, StatusCode};
 
-use crate::registry::REGISTRY;
+use crate::config::CONFIG;
+use crate::{headers, proxy, registry};
 
-pub async fn error_handler(mut res: Response) -> tide::Result<Response> {
-    if let Some(err) = res.downcast_error::<async_std::io::Error>() {
-        if let ErrorKind::NotFound = err.kind() {
-            res.set_status(StatusCode::NotFound);
+/// Fills in the body of error responses that don't have one, as json for `/api/*`
+/// routes and clients accepting `application/json`, or as the themed html page otherwise.
+/// 404s are logged as warnings with the page linking to them, unless `LOG_NOT_FOUND=0`.
+pub struct ErrorPages;
+
+#[tide::utils::async_trait]
+impl Middleware<()> for ErrorPages {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let is_api = req.url().path().starts_with("/api/");
+        let wants_json = is_api
+            || matches!(req.header(ACCEPT), Some(h) if h.last().as_str().contains("application/json"));
+        let path = String::from(req.url().path());
+        let client = proxy::client_ip(&req)
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| String::from("unknown client"));
+        let referer = req.header(REFERER).map(|h| h.last().to_string());
+        let mut res = next.run(req).await;
+
+        if let Some(err) = res.downcast_error::<async_std::io::Error>() {
+            if let ErrorKind::NotFound = err.kind() {
+                res.set_status(StatusCode::NotFound);
+            }
+        }
+        let status = res.status();
+        if status == StatusCode::NotFound && CONFIG.with(|c| c.log_not_found) {
+            match &referer {
+                Some(referer) => {
+                    tracing::warn!("not found: {} linked from {} for {}", path, referer, client)
+                }
+                None => tracing::warn!("not found: {} for {}", path, client),
+            }
+        }
+        // responses that already rendered their own page are left alone
+        let is_error = status.is_client_error() || status.is_server_error();
+        if !is_error || !res.is_empty().unwrap_or(true) {
+            return Ok(res);
+        }
+
+        if !is_api {
+            // the body format depends on the Accept header
+            headers::vary(&mut res, "Accept");
+        }
+        if wants_json {
+            // only client errors explain themselves, server errors may carry internals
+            let message = match res.error() {
+                Some(err) if status.is_client_error() => err.to_string(),
+                _ => String::from(status.canonical_reason()),
+            };
+            let body = json!({
+                "error": status.canonical_reason(),
+                "status": status as u16,
+                "message": message,
+            });
+            res.set_body(Body::from_json(&body)?);
+        } else {
+            registry::with(|c| {
+                c.render_body(
+                    &mut res,
+                    "post.html",
+                    &json!({ "content": format!("{} {}", status as u16, status.canonical_reason()) }),
+                );
+            });
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(path: &str, accept: Option<&str>) -> HttpResponse {
+        let mut app = tide::new();
+        app.with(ErrorPages);
+        app.at("/api/bad")
+            .get(|_| async { Err::<String, _>(tide::Error::from_str(400, "bad slug")) });
+        app.at("/api/broken")
+            .get(|_| async { Err::<String, _>(tide::Error::from_str(500, "db password")) });
+        app.at("/broken")
+            .get(|_| async { Err::<String, _>(tide::Error::from_str(500, "db password")) });
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if let Some(accept) = accept {
+            req.insert_header(ACCEPT, accept);
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn api_errors_are_json_without_server_internals() {
+        let mut res = get("/api/bad", None).await;
+        assert_eq!(res.status(), StatusCode::BadRequest);
+        let body: Value = res.body_json().await.unwrap();
+        assert_eq!(
+            body,
+            json!({ "error": "Bad Request", "status": 400, "message": "bad slug" })
+        );
+
+        let mut res = get("/api/broken", None).await;
+        let body: Value = res.body_json().await.unwrap();
+        assert_eq!(body["message"], "Internal Server Error");
+
+        let mut res = get("/api/missing", None).await;
+        let body: Value = res.body_json().await.unwrap();
+        assert_eq!(body["status"], 404);
+    }
+
+    #[async_std::test]
+    async fn other_errors_are_html_unless_json_is_accepted() {
+        let mut res = get("/broken", None).await;
+        assert_eq!(res.status(), StatusCode::InternalServerError);
+        assert_eq!(res["Vary"].as_str(), "Accept");
+        let body = res.body_string().await.unwrap();
+        assert!(body.contains("500 Internal Server Error"));
+        assert!(!body.contains("db password"));
+
+        let mut res = get("/broken", Some("application/json")).await;
+        let body: Value = res.body_json().await.unwrap();
+        assert_eq!(body["status"], 500);
+    }
+
+    /// Log output shared with the subscriber writing it
+    #[derive(Clone, Default)]
+    struct Logs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
-    let status = res.status();
-    if !status.is_success() {
-        REGISTRY.with(|c| {
-            c.render_body(
-                &mut res,
-                "post.html",
-                &json!({ "content": format!("{} {}", status as u16, status.canonical_reason()) }),
-            );
+
+    #[test]
+    fn not_found_is_logged_with_the_path_and_referer() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            async_std::task::block_on(async {
+                let mut app = tide::new();
+                app.with(ErrorPages);
+                let url = Url::parse("http://localhost/missing").unwrap();
+                let mut req = HttpRequest::new(Method::Get, url);
+                req.set_peer_addr(Some("127.0.0.1:4000"));
+                req.insert_header(REFERER, "https://example.com/links");
+                let res: HttpResponse = app.respond(req).await.unwrap();
+                assert_eq!(res.status(), StatusCode::NotFound);
+            })
         });
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("WARN"));
+        assert!(logs
+            .contains("not found: /missing linked from https://example.com/links for 127.0.0.1"));
     }
-    Ok(res)
 }
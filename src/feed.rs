@@ -0,0 +1,210 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tide::http::headers::CACHE_CONTROL;
+use tide::{Request, Response, StatusCode};
+
+use crate::{config::CONFIG, content, headers, markdown::slugify, post::Post, registry};
+
+/// A single entry of an rss feed
+#[derive(Serialize)]
+struct Item<'a> {
+    title: &'a str,
+    /// Canonical url of the post, which may live on another site
+    link: String,
+    guid: String,
+    pub_date: Option<String>,
+    tags: &'a [String],
+    content: &'a str,
+}
+
+/// Path of the site feed
+const SITE_PATH: &str = "/feed.xml";
+
+/// Path of the feed of a tag slug
+fn tag_path(tag: &str) -> String {
+    format!("/tags/{}/feed.xml", tag)
+}
+
+fn tag_title(tag: &str) -> String {
+    format!("{} #{}", CONFIG.with(|c| c.site_name.clone()), tag)
+}
+
+/// Title and absolute url of the site feed, advertised with a `<link rel="alternate">`
+pub fn site_link() -> Value {
+    CONFIG.with(|c| json!({ "title": c.site_name, "href": c.absolute_url(SITE_PATH) }))
+}
+
+/// Title and absolute url of the feed of a tag slug
+pub fn tag_link(tag: &str) -> Value {
+    let href = CONFIG.with(|c| c.absolute_url(&tag_path(tag)));
+    json!({ "title": tag_title(tag), "href": href })
+}
+
+/// Renders the rss feed of the most recent posts
+pub async fn site(_req: Request<()>) -> tide::Result<Response> {
+    let title = CONFIG.with(|c| c.site_name.clone());
+    render_feed(&title, SITE_PATH, &recent(|_| true).await).await
+}
+
+/// Renders the rss feed of the posts carrying a tag, 404-ing for unknown tags
+pub async fn tag(req: Request<()>) -> tide::Result<Response> {
+    let tag = slugify(req.param("tag")?);
+    let posts = recent(|post| post.tags().iter().any(|t| slugify(t) == tag)).await;
+    if posts.is_empty() {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+    render_feed(&tag_title(&tag), &tag_path(&tag), &posts).await
+}
+
+/// The `FEED_LIMIT` most recent posts matching a filter
+async fn recent<F>(filter: F) -> Vec<Post>
+where
+    F: Fn(&Post) -> bool,
+{
+    let (posts_dir, limit) = CONFIG.with(|c| (c.posts_dir(), c.feed_limit));
+    // posts come newest first, so this keeps the most recent
+    content::posts(&posts_dir)
+        .await
+        .into_iter()
+        .filter(|post| filter(post))
+        .take(limit)
+        .collect()
+}
+
+/// Renders an rss feed of posts living at `path`, cacheable for `FEED_MAX_AGE` and last
+/// modified with its most recently changed post. A feed without posts is still a valid,
+/// empty channel.
+async fn render_feed(title: &str, path: &str, posts: &[Post]) -> tide::Result<Response> {
+    let (excerpts, max_age) = CONFIG.with(|c| (c.feed_excerpts, c.feed_max_age));
+    let items: Vec<Item> = posts
+        .iter()
+        .map(|post| {
+            let url = CONFIG.with(|c| c.absolute_url(post.url()));
+            Item {
+                title: post.title(),
+                link: post
+                    .canonical()
+                    .map(String::from)
+                    .unwrap_or_else(|| url.clone()),
+                guid: url,
+                pub_date: post
+                    .date()
+                    .map(|date| DateTime::<Utc>::from_utc(date.and_hms(0, 0, 0), Utc).to_rfc2822()),
+                tags: post.tags(),
+                content: match post.excerpt() {
+                    Some(excerpt) if excerpts => excerpt,
+                    _ => post.content(),
+                },
+            }
+        })
+        .collect();
+
+    let data = json!({
+        "title": title,
+        "link": CONFIG.with(|c| c.absolute_url("/")),
+        "feed_link": CONFIG.with(|c| c.absolute_url(path)),
+        "items": items,
+    });
+    let mut res = registry::with(|c| c.render("feed.xml", &data))?;
+    res.set_content_type("application/rss+xml");
+    res.insert_header(CACHE_CONTROL, format!("public, max-age={}", max_age));
+    let modified = posts.iter().filter_map(|post| post.modified()).max();
+    headers::validators(&mut res, modified).await?;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::io::ReadExt;
+    use tide::http::headers::{ETAG, LAST_MODIFIED};
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn request(method: Method, path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        app.at("/feed.xml").get(site);
+        app.at("/tags/:tag/feed.xml").get(tag);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(method, url)).await.unwrap()
+    }
+
+    async fn get(path: &str) -> HttpResponse {
+        request(Method::Get, path).await
+    }
+
+    #[async_std::test]
+    async fn feeds_are_cacheable_for_the_max_age() {
+        let max_age = CONFIG.with(|c| c.feed_max_age);
+        let expected = format!("public, max-age={}", max_age);
+        for path in ["/feed.xml", "/tags/webdev/feed.xml"] {
+            let res = get(path).await;
+            assert_eq!(res.status(), StatusCode::Ok, "{}", path);
+            assert_eq!(res[CACHE_CONTROL], expected.as_str(), "{}", path);
+        }
+    }
+
+    #[async_std::test]
+    async fn head_requests_get_the_validators_of_the_feed_without_a_body() {
+        let get = get("/feed.xml").await;
+        assert!(get.header(LAST_MODIFIED).is_some());
+        let head = request(Method::Head, "/feed.xml").await;
+        assert_eq!(head.status(), StatusCode::Ok);
+        assert_eq!(head[ETAG].as_str(), get[ETAG].as_str());
+        assert_eq!(head.content_type(), get.content_type());
+
+        // async-h1 writes the head of the response and leaves the body out
+        let mut written = String::new();
+        async_h1::server::Encoder::new(head, Method::Head)
+            .read_to_string(&mut written)
+            .await
+            .unwrap();
+        let (head, body) = written.split_once("\r\n\r\n").unwrap();
+        assert!(head.to_lowercase().contains("etag: "));
+        assert_eq!(body, "");
+    }
+
+    #[async_std::test]
+    async fn feeds_without_posts_are_empty_channels() {
+        let mut res = render_feed("Notes", SITE_PATH, &[]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        let body = res.take_body().into_string().await.unwrap();
+        assert!(body.contains("<channel>"));
+        assert!(!body.contains("<item>"));
+    }
+
+    #[async_std::test]
+    async fn the_site_feed_keeps_the_newest_posts_up_to_the_limit() {
+        let (posts_dir, limit) = CONFIG.with(|c| (c.posts_dir(), c.feed_limit));
+        let posts = content::posts(&posts_dir).await;
+        assert!(
+            posts.len() > limit,
+            "the content has more posts than a feed holds"
+        );
+
+        let mut res = get("/feed.xml").await;
+        let body = res.body_string().await.unwrap();
+        assert_eq!(body.matches("<item>").count(), limit);
+        let guid = |post: &Post| {
+            let url = CONFIG.with(|c| c.absolute_url(post.url()));
+            format!("<guid>{}</guid>", url)
+        };
+        assert!(posts[..limit].iter().all(|post| body.contains(&guid(post))));
+        assert!(!body.contains(&guid(&posts[limit])));
+    }
+
+    #[async_std::test]
+    async fn unknown_tags_have_no_feed() {
+        let res = get("/tags/no-such-tag/feed.xml").await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+
+    #[async_std::test]
+    async fn tag_feeds_only_hold_posts_with_the_tag() {
+        let mut res = get("/tags/rust/feed.xml").await;
+        let body = res.body_string().await.unwrap();
+        let items = body.matches("<item>").count();
+        assert!(items > 0);
+        assert_eq!(body.matches("<category>rust</category>").count(), items);
+    }
+}
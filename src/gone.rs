@@ -0,0 +1,101 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+use crate::{registry, routes::normalize_path};
+
+/// A retired url, optionally suggesting where its content lives now
+#[derive(Deserialize)]
+struct Retired {
+    path: String,
+    suggestion: Option<String>,
+}
+
+/// Answers retired urls with a 410 Gone before they reach the post handlers
+#[derive(Default)]
+pub struct Gone {
+    retired: HashMap<String, Option<String>>,
+}
+
+impl Gone {
+    /// Loads the list of retired urls from a json file such as
+    /// `[{ "path": "/2019/08/14/old", "suggestion": "/2019/08/15/new" }]`.
+    /// A missing or invalid file retires nothing.
+    pub fn from_file(path: &str) -> Self {
+        let retired: Vec<Retired> = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                tracing::warn!("ignoring invalid gone list {}: {}", path, err);
+                Vec::new()
+            }),
+            Err(_) => {
+                tracing::debug!("no gone list found at {}", path);
+                Vec::new()
+            }
+        };
+        Gone {
+            retired: retired
+                .into_iter()
+                .map(|r| (normalize_path(&r.path), r.suggestion))
+                .collect(),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for Gone {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let suggestion = match self.retired.get(&normalize_path(req.url().path())) {
+            Some(suggestion) => suggestion,
+            None => return Ok(next.run(req).await),
+        };
+
+        let status = StatusCode::Gone;
+        let mut content = format!("{} {}", status as u16, status.canonical_reason());
+        if let Some(suggestion) = suggestion {
+            content.push_str(&format!(
+                "<p>You may be looking for <a href=\"{0}\">{0}</a></p>",
+                suggestion
+            ));
+        }
+        let mut res = Response::new(status);
+        registry::with(|c| c.render_body(&mut res, "post.html", &json!({ "content": content })));
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(gone: Gone, path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        app.with(gone);
+        app.at("/*").get(|_| async { Ok("served") });
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn retired_urls_are_gone_with_their_suggestion() {
+        let file = std::env::temp_dir().join(format!("gone-{}.json", std::process::id()));
+        std::fs::write(
+            &file,
+            r#"[{ "path": "/2019/08/14/old/", "suggestion": "/2019/08/15/new" }]"#,
+        )
+        .unwrap();
+        let gone = || Gone::from_file(&file.to_string_lossy());
+
+        let mut res = get(gone(), "/2019/08/14/old").await;
+        assert_eq!(res.status(), StatusCode::Gone);
+        let body = res.body_string().await.unwrap();
+        assert!(body.contains("<a href=\"/2019/08/15/new\">"));
+        let mut res = get(gone(), "/2019/08/15/new").await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), "served");
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+use tide::http::conditional::{ETag, LastModified};
+use tide::http::headers::VARY;
+use tide::{Body, Response};
+
+/// Adds a request header to the response's `Vary`, so shared caches keep a variant per
+/// value. Headers already listed aren't repeated.
+pub fn vary(res: &mut Response, header: &str) {
+    let listed = res.header(VARY).into_iter().any(|values| {
+        values.iter().any(|value| {
+            value
+                .as_str()
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case(header) || v.trim() == "*")
+        })
+    });
+    if !listed {
+        res.append_header(VARY, header);
+    }
+}
+
+/// Sets the ETag, a hash of the body, and the Last-Modified headers of a rendered response
+pub async fn validators(res: &mut Response, modified: Option<SystemTime>) -> tide::Result<()> {
+    let mime = res.content_type();
+    let body = res.take_body().into_string().await?;
+
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    ETag::new(format!("{:x}", hasher.finish())).apply(&mut *res);
+    if let Some(modified) = modified {
+        LastModified::new(modified).apply(&mut *res);
+    }
+
+    let mut body = Body::from_string(body);
+    if let Some(mime) = mime {
+        body.set_mime(mime);
+    }
+    res.set_body(body);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::StatusCode;
+
+    fn varies(res: &Response) -> Vec<&str> {
+        res.header(VARY)
+            .map(|values| values.iter().map(|value| value.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn vary_lists_each_header_once() {
+        let mut res = Response::new(StatusCode::Ok);
+        vary(&mut res, "Accept");
+        vary(&mut res, "Cookie");
+        vary(&mut res, "accept");
+        assert_eq!(varies(&res), ["Accept", "Cookie"]);
+
+        let mut res = Response::new(StatusCode::Ok);
+        res.insert_header(VARY, "Origin, Accept-Language");
+        vary(&mut res, "accept-language");
+        assert_eq!(varies(&res), ["Origin, Accept-Language"]);
+
+        let mut res = Response::new(StatusCode::Ok);
+        res.insert_header(VARY, "*");
+        vary(&mut res, "Cookie");
+        assert_eq!(varies(&res), ["*"]);
+    }
+
+    #[async_std::test]
+    async fn validators_hash_the_body_and_keep_it() {
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_656_201_600);
+        let mut etags = Vec::new();
+        for body in ["<p>one</p>", "<p>one</p>", "<p>two</p>"] {
+            let mut res = Response::builder(StatusCode::Ok)
+                .body(body)
+                .content_type("text/html")
+                .build();
+            validators(&mut res, Some(modified)).await.unwrap();
+            assert_eq!(
+                res["Last-Modified"].as_str(),
+                "Sun, 26 Jun 2022 00:00:00 GMT"
+            );
+            assert_eq!(res.content_type().unwrap().essence(), "text/html");
+            assert_eq!(res.take_body().into_string().await.unwrap(), body);
+            etags.push(String::from(res["ETag"].as_str()));
+        }
+        assert_eq!(etags[0], etags[1]);
+        assert_ne!(etags[0], etags[2]);
+    }
+}
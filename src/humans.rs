@@ -0,0 +1,89 @@
+use tide::http::mime;
+use tide::{Body, Request, Response};
+
+use crate::config::Config;
+
+/// Serves `/humans.txt` crediting the people and tools behind the site, only when
+/// `HUMANS_AUTHOR` is set
+pub fn configure(app: &mut tide::Server<()>, config: &Config) {
+    let author = match &config.humans_author {
+        Some(author) => author,
+        None => return,
+    };
+    let text = humans_txt(author, config);
+    app.at("/humans.txt").get(move |_req: Request<()>| {
+        let text = text.clone();
+        async move {
+            let mut body = Body::from_string(text);
+            body.set_mime(mime::PLAIN);
+            let mut res = Response::new(200);
+            res.set_body(body);
+            Ok(res)
+        }
+    });
+}
+
+/// The text of `/humans.txt`, in the sections of humanstxt.org
+fn humans_txt(author: &str, config: &Config) -> String {
+    let mut text = format!(
+        "/* TEAM */\nAuthor: {}\nSite: {}\n",
+        author,
+        config.absolute_url("/")
+    );
+    if !config.humans_credits.is_empty() {
+        text.push_str("\n/* THANKS */\n");
+        for name in &config.humans_credits {
+            text.push_str(&format!("{}\n", name));
+        }
+    }
+    text.push_str(&format!(
+        "\n/* SITE */\nLanguage: {}\n",
+        config.locales.join(", ")
+    ));
+    if !config.humans_tools.is_empty() {
+        text.push_str(&format!("Software: {}\n", config.humans_tools.join(", ")));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+    use tide::StatusCode;
+
+    async fn get(config: &Config) -> HttpResponse {
+        let mut app = tide::new();
+        configure(&mut app, config);
+        let url = Url::parse("http://localhost/humans.txt").unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn humans_txt_credits_the_author_thanks_and_tools() {
+        let config = Config {
+            humans_author: Some(String::from("Tom")),
+            humans_credits: vec![String::from("Ferris")],
+            humans_tools: vec![String::from("tide"), String::from("handlebars")],
+            locales: vec![String::from("en"), String::from("fr")],
+            ..Config::default()
+        };
+        let mut res = get(&config).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.content_type(), Some(mime::PLAIN));
+        let expected = format!(
+            "/* TEAM */\nAuthor: Tom\nSite: {}\n\n/* THANKS */\nFerris\n\n\
+             /* SITE */\nLanguage: en, fr\nSoftware: tide, handlebars\n",
+            config.absolute_url("/")
+        );
+        assert_eq!(res.body_string().await.unwrap(), expected);
+    }
+
+    #[async_std::test]
+    async fn humans_txt_is_absent_without_an_author() {
+        let res = get(&Config::default()).await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+}
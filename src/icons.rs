@@ -0,0 +1,92 @@
+use handlebars::{Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// The `{{icon "name"}}` helper, inlining `icons/<name>.svg` of the template directory
+/// so pages don't make a request per icon. Unknown names render nothing.
+pub struct IconHelper {
+    icons: HashMap<String, String>,
+}
+
+impl IconHelper {
+    /// Reads every `.svg` file of a directory, which may not exist
+    pub fn from_dir(dir: &Path) -> Self {
+        let mut icons = HashMap::new();
+        for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.extension() != Some(OsStr::new("svg")) {
+                continue;
+            }
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            match std::fs::read_to_string(&path) {
+                Ok(svg) => {
+                    tracing::debug!("registering icon {}", name);
+                    icons.insert(name.into_owned(), inline(&svg));
+                }
+                Err(err) => tracing::warn!("unable to read icon {:?}: {}", path, err),
+            }
+        }
+        IconHelper { icons }
+    }
+}
+
+/// Markup of an svg file fit for embedding, without its xml declaration or doctype
+fn inline(svg: &str) -> String {
+    let start = svg.find("<svg").unwrap_or(0);
+    String::from(svg[start..].trim())
+}
+
+impl HelperDef for IconHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let name = h
+            .param(0)
+            .and_then(|p| p.value().as_str())
+            .unwrap_or_default();
+        match self.icons.get(name) {
+            Some(svg) => out.write(svg)?,
+            None => tracing::warn!("no icon named {:?}", name),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn icons_are_inlined_by_name() {
+        let dir = std::env::temp_dir().join(format!("icons-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let svg = "<?xml version=\"1.0\"?>\n<!DOCTYPE svg>\n<svg viewBox=\"0 0 8 8\"></svg>\n";
+        std::fs::write(dir.join("rss.svg"), svg).unwrap();
+        std::fs::write(dir.join("notes.txt"), "<svg>not an icon</svg>").unwrap();
+        let helper = IconHelper::from_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut handlebars = Handlebars::new();
+        handlebars.register_helper("icon", Box::new(helper));
+        let render = |template| handlebars.render_template(template, &json!({})).unwrap();
+        assert_eq!(
+            render("[{{icon \"rss\"}}]"),
+            "[<svg viewBox=\"0 0 8 8\"></svg>]"
+        );
+        assert_eq!(render("[{{icon \"notes\"}}]"), "[]");
+        assert_eq!(render("[{{icon}}]"), "[]");
+    }
+
+    #[test]
+    fn missing_icon_directories_have_no_icons() {
+        let helper = IconHelper::from_dir(Path::new("/nonexistent/icons"));
+        assert!(helper.icons.is_empty());
+    }
+}
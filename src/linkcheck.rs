@@ -0,0 +1,211 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::config::{Config, LinkCheck};
+use crate::markdown::{html_attributes, slugify};
+use crate::{content, post::Post, routes::PAGES};
+
+/// An internal link whose target doesn't exist
+#[derive(Serialize, Debug)]
+pub struct BrokenLink {
+    /// Page the link appears on
+    pub source: String,
+    pub link: String,
+    pub reason: &'static str,
+}
+
+/// Runs the startup link check according to `LINK_CHECK`, failing in strict mode
+/// when any internal link is broken.
+pub async fn startup(config: &Config) -> std::io::Result<()> {
+    if config.link_check == LinkCheck::Off {
+        return Ok(());
+    }
+    let broken = check(config).await;
+    let strict = config.link_check == LinkCheck::Strict;
+    for link in &broken {
+        let message = format!(
+            "broken link {} on {}: {}",
+            link.link, link.source, link.reason
+        );
+        if strict {
+            tracing::error!("{}", message);
+        } else {
+            tracing::warn!("{}", message);
+        }
+    }
+    if strict && !broken.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} broken internal links (LINK_CHECK=strict)", broken.len()),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks every internal link of the pages and posts, returning the broken ones
+pub async fn check(config: &Config) -> Vec<BrokenLink> {
+    // every page and post, keyed by the path it is served from
+    let mut pages: HashMap<String, Post> = HashMap::new();
+    for (path, file) in PAGES {
+        let file = format!("{}/{}", config.content_dir, file);
+        if let Ok(post) = Post::from_file(&file).await {
+            pages.insert(String::from(path), post);
+        }
+    }
+    let posts = content::posts(&config.posts_dir()).await;
+    let tags: HashSet<String> = posts
+        .iter()
+        .flat_map(|post| post.tags().iter().map(|tag| slugify(tag)))
+        .collect();
+    for post in posts {
+        pages.insert(format!("/{}", post.url()), post);
+    }
+    let mut sources: Vec<&String> = pages.keys().collect();
+    sources.sort();
+
+    let mut broken = Vec::new();
+    for source in sources {
+        for link in html_attributes(pages[source].content(), "href") {
+            let target = match internal_path(link, &config.base_url) {
+                Some(target) => target,
+                None => continue,
+            };
+            let (path, fragment) = match target.split_once('#') {
+                Some((path, fragment)) => (path, Some(fragment)),
+                None => (target, None),
+            };
+            let path = path.split('?').next().unwrap_or_default();
+            let path = if path.is_empty() {
+                source.as_str()
+            } else {
+                path.trim_end_matches('/')
+            };
+            let path = if path.is_empty() { "/" } else { path };
+
+            let reason = match pages.get(path) {
+                Some(page) => match fragment {
+                    Some(fragment) if !fragment.is_empty() && !page.has_anchor(fragment) => {
+                        Some("missing anchor")
+                    }
+                    _ => None,
+                },
+                None if resolves(path, config, &tags) => None,
+                None => Some("no such page"),
+            };
+            if let Some(reason) = reason {
+                broken.push(BrokenLink {
+                    source: source.clone(),
+                    link: String::from(link),
+                    reason,
+                });
+            }
+        }
+    }
+    broken
+}
+
+/// Path of a link within the site, or `None` for external links
+fn internal_path<'a>(link: &'a str, base_url: &str) -> Option<&'a str> {
+    if let Some(path) = link.strip_prefix(base_url) {
+        return Some(path);
+    }
+    if link.starts_with("//") || link.contains(':') {
+        // other hosts and schemes such as mailto:
+        return None;
+    }
+    if link.starts_with('/') || link.starts_with('#') {
+        Some(link)
+    } else {
+        None
+    }
+}
+
+/// Whether a path that isn't a page resolves to a route or static file
+fn resolves(path: &str, config: &Config, tags: &HashSet<String>) -> bool {
+    if let Some(file) = path.strip_prefix("/assets/") {
+        return Path::new(&config.content_dir)
+            .join("assets")
+            .join(file)
+            .is_file();
+    }
+    if let Some(file) = path.strip_prefix("/static/") {
+        return Path::new(&config.static_dir).join(file).is_file();
+    }
+    if let Some(tag) = path
+        .strip_prefix("/tags/")
+        .map(|rest| rest.strip_suffix("/feed.xml").unwrap_or(rest))
+    {
+        return tags.contains(tag);
+    }
+    matches!(
+        path,
+        "/feed.xml"
+            | "/favicon.ico"
+            | "/site.webmanifest"
+            | "/version"
+            | "/api/posts"
+            | "/api/tags"
+            | "/sitemap.xml"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn broken_internal_links_are_reported_with_their_reason() {
+        let dir = std::env::temp_dir().join(format!("linkcheck-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("posts")).unwrap();
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("assets/logo.png"), "").unwrap();
+        std::fs::write(
+            dir.join("posts/2022-06-26-tide.md"),
+            "---\ntitle: Tide\ntags: [rust]\n---\n\n## Setup\n\n[top](#setup) [gone](#teardown)\n",
+        )
+        .unwrap();
+        let links = [
+            "/2022/06/26/tide#setup",
+            "/2022/06/26/tide/",
+            "/assets/logo.png",
+            "/tags/rust/feed.xml",
+            "/feed.xml",
+            "https://example.com/missing",
+            "mailto:me@example.com",
+            "/2022/06/26/tide#nope",
+            "/tags/go",
+            "/missing?page=2",
+        ];
+        let index: String = links
+            .iter()
+            .map(|link| format!("[link]({})\n", link))
+            .collect();
+        std::fs::write(
+            dir.join("index.md"),
+            format!("---\ntitle: Home\n---\n\n{}", index),
+        )
+        .unwrap();
+
+        let config = Config {
+            content_dir: dir.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let broken = check(&config).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let broken: Vec<_> = broken
+            .iter()
+            .map(|link| (link.source.as_str(), link.link.as_str(), link.reason))
+            .collect();
+        assert_eq!(
+            broken,
+            [
+                ("/", "/2022/06/26/tide#nope", "missing anchor"),
+                ("/", "/tags/go", "no such page"),
+                ("/", "/missing?page=2", "no such page"),
+                ("/2022/06/26/tide", "#teardown", "missing anchor"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use async_h1::server::{ConnectionStatus, Server};
+use async_io::Timer;
+use async_lock::{Semaphore, SemaphoreGuardArc};
+use async_std::io::{self, Read, Write};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Accepts connections for the app, applying the keep-alive and read/write timeouts
+/// from the config to every connection.
+///
+/// With `MAX_CONNECTIONS` set, connections past the limit are queued rather than rejected:
+/// nothing is accepted until a served connection closes, so clients wait in the listen
+/// backlog of the operating system, which refuses them once it fills up.
+pub async fn listen(app: tide::Server<()>, config: &Config) -> io::Result<()> {
+    let listener = TcpListener::bind(format!("{}:{}", config.host, config.port)).await?;
+    tracing::info!("server listening on http://{}", listener.local_addr()?);
+
+    let limit = config
+        .max_connections
+        .map(|max| Arc::new(Semaphore::new(max)));
+    let mut incoming = listener.incoming();
+    loop {
+        let permit = match &limit {
+            Some(limit) => Some(acquire(limit).await),
+            None => None,
+        };
+        let stream = match incoming.next().await {
+            Some(stream) => stream,
+            None => break,
+        };
+        match stream {
+            Ok(stream) => {
+                let app = app.clone();
+                let config = config.clone();
+                task::spawn(async move {
+                    if let Err(err) = handle_connection(app, stream, &config).await {
+                        tracing::error!("connection error {}", err);
+                    }
+                    // frees the slot for the next connection waiting to be accepted
+                    drop(permit);
+                });
+            }
+            Err(err) => {
+                tracing::error!("failed to accept connection {}, pausing", err);
+                task::sleep(Duration::from_millis(500)).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Waits for a connection slot, logging when every slot is taken
+async fn acquire(limit: &Arc<Semaphore>) -> SemaphoreGuardArc {
+    match limit.try_acquire_arc() {
+        Some(permit) => permit,
+        None => {
+            tracing::warn!("connection limit reached, waiting for a connection to close");
+            limit.acquire_arc().await
+        }
+    }
+}
+
+/// Serves requests on a single connection until it closes or sits idle past the keep-alive
+async fn handle_connection(
+    app: tide::Server<()>,
+    stream: TcpStream,
+    config: &Config,
+) -> tide::http::Result<()> {
+    let local_addr = stream.local_addr().ok();
+    let peer_addr = stream.peer_addr().ok();
+    let io = TimeoutStream::new(stream.clone(), config.read_timeout, config.write_timeout);
+    let mut server = Server::new(io, |mut req: tide::http::Request| {
+        let app = app.clone();
+        async move {
+            req.set_local_addr(local_addr);
+            req.set_peer_addr(peer_addr);
+            app.respond(req).await
+        }
+    });
+
+    while server.accept_one().await? == ConnectionStatus::KeepAlive {
+        // wait for the next request without consuming any of it
+        let mut buf = [0; 1];
+        match io::timeout(config.keep_alive_timeout, stream.peek(&mut buf)).await {
+            Ok(0) => break,
+            Ok(_) => continue,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                tracing::debug!("closing idle connection from {:?}", peer_addr);
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+/// A tcp stream that fails any single read or write blocking longer than its timeout
+struct TimeoutStream {
+    stream: TcpStream,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    read_timer: Option<Timer>,
+    write_timer: Option<Timer>,
+}
+
+impl TimeoutStream {
+    fn new(stream: TcpStream, read: Option<Duration>, write: Option<Duration>) -> Self {
+        TimeoutStream {
+            stream,
+            read_timeout: read,
+            write_timeout: write,
+            read_timer: None,
+            write_timer: None,
+        }
+    }
+}
+
+impl Clone for TimeoutStream {
+    fn clone(&self) -> Self {
+        TimeoutStream::new(self.stream.clone(), self.read_timeout, self.write_timeout)
+    }
+}
+
+/// Polls the pending operation's timer, starting it on first use
+fn poll_timeout<T>(
+    timer: &mut Option<Timer>,
+    timeout: Option<Duration>,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<T>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Poll::Pending,
+    };
+    let timer = timer.get_or_insert_with(|| Timer::after(timeout));
+    match Pin::new(timer).poll(cx) {
+        Poll::Ready(_) => Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "socket operation timed out",
+        ))),
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+impl Read for TimeoutStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.stream).poll_read(cx, buf) {
+            Poll::Ready(res) => {
+                this.read_timer = None;
+                Poll::Ready(res)
+            }
+            Poll::Pending => poll_timeout(&mut this.read_timer, this.read_timeout, cx),
+        }
+    }
+}
+
+impl Write for TimeoutStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.stream).poll_write(cx, buf) {
+            Poll::Ready(res) => {
+                this.write_timer = None;
+                Poll::Ready(res)
+            }
+            Poll::Pending => poll_timeout(&mut this.write_timer, this.write_timeout, cx),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends a request on a connection, returning the status line of the answer unless it
+    /// takes longer than `wait`
+    async fn status(stream: &mut TcpStream, wait: Duration) -> io::Result<String> {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        let mut buf = [0; 256];
+        let read = io::timeout(wait, stream.read(&mut buf)).await?;
+        let answer = String::from_utf8_lossy(&buf[..read]);
+        Ok(String::from(answer.lines().next().unwrap_or_default()))
+    }
+
+    #[async_std::test]
+    async fn connections_past_the_limit_wait_for_a_slot() {
+        // a free port for the listener to bind
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let config = Config {
+            host: String::from("127.0.0.1"),
+            port: port.to_string(),
+            max_connections: Some(1),
+            keep_alive_timeout: Duration::from_secs(30),
+            ..Config::default()
+        };
+        let mut app = tide::new();
+        app.at("/").get(|_| async { Ok("ok") });
+        task::spawn(async move { listen(app, &config).await });
+
+        let addr = format!("127.0.0.1:{}", port);
+        let mut first = loop {
+            match TcpStream::connect(&addr).await {
+                Ok(stream) => break stream,
+                Err(_) => task::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let served = status(&mut first, Duration::from_secs(5)).await;
+        assert_eq!(served.unwrap(), "HTTP/1.1 200 OK");
+
+        // the first connection is kept alive, holding the only slot
+        let mut second = TcpStream::connect(&addr).await.unwrap();
+        let queued = status(&mut second, Duration::from_millis(200)).await;
+        assert_eq!(queued.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        drop(first);
+        let mut buf = [0; 256];
+        let read = io::timeout(Duration::from_secs(5), second.read(&mut buf))
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&buf[..read]).starts_with("HTTP/1.1 200 OK"));
+    }
+}
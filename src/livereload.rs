@@ -0,0 +1,138 @@
+use async_std::task;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+use crate::registry;
+
+/// Every watched file of a directory along with its modified time
+type Snapshot = Vec<(PathBuf, Option<SystemTime>)>;
+
+/// How often the watched directories and the change counter are polled
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Incremented by the watcher whenever a watched file changes
+static CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers the `/livereload` event stream and starts the file watcher. Connected
+/// browsers receive a `reload` event on every change. This is a server-sent events
+/// stream rather than a websocket, since reloads only go from the server to the
+/// browser. Only enabled in development.
+pub fn configure(app: &mut tide::Server<()>, config: &Config) {
+    if !config.is_dev() {
+        return;
+    }
+
+    let dirs = watched(config);
+    tracing::info!("live reload enabled, watching {:?}", dirs);
+    task::spawn(watch(dirs, config.template_dir.clone()));
+    app.at("/livereload")
+        .get(tide::sse::endpoint(|_req, sender| async move {
+            let mut seen = CHANGES.load(Ordering::SeqCst);
+            loop {
+                task::sleep(POLL_INTERVAL).await;
+                let changes = CHANGES.load(Ordering::SeqCst);
+                if changes != seen {
+                    seen = changes;
+                    sender.send("reload", "", None).await?;
+                }
+            }
+        }));
+}
+
+/// Directories watched for changes while authoring: the content (pages, posts and assets),
+/// the templates and the static assets, wherever they are configured
+fn watched(config: &Config) -> Vec<String> {
+    let mut dirs = vec![config.content_dir.clone(), config.template_dir.clone()];
+    if !dirs.contains(&config.static_dir) {
+        dirs.push(config.static_dir.clone());
+    }
+    dirs
+}
+
+/// Polls the watched directories, bumping the change counter when any file changes.
+/// Templates are reloaded first when their directory changed, so clients reload into them.
+async fn watch(dirs: Vec<String>, template_dir: String) {
+    let mut last = snapshots(&dirs).await;
+    loop {
+        task::sleep(POLL_INTERVAL).await;
+        let current = snapshots(&dirs).await;
+        if current == last {
+            continue;
+        }
+        let templates = dirs.iter().position(|dir| *dir == template_dir);
+        if matches!(templates, Some(i) if current[i] != last[i]) {
+            match registry::reload() {
+                Ok(_) => tracing::info!("templates changed, reloaded them"),
+                Err(err) => tracing::warn!("keeping previous templates: {}", err),
+            }
+        }
+        tracing::info!("watched files changed, reloading clients");
+        CHANGES.fetch_add(1, Ordering::SeqCst);
+        last = current;
+    }
+}
+
+/// Snapshots each directory, in order
+async fn snapshots(dirs: &[String]) -> Vec<Snapshot> {
+    let dirs = dirs.to_vec();
+    task::spawn_blocking(move || dirs.iter().map(|dir| snapshot(Path::new(dir))).collect()).await
+}
+
+/// Lists every file below a directory along with its modified time. Symlinked directories
+/// are followed, once each.
+fn snapshot(dir: &Path) -> Snapshot {
+    let mut files = Vec::new();
+    collect(dir, &mut HashSet::new(), &mut files);
+    files.sort();
+    files
+}
+
+fn collect(dir: &Path, visited: &mut HashSet<PathBuf>, files: &mut Snapshot) {
+    let visited_before = match dir.canonicalize() {
+        Ok(canonical) => !visited.insert(canonical),
+        Err(_) => true,
+    };
+    if visited_before {
+        return;
+    }
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // metadata follows symlinks, unlike the entry's own
+        match std::fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => collect(&path, visited, files),
+            Ok(meta) => files.push((path, meta.modified().ok())),
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watches_the_configured_directories() {
+        let config = Config {
+            content_dir: String::from("/srv/notes"),
+            template_dir: String::from("/srv/client"),
+            static_dir: String::from("/srv/client"),
+            ..Config::default()
+        };
+        assert_eq!(watched(&config), ["/srv/notes", "/srv/client"]);
+        let config = Config {
+            static_dir: String::from("/srv/static"),
+            ..config
+        };
+        assert_eq!(
+            watched(&config),
+            ["/srv/notes", "/srv/client", "/srv/static"]
+        );
+    }
+}
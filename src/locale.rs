@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use tide::http::headers::ACCEPT_LANGUAGE;
+use tide::{Middleware, Next, Request};
+
+use crate::headers;
+
+async_std::task_local! {
+    /// Locale negotiated for the request currently being handled
+    static CURRENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Locale of the request being handled, or `None` outside of a request
+pub fn current() -> Option<String> {
+    CURRENT
+        .try_with(|locale| locale.borrow().clone())
+        .ok()
+        .flatten()
+}
+
+/// Picks the best available locale for an `Accept-Language` header. Ranges are tried in
+/// order of their quality, matching either exactly or on the primary language (`fr-CA` ~ `fr`).
+pub fn negotiate<'a>(header: &str, available: &'a [String]) -> Option<&'a str> {
+    let mut ranges: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+            Some((tag, quality)).filter(|(tag, q)| !tag.is_empty() && *q > 0.0)
+        })
+        .collect();
+    // stable, so equally weighted ranges keep their header order
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    let primary = |tag: &str| tag.split('-').next().unwrap_or_default().to_lowercase();
+    for (tag, _) in ranges {
+        if tag == "*" {
+            return available.first().map(String::as_str);
+        }
+        let found = available
+            .iter()
+            .find(|locale| locale.eq_ignore_ascii_case(tag))
+            .or_else(|| {
+                available
+                    .iter()
+                    .find(|locale| primary(locale) == primary(tag))
+            });
+        if let Some(locale) = found {
+            return Some(locale);
+        }
+    }
+    None
+}
+
+/// Negotiates the request locale from `Accept-Language`, falling back to the default locale
+pub struct LocaleNegotiator {
+    default: String,
+    available: Vec<String>,
+}
+
+impl LocaleNegotiator {
+    pub fn new(default: &str, available: &[String]) -> Self {
+        LocaleNegotiator {
+            default: String::from(default),
+            available: available.to_vec(),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for LocaleNegotiator {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let header = req.header(ACCEPT_LANGUAGE).map(|h| h.last().as_str());
+        let locale = header
+            .and_then(|header| negotiate(header, &self.available))
+            .unwrap_or(&self.default);
+        CURRENT.with(|current| *current.borrow_mut() = Some(String::from(locale)));
+        let mut res = next.run(req).await;
+        if self.available.len() > 1 {
+            // pages differ by language, shared caches must key on it
+            headers::vary(&mut res, "Accept-Language");
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locales(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| String::from(*tag)).collect()
+    }
+
+    #[test]
+    fn negotiation_follows_quality_then_primary_language() {
+        let available = locales(&["en", "fr-CA"]);
+        assert_eq!(negotiate("fr;q=0.9, en;q=0.8", &available), Some("fr-CA"));
+        assert_eq!(negotiate("de, en-GB;q=0.5", &available), Some("en"));
+        assert_eq!(negotiate("EN, fr-ca", &available), Some("en"));
+        assert_eq!(negotiate("en;q=0.2, fr-CA", &available), Some("fr-CA"));
+        assert_eq!(negotiate("de, *;q=0.1", &available), Some("en"));
+        assert_eq!(negotiate("en;q=0, de", &available), None);
+        assert_eq!(negotiate("en;q=abc", &available), None);
+        assert_eq!(negotiate("", &available), None);
+    }
+
+    #[async_std::test]
+    async fn requests_carry_their_locale_and_vary_on_it() {
+        let mut app = tide::new();
+        app.with(LocaleNegotiator::new("en", &locales(&["en", "fr"])));
+        app.at("/")
+            .get(|_| async { Ok(current().unwrap_or_default()) });
+
+        let url = tide::http::Url::parse("http://localhost/").unwrap();
+        let mut req = tide::http::Request::new(tide::http::Method::Get, url.clone());
+        req.insert_header(ACCEPT_LANGUAGE, "fr-BE, en;q=0.5");
+        let mut res: tide::http::Response = app.respond(req).await.unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "fr");
+        assert_eq!(res["Vary"].as_str(), "Accept-Language");
+
+        let req = tide::http::Request::new(tide::http::Method::Get, url);
+        let mut res: tide::http::Response = app.respond(req).await.unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "en");
+    }
+}
@@ -0,0 +1,144 @@
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tide::{Middleware, Next, Request};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Instrument, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{prelude::*, EnvFilter};
+
+use crate::config::{Config, LogFormat};
+
+/// Header carrying the request id, accepted from proxies and echoed on responses
+const REQUEST_ID: &str = "X-Request-Id";
+
+/// Builds the subscriber for the configured log format. `RUST_LOG` takes precedence
+/// over `LOG_LEVEL`, which defaults to errors only.
+pub fn subscriber(config: &Config) -> impl Subscriber + Send + Sync {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(config.log_level.as_deref().unwrap_or("error")));
+    let json = config.log_format == LogFormat::Json;
+    let pretty_layer = if json {
+        None
+    } else {
+        Some(tracing_subscriber::fmt::layer())
+    };
+    let json_layer = if json {
+        Some(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .event_format(JsonFormat),
+        )
+    } else {
+        None
+    };
+    tracing_subscriber::registry()
+        .with(pretty_layer)
+        .with(json_layer)
+        .with(filter)
+}
+
+/// Formats events as a json object with their fields and enclosing spans
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let mut fields = JsonFields(Map::new());
+        event.record(&mut fields);
+
+        let mut line = json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "level": meta.level().to_string(),
+            "target": meta.target(),
+            "fields": fields.0,
+        });
+        let mut spans = Vec::new();
+        for span in ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            let extensions = span.extensions();
+            let formatted = extensions.get::<FormattedFields<N>>();
+            let span_fields = formatted.map(|f| f.fields.as_str()).unwrap_or_default();
+            // lift the request id to the top level so aggregators can group by it
+            if let Some(id) = span_fields.strip_prefix("request_id=") {
+                line["request_id"] = json!(id.split(' ').next().unwrap_or(id));
+            }
+            spans.push(json!({ "name": span.name(), "fields": span_fields }));
+        }
+        line["spans"] = json!(spans);
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// Collects event fields into a json map
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), json!(format!("{:?}", value)));
+    }
+}
+
+/// Tags each request with an id, taken from `X-Request-Id` when a proxy set one, so
+/// its log lines can be correlated. The id is echoed back on the response.
+pub struct RequestId;
+
+/// Sequence number of the requests handled by this process
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Start time of the process, keeping generated ids unique across restarts
+static STARTED: Lazy<i64> = Lazy::new(|| Utc::now().timestamp());
+
+#[tide::utils::async_trait]
+impl Middleware<()> for RequestId {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let forwarded = req.header(REQUEST_ID).map(|h| h.last().as_str());
+        let id = match forwarded {
+            Some(id) if id.len() <= 64 && id.chars().all(|c| c.is_ascii_graphic()) => {
+                String::from(id)
+            }
+            _ => format!(
+                "{:x}-{:x}",
+                *STARTED,
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ),
+        };
+        let span = tracing::info_span!("request", request_id = %id);
+        let mut res = next.run(req).instrument(span).await;
+        res.insert_header(REQUEST_ID, id);
+        Ok(res)
+    }
+}
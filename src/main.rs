@@ -1,34 +1,119 @@
+mod admin;
+mod api;
+mod body;
+mod bots;
+mod cache;
+mod canonical;
+mod comments;
+mod config;
+mod content;
+mod csp;
+mod diskcache;
 mod errors;
+mod feed;
+mod gone;
+mod headers;
+mod humans;
+mod icons;
+mod linkcheck;
+mod listener;
+mod livereload;
+mod locale;
+mod logging;
+mod maintenance;
+mod manifest;
+mod markdown;
+mod normalize;
+mod og;
+mod png;
 mod post;
+mod preview;
+mod proxy;
+mod redirects;
 mod registry;
 mod routes;
+mod shortlinks;
+mod sitemap;
+mod tags;
+mod theme;
+mod thumbs;
+mod unavailable;
+mod views;
 
-use tide::utils::After;
+use config::CONFIG;
 use tide_tracing::TraceMiddleware;
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use tracing_subscriber::util::SubscriberInitExt;
 
 #[async_std::main]
 async fn main() -> std::io::Result<()> {
     let mut app = tide::new();
     // env_logger::init();
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
+    logging::subscriber(&config::Config::from_env()).init();
+    let config = CONFIG.with(|c| c.clone());
 
     // serve static files
-    app.at("/static").serve_dir("client/dist")?;
-    app.at("/assets").serve_dir("content/assets")?;
-    app.at("/favicon.ico").serve_file("favicon.ico")?;
+    // fail early with a readable error rather than on the first render
+    if let Err(err) = registry::State::from_dir(&config.template_dir) {
+        tracing::error!("{}", err);
+        return Err(err);
+    }
+    if let Err(err) = content::validate(&config).await {
+        tracing::error!("{}", err);
+        return Err(err);
+    }
+    content::index(&config.posts_dir()).await;
+    if let Err(err) = linkcheck::startup(&config).await {
+        tracing::error!("{}", err);
+        return Err(err);
+    }
+    app.at("/static").serve_dir(&config.static_dir)?;
+    app.at("/assets")
+        .serve_dir(format!("{}/assets", config.content_dir))?;
+    thumbs::configure(&mut app, &config);
+    manifest::configure(&mut app, &config)?;
+    humans::configure(&mut app, &config);
 
     // app.with(tide::log::LogMiddleware::new());
+    app.with(logging::RequestId);
     app.with(TraceMiddleware::new());
-    app.with(After(errors::error_handler));
+    app.with(theme::ThemeSelector::new(&config.default_theme));
+    app.with(locale::LocaleNegotiator::new(
+        &config.locale,
+        &config.locales,
+    ));
+    app.with(bots::BotDetector::new(&config.bot_user_agents));
+    app.with(errors::ErrorPages);
+    app.with(maintenance::Maintenance::new(
+        config.maintenance,
+        &config.maintenance_file,
+        config.retry_after,
+    ));
+    if let Some(policy) = &config.content_security_policy {
+        app.with(csp::ContentSecurityPolicy::new(policy, config.csp_report));
+    }
+    if let Some(host) = &config.canonical_host {
+        app.with(canonical::CanonicalHost::new(host));
+    }
+    app.with(normalize::NormalizePath::new(config.path_lowercase));
+    let posts = content::posts(&config.posts_dir()).await;
+    if let Err(err) = shortlinks::assign(&posts).await {
+        tracing::error!("unable to assign post ids: {}", err);
+    }
+    app.with(redirects::Redirects::from_file(&config.redirects_path).with_aliases(&posts));
+    app.with(gone::Gone::from_file(&config.gone_path));
+    app.with(unavailable::Unavailable::from_file(
+        &config.legal_path,
+        config.blocked_by.as_deref(),
+    ));
+    if !config.cache_ttl.is_empty() {
+        app.with(cache::ResponseCache::new(&config.cache_ttl));
+    }
+    admin::configure(&mut app, &config);
     routes::configure(&mut app);
+    comments::configure(&mut app, &config);
+    livereload::configure(&mut app, &config);
 
     // listen and await
-    let host = option_env!("HOST").unwrap_or("0.0.0.0");
-    let port = option_env!("PORT").unwrap_or("7000");
-    app.listen(format!("{}:{}", host, port)).await?;
+    listener::listen(app, &config).await?;
     Ok(())
 }
@@ -0,0 +1,80 @@
+use async_std::path::Path;
+use tide::http::headers::RETRY_AFTER;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+/// Answers every route except `/health_check` with a 503 while maintenance mode is on,
+/// either from `MAINTENANCE=1` at startup or while the maintenance file exists. The file
+/// is checked on each request, so creating or removing it toggles maintenance without a
+/// restart.
+pub struct Maintenance {
+    on: bool,
+    file: String,
+    retry_after: u64,
+}
+
+impl Maintenance {
+    pub fn new(on: bool, file: &str, retry_after: u64) -> Self {
+        Maintenance {
+            on,
+            file: String::from(file),
+            retry_after,
+        }
+    }
+
+    async fn is_on(&self) -> bool {
+        self.on || Path::new(&self.file).exists().await
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for Maintenance {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        if req.url().path() == "/health_check" || !self.is_on().await {
+            return Ok(next.run(req).await);
+        }
+
+        tracing::info!("in maintenance, refusing {}", req.url().path());
+        let mut res = Response::new(StatusCode::ServiceUnavailable);
+        res.insert_header(RETRY_AFTER, self.retry_after.to_string());
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(maintenance: Maintenance, path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        app.with(maintenance);
+        app.at("/").get(|_| async { Ok("home") });
+        app.at("/health_check").get(|_| async { Ok("") });
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn switched_on_at_startup() {
+        let res = get(Maintenance::new(true, "no-such-file", 60), "/").await;
+        assert_eq!(res.status(), StatusCode::ServiceUnavailable);
+        assert_eq!(res[RETRY_AFTER], "60");
+        let res = get(Maintenance::new(true, "no-such-file", 60), "/health_check").await;
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+
+    #[async_std::test]
+    async fn toggled_by_the_file() {
+        let file = std::env::temp_dir().join(format!("maintenance-{}", std::process::id()));
+        let file = file.to_string_lossy().into_owned();
+        let res = get(Maintenance::new(false, &file, 60), "/").await;
+        assert_eq!(res.status(), StatusCode::Ok);
+
+        std::fs::write(&file, "").unwrap();
+        let res = get(Maintenance::new(false, &file, 60), "/").await;
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(res.status(), StatusCode::ServiceUnavailable);
+    }
+}
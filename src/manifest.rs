@@ -0,0 +1,142 @@
+use serde::Serialize;
+use serde_json::json;
+use std::path::Path;
+use tide::{Body, Request, Response};
+
+use crate::config::Config;
+use crate::registry::State;
+
+/// Path the web app manifest is served from
+pub const MANIFEST_PATH: &str = "/site.webmanifest";
+
+/// An icon listed in the web app manifest, served from `/icons/<file name>`
+#[derive(Serialize)]
+struct Icon {
+    src: String,
+    #[serde(rename = "type")]
+    mime: &'static str,
+}
+
+/// Serves the configured favicon, the manifest icons and `/site.webmanifest`.
+/// Icon files that don't exist are skipped with a warning.
+pub fn configure(app: &mut tide::Server<()>, config: &Config) -> std::io::Result<()> {
+    if Path::new(&config.favicon).is_file() {
+        app.at("/favicon.ico").serve_file(&config.favicon)?;
+    } else {
+        tracing::warn!("favicon {} not found, skipping", config.favicon);
+    }
+
+    let mut icons = Vec::new();
+    for file in &config.icons {
+        let path = Path::new(file);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if !path.is_file() {
+            tracing::warn!("icon {} not found, skipping", file);
+            continue;
+        }
+        let src = format!("/icons/{}", name);
+        app.at(&src).serve_file(path)?;
+        icons.push(Icon {
+            src,
+            mime: mime_type(path),
+        });
+    }
+
+    let manifest = json!({
+        "name": config.site_name,
+        "short_name": config.site_name,
+        "start_url": "/",
+        "display": "standalone",
+        "theme_color": config.theme_color,
+        "background_color": config.theme_color,
+        "icons": icons,
+    })
+    .to_string();
+    app.at(MANIFEST_PATH).get(move |_req: Request<()>| {
+        let manifest = manifest.clone();
+        async move {
+            let mut body = Body::from_string(manifest);
+            body.set_mime("application/manifest+json");
+            let mut res = Response::new(200);
+            res.set_body(body);
+            Ok(res)
+        }
+    });
+    Ok(())
+}
+
+/// Adds the favicon, manifest and theme color `<link>`s to the render context
+pub fn context_provider(state: &mut State, config: &Config) {
+    let favicon = if Path::new(&config.favicon).is_file() {
+        Some("/favicon.ico")
+    } else {
+        None
+    };
+    let head = json!({
+        "favicon": favicon,
+        "manifest": MANIFEST_PATH,
+        "theme_color": config.theme_color,
+    });
+    state.context_provider(move || json!({ "head": head }));
+}
+
+/// Mime type of an icon file, guessed from its extension
+fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+    use tide::StatusCode;
+
+    async fn get(app: &tide::Server<()>, path: &str) -> HttpResponse {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn manifest_lists_the_icons_that_exist() {
+        let config = Config {
+            site_name: String::from("Notes"),
+            theme_color: String::from("#000000"),
+            favicon: String::from("content/assets/favicon-32x32.png"),
+            icons: vec![
+                String::from("content/assets/favicon-32x32.png"),
+                String::from("content/assets/missing.png"),
+            ],
+            ..Config::default()
+        };
+        let mut app = tide::new();
+        configure(&mut app, &config).unwrap();
+
+        let mut res = get(&app, MANIFEST_PATH).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(
+            res.content_type().unwrap().essence(),
+            "application/manifest+json"
+        );
+        let manifest: Value = res.body_json().await.unwrap();
+        assert_eq!(manifest["name"], json!("Notes"));
+        assert_eq!(manifest["theme_color"], json!("#000000"));
+        assert_eq!(
+            manifest["icons"],
+            json!([{ "src": "/icons/favicon-32x32.png", "type": "image/png" }])
+        );
+        for path in ["/favicon.ico", "/icons/favicon-32x32.png"] {
+            assert_eq!(get(&app, path).await.status(), StatusCode::Ok, "{}", path);
+        }
+        let res = get(&app, "/icons/missing.png").await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+}
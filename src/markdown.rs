@@ -0,0 +1,1122 @@
+use pulldown_cmark::{Event, HeadingLevel, LinkType, Options, Parser, Tag};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::config::AnchorPlacement;
+use crate::content;
+
+/// How deeply `{{include: path}}` directives may nest
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Paragraphs replaced with the table of contents
+const TOC_PLACEHOLDERS: [&str; 2] = ["[[toc]]", "[TOC]"];
+
+/// How markdown is converted into html
+pub struct RenderOptions<'a> {
+    /// Prefixes generated heading ids, for pages rendering several posts
+    pub id_prefix: Option<&'a str>,
+    /// Links bare `http(s)://` urls
+    pub autolinks: bool,
+    /// Heading of the section the footnote definitions are gathered in
+    pub footnotes_heading: &'a str,
+    /// Levels of the headings given a generated id and a permalink anchor
+    pub anchor_levels: &'a [u8],
+    /// Text or html the permalink anchors are made of
+    pub anchor_symbol: &'a str,
+    /// Whether the permalink anchors go before or after the heading text
+    pub anchor_placement: AnchorPlacement,
+}
+
+/// Converts markdown content into html. Headings of the anchor levels without an explicit
+/// `{#id}` get one generated from their text, prefixed with the `id_prefix` if any, and a
+/// `[[toc]]` or `[TOC]` paragraph is replaced with a table of contents of the headings.
+/// Footnote definitions are moved to a section closing the content.
+pub fn to_html(markdown: &str, render: &RenderOptions) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    let events: Vec<Event> = Parser::new_ext(markdown, options).collect();
+
+    let levels = render.anchor_levels;
+    let ids = heading_ids(&events, render.id_prefix, levels);
+    let mut ids = ids.iter();
+    let mut anchor = None;
+    let events: Vec<Event> = events
+        .into_iter()
+        .flat_map(|event| match event {
+            Event::Start(Tag::Heading(level, id, classes)) => {
+                let id = match id {
+                    None if levels.contains(&(level as u8)) => ids.next().map(|id| id.as_str()),
+                    id => id,
+                };
+                if levels.contains(&(level as u8)) {
+                    anchor = id;
+                }
+                let start = Event::Start(Tag::Heading(level, id, classes));
+                match anchor {
+                    Some(id) if render.anchor_placement == AnchorPlacement::Before => {
+                        vec![
+                            start,
+                            Event::Html(anchor_link(id, render.anchor_symbol).into()),
+                        ]
+                    }
+                    _ => vec![start],
+                }
+            }
+            Event::End(Tag::Heading(..)) => match anchor.take() {
+                Some(id) if render.anchor_placement == AnchorPlacement::After => {
+                    vec![
+                        Event::Html(anchor_link(id, render.anchor_symbol).into()),
+                        event,
+                    ]
+                }
+                _ => vec![event],
+            },
+            event => vec![event],
+        })
+        .collect();
+
+    let mut toc: Option<String> = None;
+    let mut output = Vec::with_capacity(events.len());
+    let mut i = 0;
+    while i < events.len() {
+        if let Some(end) = toc_placeholder(&events, i) {
+            let toc = toc.get_or_insert_with(|| toc_html(&events));
+            output.push(Event::Html(toc.clone().into()));
+            i = end + 1;
+            continue;
+        }
+        output.push(events[i].clone());
+        i += 1;
+    }
+
+    if render.autolinks {
+        output = autolink(output);
+    }
+    output = footnotes_section(output, render.footnotes_heading);
+
+    let mut html_content = String::new();
+    pulldown_cmark::html::push_html(&mut html_content, output.into_iter());
+    html_content
+}
+
+/// Permalink to a heading, with the symbol as its content
+fn anchor_link(id: &str, symbol: &str) -> String {
+    format!(
+        "<a class=\"anchor\" href=\"#{}\" aria-label=\"Link to this section\">{}</a>",
+        escape_html(id),
+        symbol
+    )
+}
+
+/// Converts the start of markdown content into an html fragment fit for listings and feeds.
+/// Raw html such as scripts and embeds, footnotes and the table of contents are dropped,
+/// headings above `heading_level` are demoted to it, and the fragment ends after
+/// `paragraphs` top level paragraphs, or at the end of the content when it's `0`. Headings
+/// get no ids unless given an `id_prefix`, which then namespaces all of them so that the
+/// excerpts of several posts can share a page.
+pub fn excerpt_html(
+    markdown: &str,
+    paragraphs: usize,
+    heading_level: u8,
+    autolinks: bool,
+    id_prefix: Option<&str>,
+) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    let events: Vec<Event> = Parser::new_ext(markdown, options).collect();
+    let cap = HeadingLevel::try_from(heading_level as usize).unwrap_or(HeadingLevel::H3);
+    let ids = match id_prefix {
+        Some(prefix) => excerpt_heading_ids(&events, prefix),
+        None => Vec::new(),
+    };
+    let mut ids = ids.iter().map(|id| id.as_str());
+    let mut id = None;
+
+    let mut output = Vec::new();
+    let mut depth = 0;
+    let mut in_footnote = false;
+    let mut count = 0;
+    let mut i = 0;
+    while i < events.len() {
+        if let Some(end) = toc_placeholder(&events, i) {
+            i = end + 1;
+            continue;
+        }
+        let event = match &events[i] {
+            Event::Html(_) | Event::FootnoteReference(_) => None,
+            Event::Start(Tag::FootnoteDefinition(_)) => {
+                in_footnote = true;
+                None
+            }
+            Event::End(Tag::FootnoteDefinition(_)) => {
+                in_footnote = false;
+                None
+            }
+            _ if in_footnote => None,
+            Event::Start(Tag::Heading(level, _, classes)) => {
+                id = ids.next();
+                Some(Event::Start(Tag::Heading(
+                    (*level).max(cap),
+                    id,
+                    classes.clone(),
+                )))
+            }
+            Event::End(Tag::Heading(level, _, classes)) => Some(Event::End(Tag::Heading(
+                (*level).max(cap),
+                id.take(),
+                classes.clone(),
+            ))),
+            event => Some(event.clone()),
+        };
+        i += 1;
+        let event = match event {
+            Some(event) => event,
+            None => continue,
+        };
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(tag) => {
+                depth -= 1;
+                if depth == 0 && matches!(tag, Tag::Paragraph) {
+                    count += 1;
+                }
+            }
+            _ => {}
+        }
+        output.push(event);
+        if paragraphs > 0 && count >= paragraphs {
+            break;
+        }
+    }
+
+    if autolinks {
+        output = autolink(output);
+    }
+    let mut html_content = String::new();
+    pulldown_cmark::html::push_html(&mut html_content, output.into_iter());
+    html_content
+}
+
+/// Moves the footnote definitions to the end, under a rule and a heading
+fn footnotes_section<'a>(events: Vec<Event<'a>>, heading: &str) -> Vec<Event<'a>> {
+    let mut output = Vec::with_capacity(events.len());
+    let mut footnotes = Vec::new();
+    let mut in_footnote = false;
+    for event in events {
+        match &event {
+            Event::Start(Tag::FootnoteDefinition(_)) => in_footnote = true,
+            Event::End(Tag::FootnoteDefinition(_)) => {
+                footnotes.push(event);
+                in_footnote = false;
+                continue;
+            }
+            _ => {}
+        }
+        if in_footnote {
+            footnotes.push(event);
+        } else {
+            output.push(event);
+        }
+    }
+    if !footnotes.is_empty() {
+        let open = format!(
+            "<hr>\n<section class=\"footnotes\">\n<h2>{}</h2>\n",
+            escape_html(heading)
+        );
+        output.push(Event::Html(open.into()));
+        output.extend(footnotes);
+        output.push(Event::Html("</section>\n".into()));
+    }
+    output
+}
+
+/// Wraps the bare urls of text in links, leaving code and the text of links alone
+fn autolink(events: Vec<Event>) -> Vec<Event> {
+    let mut output = Vec::with_capacity(events.len());
+    // text is split around some characters, gather it up to find whole urls
+    let mut text = String::new();
+    let mut skip = 0;
+    for event in events {
+        if let Event::Text(t) = &event {
+            if skip == 0 {
+                text.push_str(t);
+                continue;
+            }
+        }
+        push_autolinked(&std::mem::take(&mut text), &mut output);
+        match &event {
+            Event::Start(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => skip += 1,
+            Event::End(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => skip -= 1,
+            // links written out whole, such as heading anchors, hold no text to skip
+            Event::Html(html) if html.starts_with("<a ") && !html.contains("</a>") => skip += 1,
+            Event::Html(html) if html.starts_with("</a>") && skip > 0 => skip -= 1,
+            _ => {}
+        }
+        output.push(event);
+    }
+    push_autolinked(&text, &mut output);
+    output
+}
+
+/// Pushes text as events, with the urls it contains as links
+fn push_autolinked<'a>(text: &str, output: &mut Vec<Event<'a>>) {
+    let mut rest = text;
+    while let Some((start, end)) = find_url(rest) {
+        let url = String::from(&rest[start..end]);
+        if start > 0 {
+            output.push(Event::Text(String::from(&rest[..start]).into()));
+        }
+        let link = Tag::Link(LinkType::Autolink, url.clone().into(), "".into());
+        output.push(Event::Start(link.clone()));
+        output.push(Event::Text(url.into()));
+        output.push(Event::End(link));
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        output.push(Event::Text(String::from(rest).into()));
+    }
+}
+
+/// Byte range of the first `http://` or `https://` url starting a word of the text
+fn find_url(text: &str) -> Option<(usize, usize)> {
+    for (start, _) in text.match_indices("http") {
+        let candidate = &text[start..];
+        let scheme = if candidate.starts_with("https://") {
+            8
+        } else if candidate.starts_with("http://") {
+            7
+        } else {
+            continue;
+        };
+        if matches!(text[..start].chars().last(), Some(c) if c.is_alphanumeric()) {
+            continue;
+        }
+        let len = candidate
+            .find(|c: char| c.is_whitespace() || c == '<' || c == '>')
+            .unwrap_or(candidate.len());
+        // trailing punctuation ends the sentence rather than the url
+        let mut url = &candidate[..len];
+        loop {
+            let trimmed = url.trim_end_matches(&['.', ',', ':', ';', '!', '?', '\'', '"'][..]);
+            let trimmed = match trimmed.strip_suffix(')') {
+                Some(inner) if inner.matches('(').count() < trimmed.matches(')').count() => inner,
+                _ => trimmed,
+            };
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+        if url.len() > scheme {
+            return Some((start, start + url.len()));
+        }
+    }
+    None
+}
+
+/// Index of the end of the paragraph starting at `start` when it only holds a
+/// table of contents placeholder
+fn toc_placeholder(events: &[Event], start: usize) -> Option<usize> {
+    if !matches!(events[start], Event::Start(Tag::Paragraph)) {
+        return None;
+    }
+    let mut text = String::new();
+    for (i, event) in events.iter().enumerate().skip(start + 1) {
+        match event {
+            Event::Text(t) => text.push_str(t),
+            Event::End(Tag::Paragraph) if TOC_PLACEHOLDERS.contains(&text.trim()) => {
+                return Some(i)
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Level, id (empty when it has none) and text of each heading, in document order
+fn headings<'a>(events: &'a [Event]) -> Vec<(u32, &'a str, String)> {
+    let mut headings = Vec::new();
+    let mut current: Option<(u32, &str, String)> = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading(level, id, _)) => {
+                current = Some((*level as u32, id.unwrap_or_default(), String::new()))
+            }
+            Event::Text(t) | Event::Code(t) => {
+                if let Some((_, _, text)) = current.as_mut() {
+                    text.push_str(t);
+                }
+            }
+            Event::End(Tag::Heading(..)) => headings.extend(current.take()),
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// Text of the first heading of markdown content, whatever its level
+pub fn first_heading(markdown: &str) -> Option<String> {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    let events: Vec<Event> = Parser::new_ext(markdown, options).collect();
+    headings(&events)
+        .into_iter()
+        .map(|(_, _, text)| String::from(text.trim()))
+        .find(|text| !text.is_empty())
+}
+
+/// Nested `<ul>` of links to the headings, inside a `<nav class="toc">`
+fn toc_html(events: &[Event]) -> String {
+    let headings = headings(events)
+        .into_iter()
+        .filter(|(_, id, _)| !id.is_empty());
+
+    let mut html = String::from("<nav class=\"toc\">\n");
+    let mut levels: Vec<u32> = Vec::new();
+    for (level, id, text) in headings {
+        while matches!(levels.last(), Some(&open) if open > level) {
+            html.push_str("</li>\n</ul>\n");
+            levels.pop();
+        }
+        match levels.last() {
+            Some(&open) if open == level => html.push_str("</li>\n"),
+            _ => {
+                html.push_str("<ul>\n");
+                levels.push(level);
+            }
+        }
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape_html(id),
+            escape_html(&text)
+        ));
+    }
+    for _ in levels {
+        html.push_str("</li>\n</ul>\n");
+    }
+    html.push_str("</nav>\n");
+    html
+}
+
+/// Rewrites `Term` lines directly followed by one or more `: definition` lines into
+/// `<dl>` html. Terms must start a block (follow a blank line) so regular paragraphs
+/// are left alone, and nothing inside fenced or indented code is touched.
+pub fn definition_lists(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let is_definition = |i: usize| matches!(lines.get(i), Some(l) if l.starts_with(": "));
+    let is_term = |i: usize| {
+        let line = lines[i];
+        !line.trim().is_empty()
+            && !line.starts_with(':')
+            && !line.starts_with("    ")
+            && (i == 0 || lines[i - 1].trim().is_empty() || is_definition(i - 1))
+            && is_definition(i + 1)
+    };
+
+    let mut output = String::new();
+    let mut in_fence = false;
+    let mut in_list = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+
+        if !in_fence && is_term(i) {
+            if !in_list {
+                output.push_str("<dl>\n");
+                in_list = true;
+            }
+            output.push_str(&format!("<dt>{}</dt>\n", inline_html(line)));
+            i += 1;
+            while is_definition(i) {
+                output.push_str(&format!("<dd>{}</dd>\n", inline_html(&lines[i][2..])));
+                i += 1;
+            }
+            continue;
+        }
+
+        if in_list {
+            output.push_str("</dl>\n\n");
+            in_list = false;
+        }
+        output.push_str(line);
+        output.push('\n');
+        i += 1;
+    }
+    if in_list {
+        output.push_str("</dl>\n");
+    }
+    output
+}
+
+/// Kinds of callout given their own class, icon and title, other kinds render as a generic
+/// callout titled with the kind
+const CALLOUTS: [(&str, &str, &str); 5] = [
+    ("note", "\u{2139}", "Note"),
+    ("tip", "\u{1f4a1}", "Tip"),
+    ("important", "\u{2757}", "Important"),
+    ("warning", "\u{26a0}", "Warning"),
+    ("caution", "\u{26d4}", "Caution"),
+];
+
+/// Icon of callouts of an unknown kind
+const CALLOUT_ICON: &str = "\u{25cf}";
+
+/// Rewrites blockquotes opening with a `[!KIND]` marker, such as `> [!NOTE]` or
+/// `> [!WARNING] Mind the gap`, into `<div class="callout callout-warning">` html with an
+/// icon and a title, the kind's own unless one follows the marker. The rest of the quote
+/// stays markdown, and nothing inside fenced code is touched.
+pub fn callouts(markdown: &str) -> String {
+    let mut output = String::new();
+    let mut in_fence = false;
+    let mut lines = markdown.lines().peekable();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        let marker = if in_fence { None } else { callout_marker(line) };
+        let (kind, title) = match marker {
+            Some(marker) => marker,
+            None => {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            }
+        };
+
+        let known = CALLOUTS
+            .iter()
+            .find(|(k, _, _)| kind.eq_ignore_ascii_case(k));
+        let (class, icon, title) = match known {
+            Some((k, icon, default)) => (
+                format!("callout callout-{}", k),
+                *icon,
+                title
+                    .map(inline_html)
+                    .unwrap_or_else(|| String::from(*default)),
+            ),
+            None => (
+                String::from("callout"),
+                CALLOUT_ICON,
+                title.map(inline_html).unwrap_or_else(|| escape_html(kind)),
+            ),
+        };
+        output.push_str(&format!(
+            "<div class=\"{}\">\n<p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\">{}</span> {}</p>\n\n",
+            class, icon, title
+        ));
+        while let Some(quoted) = lines.peek().and_then(|l| l.trim_start().strip_prefix('>')) {
+            output.push_str(quoted.strip_prefix(' ').unwrap_or(quoted));
+            output.push('\n');
+            lines.next();
+        }
+        output.push_str("\n</div>\n");
+    }
+    output
+}
+
+/// The kind and title, if any, of a `> [!KIND] title` line opening a callout
+fn callout_marker(line: &str) -> Option<(&str, Option<&str>)> {
+    let rest = line.trim_start().strip_prefix('>')?.trim_start();
+    let (kind, title) = rest.strip_prefix("[!")?.split_once(']')?;
+    if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return None;
+    }
+    let title = title.trim();
+    if title.is_empty() {
+        Some((kind, None))
+    } else {
+        Some((kind, Some(title)))
+    }
+}
+
+/// Inlines `{{include: path}}` lines with the markdown fragment at `path`, relative to
+/// `base` (the content directory). Includes may nest up to `MAX_INCLUDE_DEPTH` deep;
+/// missing files, cycles and paths outside `base` render as an html error comment.
+pub fn includes(markdown: &str, base: &Path) -> String {
+    let mut output = String::new();
+    expand_includes(markdown, base, &mut Vec::new(), &mut output);
+    output
+}
+
+fn expand_includes(markdown: &str, base: &Path, stack: &mut Vec<PathBuf>, output: &mut String) {
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        let include = trimmed
+            .strip_prefix("{{include:")
+            .and_then(|rest| rest.strip_suffix("}}"))
+            .map(str::trim);
+        let name = match include {
+            Some(name) if !in_fence => name,
+            _ => {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            }
+        };
+
+        match resolve_include(name, base, stack) {
+            Ok((path, fragment)) => {
+                stack.push(path);
+                expand_includes(&fragment, base, stack, output);
+                stack.pop();
+            }
+            Err(err) => {
+                tracing::warn!("unable to include {}: {}", name, err);
+                output.push_str(&format!("<!-- include {}: {} -->\n", name, err));
+            }
+        }
+    }
+}
+
+/// Reads an included fragment, checking it stays within `base` and isn't already being included
+fn resolve_include(
+    name: &str,
+    base: &Path,
+    stack: &[PathBuf],
+) -> Result<(PathBuf, String), String> {
+    if stack.len() >= MAX_INCLUDE_DEPTH {
+        return Err(format!("nested deeper than {} includes", MAX_INCLUDE_DEPTH));
+    }
+    let path = content::safe_content_path(base, name).map_err(|err| err.to_string())?;
+    if stack.contains(&path) {
+        return Err(String::from("include cycle"));
+    }
+    let fragment = std::fs::read_to_string(&path).map_err(|err| err.to_string())?;
+    Ok((path, fragment))
+}
+
+/// Renders a single line of markdown without the surrounding paragraph
+fn inline_html(text: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new(text.trim()));
+    let html = html.trim_end();
+    let html = html.strip_prefix("<p>").unwrap_or(html);
+    let html = html.strip_suffix("</p>").unwrap_or(html);
+    String::from(html)
+}
+
+/// Ids of every heading of an excerpt in document order, declared or generated, prefixed
+fn excerpt_heading_ids(events: &[Event], prefix: &str) -> Vec<String> {
+    let mut generated = heading_ids(events, Some(prefix), &[1, 2, 3, 4, 5, 6]).into_iter();
+    let mut in_footnote = false;
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::FootnoteDefinition(_)) => {
+                in_footnote = true;
+                None
+            }
+            Event::End(Tag::FootnoteDefinition(_)) => {
+                in_footnote = false;
+                None
+            }
+            Event::Start(Tag::Heading(_, id, _)) => {
+                let id = match id {
+                    Some(id) => format!("{}-{}", slugify(prefix), id),
+                    None => generated.next().unwrap_or_default(),
+                };
+                Some(id).filter(|_| !in_footnote)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Generates unique ids for each heading that doesn't declare one, in document order
+fn heading_ids(events: &[Event], prefix: Option<&str>, levels: &[u8]) -> Vec<String> {
+    let mut seen: HashSet<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading(_, Some(id), _)) => Some(String::from(*id)),
+            _ => None,
+        })
+        .collect();
+
+    let mut ids = Vec::new();
+    let mut text: Option<String> = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading(level, None, _)) if levels.contains(&(*level as u8)) => {
+                text = Some(String::new())
+            }
+            Event::Text(t) | Event::Code(t) => {
+                if let Some(text) = text.as_mut() {
+                    text.push_str(t);
+                }
+            }
+            Event::End(Tag::Heading(_, None, _)) if text.is_some() => {
+                let mut id = slugify(&text.take().unwrap_or_default());
+                if let Some(prefix) = prefix {
+                    id = format!("{}-{}", slugify(prefix), id);
+                }
+                let mut unique = id.clone();
+                let mut n = 1;
+                while seen.contains(&unique) {
+                    unique = format!("{}-{}", id, n);
+                    n += 1;
+                }
+                seen.insert(unique.clone());
+                ids.push(unique);
+            }
+            _ => {}
+        }
+    }
+    ids
+}
+
+/// Extracts the text of markdown content, without markup or code blocks
+pub fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut in_code_block = false;
+    let events: Vec<Event> = Parser::new(markdown).collect();
+    let mut i = 0;
+    while i < events.len() {
+        if let Some(end) = toc_placeholder(&events, i) {
+            i = end + 1;
+            continue;
+        }
+        match &events[i] {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Text(t) | Event::Code(t) if !in_code_block => text.push_str(t),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push(' '),
+            _ => {}
+        }
+        i += 1;
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Counts the lines of each code block of markdown content
+pub fn code_block_lines(markdown: &str) -> Vec<usize> {
+    let mut blocks = Vec::new();
+    let mut lines = None;
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => lines = Some(0),
+            Event::End(Tag::CodeBlock(_)) => blocks.extend(lines.take()),
+            Event::Text(t) => {
+                if let Some(lines) = lines.as_mut() {
+                    *lines += t.lines().count();
+                }
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Escapes text for inclusion in html
+pub fn escape_html(text: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::escape::escape_html(&mut html, text).unwrap();
+    html
+}
+
+/// Shortens text to at most `limit` characters (ellipsis included), cutting at a word boundary
+pub fn truncate(text: &str, limit: usize, ellipsis: &str) -> String {
+    if text.chars().count() <= limit {
+        return String::from(text);
+    }
+    let available = limit.saturating_sub(ellipsis.chars().count());
+    let end = text
+        .char_indices()
+        .nth(available)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    // keep whole words unless the first word alone is over the limit
+    let cut = match text[..end].rfind(char::is_whitespace) {
+        Some(space) if !text[end..].starts_with(char::is_whitespace) => &text[..space],
+        _ => &text[..end],
+    };
+    format!("{}{}", cut.trim_end(), ellipsis)
+}
+
+/// Values of an attribute throughout an html document, e.g. every `href="..."`
+pub fn html_attributes<'a>(html: &'a str, name: &str) -> impl Iterator<Item = &'a str> {
+    let pattern = format!("{}=\"", name);
+    let mut rest = html;
+    std::iter::from_fn(move || loop {
+        let start = rest.find(&pattern)? + pattern.len();
+        let end = rest[start..].find('"')? + start;
+        let value = &rest[start..end];
+        let preceding = rest[..start - pattern.len()].chars().last();
+        rest = &rest[end..];
+        // skip longer attribute names ending the same way, such as data-id
+        if !matches!(preceding, Some(c) if !c.is_whitespace()) {
+            return Some(value);
+        }
+    })
+}
+
+/// Converts text into a lowercase, dash separated slug
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    String::from(slug.trim_matches('-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> RenderOptions<'static> {
+        RenderOptions {
+            id_prefix: None,
+            autolinks: false,
+            footnotes_heading: "Footnotes",
+            anchor_levels: &[2, 3],
+            anchor_symbol: "#",
+            anchor_placement: AnchorPlacement::After,
+        }
+    }
+
+    fn render(markdown: &str, id_prefix: Option<&str>) -> String {
+        to_html(
+            markdown,
+            &RenderOptions {
+                id_prefix,
+                ..options()
+            },
+        )
+    }
+
+    #[test]
+    fn headings_get_bare_unique_ids() {
+        let html = render("## Setup\n\ntext\n\n## Setup\n\n## Named {#named}\n", None);
+        assert!(html.contains("<h2 id=\"setup\">"));
+        assert!(html.contains("<h2 id=\"setup-1\">"));
+        assert!(html.contains("<h2 id=\"named\">"));
+    }
+
+    #[test]
+    fn prefixed_ids_carry_through_the_toc_and_anchors() {
+        let html = render("[[toc]]\n\n## Setup\n\n## Named {#named}\n", Some("Tide"));
+        assert!(html.contains("<li><a href=\"#tide-setup\">Setup</a>"));
+        assert!(
+            html.contains("<h2 id=\"tide-setup\">Setup<a class=\"anchor\" href=\"#tide-setup\"")
+        );
+        assert!(html.contains("<h2 id=\"named\">"));
+    }
+
+    #[test]
+    fn toc_nests_the_headings_with_ids() {
+        let markdown = "[TOC]\n\n## Intro\n\n### Setup `cargo`\n\n#### Deep\n\n## Next\n";
+        let html = render(markdown, None);
+        let toc = "<nav class=\"toc\">\n<ul>\n<li><a href=\"#intro\">Intro</a><ul>\n\
+                   <li><a href=\"#setup-cargo\">Setup cargo</a></li>\n</ul>\n</li>\n\
+                   <li><a href=\"#next\">Next</a></li>\n</ul>\n</nav>\n";
+        assert!(html.starts_with(toc), "{}", html);
+        assert!(!html.contains("[TOC]"));
+    }
+
+    #[test]
+    fn toc_placeholders_must_fill_their_paragraph() {
+        let html = render("see [[toc]] below\n\n## Intro\n", None);
+        assert!(html.contains("<p>see [[toc]] below</p>"));
+        assert!(!html.contains("<nav class=\"toc\">"));
+    }
+
+    #[test]
+    fn bare_urls_are_linked_outside_code_and_links() {
+        let markdown = "See https://example.com/a_b, or (http://example.org).\n\n\
+                        `https://code.example` [https://named.example](https://x.example) \
+                        xhttps://glued.example\n\n    https://block.example\n";
+        let html = to_html(
+            markdown,
+            &RenderOptions {
+                autolinks: true,
+                anchor_levels: &[],
+                ..options()
+            },
+        );
+        assert!(html.contains(
+            "See <a href=\"https://example.com/a_b\">https://example.com/a_b</a>, \
+             or (<a href=\"http://example.org\">http://example.org</a>)."
+        ));
+        assert!(html.contains("<code>https://code.example</code>"));
+        assert!(html.contains("<a href=\"https://x.example\">https://named.example</a>"));
+        assert!(html.contains(" xhttps://glued.example"));
+        assert!(html.contains("<pre><code>https://block.example\n</code></pre>"));
+        assert_eq!(html.matches("<a ").count(), 3);
+    }
+
+    #[test]
+    fn footnotes_close_the_content_under_their_heading() {
+        let markdown = "Claim[^1] and more.\n\n[^1]: The source.\n\nAfter the note.\n";
+        let html = to_html(
+            markdown,
+            &RenderOptions {
+                footnotes_heading: "Notes & refs",
+                ..options()
+            },
+        );
+        let (text, notes) = html
+            .split_once("<hr>\n<section class=\"footnotes\">\n")
+            .unwrap();
+        assert!(text.contains("<p>After the note.</p>"));
+        assert!(!text.contains("The source."));
+        assert!(notes.starts_with("<h2>Notes &amp; refs</h2>\n"));
+        assert!(notes.contains("The source."));
+        assert!(notes.ends_with("</section>\n"));
+
+        assert!(!render("No notes here.\n", None).contains("footnotes"));
+    }
+
+    #[test]
+    fn only_headings_of_the_anchor_levels_get_ids_and_anchors() {
+        let markdown = "# Title\n\n## Section\n\n### Detail\n\n### Named {#named}\n";
+        let html = to_html(
+            markdown,
+            &RenderOptions {
+                anchor_levels: &[2],
+                ..options()
+            },
+        );
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<h2 id=\"section\">Section<a class=\"anchor\" href=\"#section\""));
+        assert!(html.contains("<h3>Detail</h3>"));
+        assert!(html.contains("<h3 id=\"named\">Named</h3>"));
+        assert_eq!(html.matches("class=\"anchor\"").count(), 1);
+
+        let html = to_html(
+            markdown,
+            &RenderOptions {
+                anchor_levels: &[],
+                ..options()
+            },
+        );
+        assert!(!html.contains("class=\"anchor\""));
+        assert!(html.contains("<h2>Section</h2>"));
+    }
+
+    #[test]
+    fn excerpts_are_trimmed_fragments_of_the_first_paragraphs() {
+        let markdown = "[[toc]]\n\n# Big\n\nOne[^1] <b>bold</b>\n\n\
+                        <script>alert(1)</script>\n\nTwo\n\nThree\n\n[^1]: A note.\n";
+        let html = excerpt_html(markdown, 2, 3, false, None);
+        assert_eq!(html, "<h3>Big</h3>\n<p>One bold</p>\n<p>Two</p>\n");
+
+        let whole = excerpt_html(markdown, 0, 3, false, None);
+        assert!(whole.ends_with("<p>Three</p>\n"));
+        assert!(!whole.contains("A note."));
+    }
+
+    #[test]
+    fn anchors_take_the_configured_symbol_and_placement() {
+        let html = to_html(
+            "## Setup\n",
+            &RenderOptions {
+                anchor_symbol: "<span>¶</span>",
+                anchor_placement: AnchorPlacement::Before,
+                ..options()
+            },
+        );
+        assert_eq!(
+            html,
+            "<h2 id=\"setup\"><a class=\"anchor\" href=\"#setup\" \
+             aria-label=\"Link to this section\"><span>¶</span></a>Setup</h2>\n"
+        );
+        let html = render("## Setup\n", None);
+        assert!(html.ends_with(
+            "Setup<a class=\"anchor\" href=\"#setup\" \
+                                aria-label=\"Link to this section\">#</a></h2>\n"
+        ));
+    }
+
+    #[test]
+    fn the_first_heading_is_the_first_with_text() {
+        let markdown = "Intro\n\n###   \n\n### `cargo` Setup {#setup}\n\n# Title\n";
+        assert_eq!(first_heading(markdown).as_deref(), Some("cargo Setup"));
+        assert_eq!(first_heading("No headings\n"), None);
+    }
+
+    #[test]
+    fn excerpts_have_no_ids_without_a_prefix() {
+        let first = excerpt_html("# Setup\n\nfirst post", 0, 3, false, None);
+        let second = excerpt_html("## Setup {#setup}\n\nsecond post", 0, 3, false, None);
+        assert_eq!(first, "<h3>Setup</h3>\n<p>first post</p>\n");
+        assert!(!format!("{}{}", first, second).contains("id="));
+    }
+
+    #[test]
+    fn prefixed_excerpts_with_the_same_heading_get_distinct_ids() {
+        let first = excerpt_html("## Setup\n\nfirst post", 0, 3, false, Some("first"));
+        let second = excerpt_html(
+            "## Setup {#setup}\n\nsecond post",
+            0,
+            3,
+            false,
+            Some("second"),
+        );
+        assert_eq!(
+            first,
+            "<h3 id=\"first-setup\">Setup</h3>\n<p>first post</p>\n"
+        );
+        assert_eq!(
+            second,
+            "<h3 id=\"second-setup\">Setup</h3>\n<p>second post</p>\n"
+        );
+    }
+
+    #[test]
+    fn truncation_cuts_at_words_within_the_limit() {
+        let text = "Rust makes async web servers pleasant";
+        assert_eq!(truncate(text, 100, "..."), text);
+        assert_eq!(truncate(text, 20, "..."), "Rust makes async...");
+        assert_eq!(truncate(text, 20, "…"), "Rust makes async…");
+        assert!(truncate(text, 20, "...").chars().count() <= 20);
+        // a first word over the limit is cut inside the word
+        assert_eq!(truncate("Supercalifragilistic", 8, "…"), "Superca…");
+        assert_eq!(truncate("Rust makes", 4, "..."), "R...");
+    }
+
+    #[test]
+    fn definition_lists_group_consecutive_terms() {
+        let markdown = "Intro\n\nTerm\n: first *meaning*\n: second\nOther\n: third\n\nAfter\n";
+        assert_eq!(
+            definition_lists(markdown),
+            "Intro\n\n<dl>\n<dt>Term</dt>\n<dd>first <em>meaning</em></dd>\n<dd>second</dd>\n\
+             <dt>Other</dt>\n<dd>third</dd>\n</dl>\n\n\nAfter\n"
+        );
+    }
+
+    #[test]
+    fn definition_lists_leave_paragraphs_and_code_alone() {
+        for markdown in [
+            "Some text\nTerm\n: not a definition\n",
+            "```\nTerm\n: in code\n```\n",
+            "    Term\n: indented code\n",
+            "Term\n:no space\n",
+        ] {
+            assert_eq!(definition_lists(markdown), markdown);
+        }
+    }
+
+    /// A fresh content directory holding the given fragments
+    fn fragments(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, content) in files {
+            std::fs::write(dir.join(file), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn includes_inline_nested_fragments() {
+        let dir = fragments(
+            "includes",
+            &[("a.md", "A\n{{include: b.md}}\n"), ("b.md", "B\n")],
+        );
+        let expanded = includes("start\n{{include: a.md}}\nend\n", &dir);
+        let fenced = includes("```\n{{include: a.md}}\n```\n", &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(expanded, "start\nA\nB\nend\n");
+        assert_eq!(fenced, "```\n{{include: a.md}}\n```\n");
+    }
+
+    #[test]
+    fn include_cycles_are_cut() {
+        let dir = fragments(
+            "include-cycles",
+            &[
+                ("a.md", "A\n{{include: b.md}}\n"),
+                ("b.md", "B\n{{include: a.md}}\n"),
+            ],
+        );
+        let expanded = includes("{{include: a.md}}\n", &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(expanded, "A\nB\n<!-- include a.md: include cycle -->\n");
+    }
+
+    #[test]
+    fn includes_nest_up_to_the_max_depth() {
+        let files: Vec<(String, String)> = (0..=MAX_INCLUDE_DEPTH)
+            .map(|n| {
+                (
+                    format!("{}.md", n),
+                    format!("{}\n{{{{include: {}.md}}}}\n", n, n + 1),
+                )
+            })
+            .collect();
+        let files: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(file, content)| (file.as_str(), content.as_str()))
+            .collect();
+        let dir = fragments("include-depth", &files);
+        let expanded = includes("{{include: 0.md}}\n", &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let included: Vec<String> = (0..MAX_INCLUDE_DEPTH).map(|n| format!("{}\n", n)).collect();
+        let error = format!(
+            "<!-- include {}.md: nested deeper than {} includes -->\n",
+            MAX_INCLUDE_DEPTH, MAX_INCLUDE_DEPTH
+        );
+        assert_eq!(expanded, included.concat() + &error);
+    }
+
+    #[test]
+    fn missing_and_outside_includes_render_a_comment() {
+        let dir = fragments("include-missing", &[]);
+        let missing = includes("{{include: nope.md}}\n", &dir);
+        let outside = includes("{{include: ../../../../etc/passwd}}\n", &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(missing.starts_with("<!-- include nope.md: "), "{}", missing);
+        assert_eq!(
+            outside,
+            "<!-- include ../../../../etc/passwd: outside of the content directory -->\n"
+        );
+    }
+
+    #[test]
+    fn callouts_take_their_kind_or_given_title() {
+        assert_eq!(
+            callouts("> [!NOTE]\n> Body\n>\n> more\nAfter\n"),
+            "<div class=\"callout callout-note\">\n<p class=\"callout-title\">\
+             <span class=\"callout-icon\" aria-hidden=\"true\">\u{2139}</span> Note</p>\n\n\
+             Body\n\nmore\n\n</div>\nAfter\n"
+        );
+        let warning = callouts("> [!warning] Mind *the* gap\n");
+        assert!(warning.starts_with("<div class=\"callout callout-warning\">"));
+        assert!(warning.contains("</span> Mind <em>the</em> gap</p>"));
+    }
+
+    #[test]
+    fn callouts_of_unknown_kinds_are_generic() {
+        let custom = callouts("> [!side-note]\n> Body\n");
+        assert!(custom.starts_with("<div class=\"callout\">"));
+        assert!(custom.contains("\u{25cf}</span> side-note</p>"));
+    }
+
+    #[test]
+    fn callouts_need_a_well_formed_marker_outside_code() {
+        for markdown in [
+            "> [!]\n> Body\n",
+            "> [!two words]\n",
+            "> [!<b>]\n",
+            "> [NOTE]\n",
+            "> quote [!NOTE]\n",
+            "```\n> [!NOTE]\n```\n",
+        ] {
+            assert_eq!(callouts(markdown), markdown);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use tide::http::headers::LOCATION;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+/// Prefixes of files served from disk, whose names keep their case
+const CASE_SENSITIVE: [&str; 2] = ["/static/", "/assets/"];
+
+/// Permanently redirects paths with repeated slashes, such as `/Posts//2020/01/01/x`, to
+/// their single slash form, and mixed case paths to lowercase when `PATH_LOWERCASE` is set,
+/// which suits sites whose post and page urls are all lowercase.
+/// A trailing slash is left alone since routes already look `/a/b/` and `/a/b` up the same.
+pub struct NormalizePath {
+    lowercase: bool,
+}
+
+impl NormalizePath {
+    pub fn new(lowercase: bool) -> Self {
+        NormalizePath { lowercase }
+    }
+
+    /// The canonical form of a path
+    fn normalize(&self, path: &str) -> String {
+        let mut normalized = String::with_capacity(path.len());
+        for c in path.chars() {
+            if c != '/' || !normalized.ends_with('/') {
+                normalized.push(c);
+            }
+        }
+        if self.lowercase && !CASE_SENSITIVE.iter().any(|p| normalized.starts_with(p)) {
+            normalized = normalized.to_lowercase();
+        }
+        normalized
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for NormalizePath {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let path = req.url().path();
+        let normalized = self.normalize(path);
+        if normalized == path {
+            return Ok(next.run(req).await);
+        }
+
+        let mut location = normalized;
+        if let Some(query) = req.url().query() {
+            location.push('?');
+            location.push_str(query);
+        }
+        tracing::info!("redirecting {} to normalized {}", path, location);
+        let mut res = Response::new(StatusCode::MovedPermanently);
+        res.insert_header(LOCATION, location);
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(lowercase: bool, path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        app.with(NormalizePath::new(lowercase));
+        routes::configure(&mut app);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn repeated_slashes_collapse() {
+        let normalize = NormalizePath::new(false);
+        assert_eq!(
+            normalize.normalize("/Posts//2020///01/x"),
+            "/Posts/2020/01/x"
+        );
+        assert_eq!(normalize.normalize("/a/b/"), "/a/b/");
+    }
+
+    #[test]
+    fn files_served_from_disk_keep_their_case() {
+        let normalize = NormalizePath::new(true);
+        assert_eq!(normalize.normalize("/About"), "/about");
+        assert_eq!(
+            normalize.normalize("/static/Index.css"),
+            "/static/Index.css"
+        );
+        assert_eq!(
+            normalize.normalize("/assets//Cover.PNG"),
+            "/assets/Cover.PNG"
+        );
+    }
+
+    #[async_std::test]
+    async fn redirects_keep_the_query() {
+        let res = get(false, "/tags//rust?page=2").await;
+        assert_eq!(res.status(), StatusCode::MovedPermanently);
+        assert_eq!(res[LOCATION], "/tags/rust?page=2");
+    }
+
+    #[async_std::test]
+    async fn mixed_case_posts_resolve_once_lowercased() {
+        let res = get(true, "/2021/07/06/react-useEffect-useState").await;
+        assert_eq!(res.status(), StatusCode::MovedPermanently);
+        let location = res[LOCATION].as_str().to_string();
+        assert_eq!(location, "/2021/07/06/react-useeffect-usestate");
+        assert_eq!(get(true, &location).await.status(), StatusCode::Ok);
+    }
+}
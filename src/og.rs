@@ -0,0 +1,287 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tide::http::headers::CACHE_CONTROL;
+use tide::{Body, Request, Response, StatusCode};
+
+use crate::png::{self, Image};
+use crate::{config::CONFIG, content, post::Post};
+
+/// Size of the social cards, the one Open Graph recommends
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 630;
+
+const MARGIN: u32 = 80;
+const TITLE_SCALE: u32 = 8;
+const NAME_SCALE: u32 = 4;
+const MAX_LINES: usize = 4;
+const BACKGROUND: [u8; 4] = [15, 23, 42, 255];
+const ACCENT: [u8; 4] = [56, 189, 248, 255];
+const TEXT: [u8; 4] = [248, 250, 252, 255];
+const MUTED: [u8; 4] = [148, 163, 184, 255];
+
+/// Rendered cards by post slug, with the title they were drawn with
+type Cards = HashMap<String, (String, Vec<u8>)>;
+
+static CARDS: Lazy<Mutex<Cards>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Path of the social card of a post slug
+pub fn path(slug: &str) -> String {
+    format!("/og/{}.png", slug)
+}
+
+/// Serves `/og/:slug.png`, the social card of a post, rendered on first request and
+/// again whenever the title of the post changes
+pub async fn image(req: Request<()>) -> tide::Result<Response> {
+    let slug = match req.param("file")?.strip_suffix(".png") {
+        Some(slug) if !slug.is_empty() => String::from(slug),
+        _ => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    let posts_dir = CONFIG.with(|c| c.posts_dir());
+    let path = match content::find(&posts_dir, &slug).await {
+        Some(path) => path,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    let post = Post::from_file(&path.to_string_lossy()).await?;
+    if post.draft() {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+
+    let cached = CARDS
+        .lock()
+        .unwrap()
+        .get(&slug)
+        .filter(|(title, _)| title == post.title())
+        .map(|(_, card)| card.clone());
+    let card = match cached {
+        Some(card) => card,
+        None => {
+            let site_name = CONFIG.with(|c| c.site_name.clone());
+            let card = png::encode(&card(post.title(), &site_name));
+            let mut cards = CARDS.lock().unwrap();
+            cards.insert(slug, (String::from(post.title()), card.clone()));
+            card
+        }
+    };
+
+    let mut body = Body::from_bytes(card);
+    body.set_mime("image/png");
+    let mut res = Response::new(StatusCode::Ok);
+    res.insert_header(CACHE_CONTROL, "public, max-age=86400");
+    res.set_body(body);
+    Ok(res)
+}
+
+/// Draws a social card: the title wrapped over up to four lines, above the site name.
+/// Characters outside of printable ascii are left out.
+pub fn card(title: &str, site_name: &str) -> Image {
+    let mut image = Image::new(WIDTH, HEIGHT, BACKGROUND);
+    image.fill(0, 0, WIDTH, 12, ACCENT);
+    let columns = ((WIDTH - 2 * MARGIN) / (6 * TITLE_SCALE)) as usize;
+    for (i, line) in wrap(title, columns).iter().enumerate() {
+        let y = MARGIN + i as u32 * 10 * TITLE_SCALE;
+        draw_text(&mut image, line, MARGIN, y, TITLE_SCALE, TEXT);
+    }
+    let y = HEIGHT - MARGIN - 7 * NAME_SCALE;
+    draw_text(&mut image, site_name, MARGIN, y, NAME_SCALE, MUTED);
+    image
+}
+
+/// Breaks text into lines of at most `columns` characters, ending the last of the
+/// `MAX_LINES` with an ellipsis when the text doesn't fit
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let text: String = text
+        .chars()
+        .filter(|c| c.is_ascii_graphic() || c.is_whitespace())
+        .collect();
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        // text is ascii, so words longer than a line can be split anywhere
+        for part in word.as_bytes().chunks(columns) {
+            let part = std::str::from_utf8(part).unwrap_or_default();
+            if !line.is_empty() && line.len() + 1 + part.len() > columns {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(part);
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    if lines.len() > MAX_LINES {
+        lines.truncate(MAX_LINES);
+        let last = &mut lines[MAX_LINES - 1];
+        last.truncate(columns - 3);
+        last.truncate(last.trim_end().len());
+        last.push_str("...");
+    }
+    lines
+}
+
+/// Draws ascii text with its top left corner at `x`, `y`, every dot of the font a
+/// `scale` pixels square
+fn draw_text(image: &mut Image, text: &str, x: u32, y: u32, scale: u32, color: [u8; 4]) {
+    for (i, byte) in text.bytes().enumerate() {
+        let glyph = match byte {
+            b' '..=b'~' => &GLYPHS[(byte - b' ') as usize],
+            _ => continue,
+        };
+        let left = x + i as u32 * 6 * scale;
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..5 {
+                if bits & (0x10 >> column) != 0 {
+                    let top = y + row as u32 * scale;
+                    image.fill(left + column * scale, top, scale, scale, color);
+                }
+            }
+        }
+    }
+}
+
+/// A 5x7 font of the printable ascii characters, one row per byte from the top with
+/// the leftmost dot in the fifth bit
+#[rustfmt::skip]
+const GLYPHS: [[u8; 7]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+    [0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a], // #
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d], // &
+    [0x0c, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00], // '
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e], // 0
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e], // 1
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f], // 2
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e], // 3
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02], // 4
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e], // 5
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e], // 6
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e], // 8
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c], // 9
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], // :
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e], // @
+    [0x0e, 0x11, 0x11, 0x11, 0x1f, 0x11, 0x11], // A
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], // B
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e], // C
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], // D
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], // E
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], // F
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f], // G
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // H
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], // L
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // O
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], // P
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], // Q
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], // R
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e], // S
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a], // W
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], // X
+    [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], // Y
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], // Z
+    [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // \
+    [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e], // ]
+    [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f], // _
+    [0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f], // a
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1e], // b
+    [0x00, 0x00, 0x0e, 0x10, 0x10, 0x11, 0x0e], // c
+    [0x01, 0x01, 0x0d, 0x13, 0x11, 0x11, 0x0f], // d
+    [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e], // e
+    [0x06, 0x09, 0x08, 0x1c, 0x08, 0x08, 0x08], // f
+    [0x00, 0x0f, 0x11, 0x11, 0x0f, 0x01, 0x0e], // g
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11], // h
+    [0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x0e], // i
+    [0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0c], // j
+    [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12], // k
+    [0x0c, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // l
+    [0x00, 0x00, 0x1a, 0x15, 0x15, 0x11, 0x11], // m
+    [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11], // n
+    [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e], // o
+    [0x00, 0x00, 0x1e, 0x11, 0x1e, 0x10, 0x10], // p
+    [0x00, 0x00, 0x0d, 0x13, 0x0f, 0x01, 0x01], // q
+    [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10], // r
+    [0x00, 0x00, 0x0e, 0x10, 0x0e, 0x01, 0x1e], // s
+    [0x08, 0x08, 0x1c, 0x08, 0x08, 0x09, 0x06], // t
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0d], // u
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x0a, 0x04], // v
+    [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0a], // w
+    [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11], // x
+    [0x00, 0x00, 0x11, 0x11, 0x0f, 0x01, 0x0e], // y
+    [0x00, 0x00, 0x1f, 0x02, 0x04, 0x08, 0x1f], // z
+    [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02], // {
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // |
+    [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08], // }
+    [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00], // ~
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        app.at("/og/:file").get(image);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn posts_have_a_png_card_of_the_open_graph_size() {
+        let mut res = get(&path("2022-06-26-tide")).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.content_type().unwrap().essence(), "image/png");
+        let card = png::decode(&res.body_bytes().await.unwrap()).unwrap();
+        assert_eq!((card.width, card.height), (1200, 630));
+        assert!(card.pixels.chunks(4).any(|pixel| pixel == TEXT));
+
+        assert_eq!(get(&path("missing")).await.status(), StatusCode::NotFound);
+        assert_eq!(
+            get("/og/2022-06-26-tide.jpg").await.status(),
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn long_titles_wrap_and_end_with_an_ellipsis() {
+        assert_eq!(
+            wrap("Building a blog with tide", 12),
+            ["Building a", "blog with", "tide"]
+        );
+        let lines = wrap(&"word ".repeat(40), 12);
+        assert_eq!(lines.len(), MAX_LINES);
+        assert_eq!(lines[MAX_LINES - 1], "word word...");
+        assert_eq!(wrap("🏕 Camp 🏕", 12), ["Camp"]);
+    }
+}
@@ -0,0 +1,652 @@
+use std::io::{Error, ErrorKind};
+
+/// First bytes of every png file
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Largest image decoded, in pixels, so a small file can't claim gigabytes of memory
+const MAX_PIXELS: u64 = 64 * 1024 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code length code lengths of a dynamic block are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// An 8-bit RGBA image, rows top to bottom
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// An image filled with a single color
+    pub fn new(width: u32, height: u32, rgba: [u8; 4]) -> Image {
+        let len = width as usize * height as usize * 4;
+        Image {
+            width,
+            height,
+            pixels: rgba.iter().copied().cycle().take(len).collect(),
+        }
+    }
+
+    /// Fills a rectangle with a color, clipped to the image
+    pub fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, rgba: [u8; 4]) {
+        let (right, bottom) = ((x + width).min(self.width), (y + height).min(self.height));
+        for row in y.min(bottom)..bottom {
+            let start = (row as usize * self.width as usize + x.min(right) as usize) * 4;
+            let end = (row as usize * self.width as usize + right as usize) * 4;
+            for pixel in self.pixels[start..end].chunks_mut(4) {
+                pixel.copy_from_slice(&rgba);
+            }
+        }
+    }
+
+    /// Scales the image down to `width` pixels across, keeping its aspect ratio, with
+    /// every pixel averaging the ones it covers. Narrower images are returned as they are.
+    pub fn resize(&self, width: u32) -> Image {
+        if width == 0 || width >= self.width {
+            return self.clone();
+        }
+        let height = ((self.height as u64 * width as u64 + self.width as u64 / 2)
+            / self.width as u64)
+            .max(1) as u32;
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            let (top, bottom) = span(y, height, self.height);
+            for x in 0..width {
+                let (left, right) = span(x, width, self.width);
+                let mut sum = [0u64; 4];
+                for row in top..bottom {
+                    let start = (row as usize * self.width as usize + left as usize) * 4;
+                    let end = (row as usize * self.width as usize + right as usize) * 4;
+                    for pixel in self.pixels[start..end].chunks(4) {
+                        for (total, value) in sum.iter_mut().zip(pixel) {
+                            *total += *value as u64;
+                        }
+                    }
+                }
+                let count = ((bottom - top) * (right - left)) as u64;
+                pixels.extend(sum.iter().map(|total| ((total + count / 2) / count) as u8));
+            }
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Source pixels covered by pixel `i` of `to` when scaling down from `from`
+fn span(i: u32, to: u32, from: u32) -> (u32, u32) {
+    let start = (i as u64 * from as u64 / to as u64) as u32;
+    let end = ((i as u64 + 1) * from as u64 / to as u64) as u32;
+    (start, end.max(start + 1))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Decodes an 8-bit, non-interlaced png (grayscale, rgb or palette, with or without
+/// alpha) to RGBA. Other bit depths and interlaced images are `ErrorKind::Unsupported`.
+pub fn decode(bytes: &[u8]) -> Result<Image, Error> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(invalid("not a png"));
+    }
+    let mut pos = SIGNATURE.len();
+    let (mut header, mut palette, mut transparency): (&[u8], &[u8], &[u8]) = (&[], &[], &[]);
+    let mut data = Vec::new();
+    while pos + 8 <= bytes.len() {
+        let len = be32(&bytes[pos..]) as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let body = bytes
+            .get(pos + 8..pos + 8 + len)
+            .ok_or_else(|| invalid("truncated chunk"))?;
+        pos += len + 12;
+        match kind {
+            b"IHDR" => header = body,
+            b"PLTE" => palette = body,
+            b"tRNS" => transparency = body,
+            b"IDAT" => data.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    if header.len() != 13 {
+        return Err(invalid("missing IHDR"));
+    }
+    let (width, height) = (be32(&header[0..]), be32(&header[4..]));
+    let (depth, color, interlace) = (header[8], header[9], header[12]);
+    if width == 0 || height == 0 || width as u64 * height as u64 > MAX_PIXELS {
+        return Err(invalid("image dimensions out of range"));
+    }
+    if depth != 8 || interlace != 0 {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "only 8-bit non-interlaced pngs are supported",
+        ));
+    }
+    let channels = match color {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid("unknown color type")),
+    };
+    let stride = width as usize * channels;
+    let size = (stride + 1) * height as usize;
+    if data.len() < 2 || data[0] & 0x0f != 8 {
+        return Err(invalid("image data isn't deflate compressed"));
+    }
+    let raw = inflate(&data[2..], size)?;
+    if raw.len() != size {
+        return Err(invalid("image data doesn't match the dimensions"));
+    }
+    let samples = unfilter(&raw, stride, channels)?;
+
+    let pixels = match color {
+        0 => samples.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        2 => samples
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        3 => {
+            let mut pixels = Vec::with_capacity(samples.len() * 4);
+            for &index in &samples {
+                let i = index as usize;
+                let rgb = palette
+                    .get(i * 3..i * 3 + 3)
+                    .ok_or_else(|| invalid("palette index out of range"))?;
+                let alpha = transparency.get(i).copied().unwrap_or(255);
+                pixels.extend_from_slice(&[rgb[0], rgb[1], rgb[2], alpha]);
+            }
+            pixels
+        }
+        4 => samples
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        _ => samples,
+    };
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Reverses the per-row filters of the decompressed image data
+fn unfilter(raw: &[u8], stride: usize, bpp: usize) -> Result<Vec<u8>, Error> {
+    let mut samples = vec![0u8; raw.len() / (stride + 1) * stride];
+    for (y, line) in raw.chunks(stride + 1).enumerate() {
+        let (above, rest) = samples.split_at_mut(y * stride);
+        let prior = if y == 0 {
+            None
+        } else {
+            Some(&above[(y - 1) * stride..])
+        };
+        let row = &mut rest[..stride];
+        for x in 0..stride {
+            let a = if x >= bpp { row[x - bpp] } else { 0 };
+            let b = prior.map_or(0, |p| p[x]);
+            let c = if x >= bpp {
+                prior.map_or(0, |p| p[x - bpp])
+            } else {
+                0
+            };
+            let predictor = match line[0] {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(invalid("unknown filter type")),
+            };
+            row[x] = line[x + 1].wrapping_add(predictor);
+        }
+    }
+    Ok(samples)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reads a deflate stream least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> Result<u32, Error> {
+        while self.count < n {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| invalid("truncated image data"))?;
+            self.pos += 1;
+            self.bits |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.bits & ((1 << n) - 1);
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drops the bits left of the current byte, ahead of a stored block
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical huffman code, decoded one bit at a time
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, Error> {
+        let (mut code, mut first, mut index) = (0usize, 0usize, 0usize);
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as usize;
+            let count = count as usize;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid huffman code"))
+    }
+}
+
+/// Decompresses a raw deflate stream, refusing to grow past `limit` bytes
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bits: 0,
+        count: 0,
+    };
+    let mut out = Vec::with_capacity(limit);
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let len = reader.bits(16)? as usize;
+                reader.bits(16)?;
+                let stored = data
+                    .get(reader.pos..reader.pos + len)
+                    .ok_or_else(|| invalid("truncated image data"))?;
+                out.extend_from_slice(stored);
+                reader.pos += len;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                let (literals, distances) = (Huffman::new(&lengths), Huffman::new(&[5; 30]));
+                inflate_block(&mut reader, &mut out, &literals, &distances, limit)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances, limit)?;
+            }
+            _ => return Err(invalid("invalid deflate block")),
+        }
+        if out.len() > limit {
+            return Err(invalid("image data larger than its dimensions"));
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Reads the literal/length and distance codes of a dynamic huffman block
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), Error> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[i] = reader.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (len, repeat) = match code.decode(reader)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("repeated code length without a previous one"))?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            18 => (0, 11 + reader.bits(7)?),
+            _ => return Err(invalid("invalid code length")),
+        };
+        lengths.resize(lengths.len() + repeat as usize, len);
+    }
+    if lengths.len() > literals + distances {
+        return Err(invalid("code lengths overflow the block"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+    limit: usize,
+) -> Result<(), Error> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let i = symbol - 257;
+        if i >= LENGTH_BASE.len() {
+            return Err(invalid("invalid length code"));
+        }
+        let len = LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i] as u32)? as usize;
+        let d = distances.decode(reader)? as usize;
+        if d >= DISTANCE_BASE.len() {
+            return Err(invalid("invalid distance code"));
+        }
+        let distance = DISTANCE_BASE[d] as usize + reader.bits(DISTANCE_EXTRA[d] as u32)? as usize;
+        if distance > out.len() || out.len() + len > limit {
+            return Err(invalid("invalid back reference"));
+        }
+        let start = out.len() - distance;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+}
+
+/// Encodes an image as an RGBA png
+pub fn encode(image: &Image) -> Vec<u8> {
+    let stride = image.width as usize * 4;
+    let mut raw = Vec::with_capacity((stride + 1) * image.height as usize);
+    for row in image.pixels.chunks(stride.max(1)) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Writes a deflate stream least significant bit first
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, n: u32) {
+        self.bits |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit
+    fn code(&mut self, code: u32, n: u32) {
+        self.bits(code.reverse_bits() >> (32 - n), n);
+    }
+
+    fn literal(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, len: usize, distance: usize) {
+        let i = LENGTH_BASE
+            .iter()
+            .rposition(|&base| base as usize <= len)
+            .unwrap();
+        self.literal(257 + i as u32);
+        self.bits(
+            (len - LENGTH_BASE[i] as usize) as u32,
+            LENGTH_EXTRA[i] as u32,
+        );
+        let d = DISTANCE_BASE
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap();
+        self.code(d as u32, 5);
+        self.bits(
+            (distance - DISTANCE_BASE[d] as usize) as u32,
+            DISTANCE_EXTRA[d] as u32,
+        );
+    }
+}
+
+/// Compresses data into a zlib stream of a single fixed huffman block, replacing
+/// repeats with back references to the last position sharing their first bytes
+fn zlib(data: &[u8]) -> Vec<u8> {
+    const WINDOW: usize = 32 * 1024;
+    let mut writer = BitWriter {
+        out: vec![0x78, 0x01],
+        ..BitWriter::default()
+    };
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+    let mut last = vec![usize::MAX; 1 << 15];
+    let mut i = 0;
+    while i < data.len() {
+        let mut len = 0;
+        let mut distance = 0;
+        if i + 3 <= data.len() {
+            let hash =
+                ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize)
+                    & 0x7fff;
+            let candidate = last[hash];
+            last[hash] = i;
+            if candidate != usize::MAX && i - candidate <= WINDOW {
+                let max = (data.len() - i).min(258);
+                len = (0..max)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                distance = i - candidate;
+            }
+        }
+        if len >= 3 {
+            writer.copy(len, distance);
+            i += len;
+        } else {
+            writer.literal(data[i] as u32);
+            i += 1;
+        }
+    }
+    writer.literal(256);
+    if writer.count > 0 {
+        writer.bits(0, 8 - writer.count);
+    }
+    let mut out = writer.out;
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_images_decode_to_the_same_pixels() {
+        let image = Image {
+            width: 37,
+            height: 21,
+            pixels: (0..37 * 21 * 4).map(|i| (i * i % 251) as u8).collect(),
+        };
+        assert_eq!(decode(&encode(&image)).unwrap(), image);
+    }
+
+    #[test]
+    fn content_images_decode() {
+        // rgba, grayscale and palette images compressed with dynamic huffman codes
+        for (file, width, height) in [
+            ("favicon-32x32.png", 32, 32),
+            ("43k38ha3pm0q9c6vthby.png", 515, 246),
+            ("jpkhb0np6184hp0wm53q.png", 1141, 697),
+        ] {
+            let bytes = std::fs::read(format!("content/assets/{}", file)).unwrap();
+            let image = decode(&bytes).unwrap();
+            assert_eq!((image.width, image.height), (width, height), "{}", file);
+            assert_eq!(image.pixels.len(), width as usize * height as usize * 4);
+        }
+    }
+
+    #[test]
+    fn resizing_keeps_the_aspect_ratio_and_averages_pixels() {
+        let (white, black) = ([255, 255, 255, 255], [0, 0, 0, 255]);
+        let image = Image {
+            width: 4,
+            height: 2,
+            pixels: [white, black, black, black, white, black, black, black].concat(),
+        };
+        let resized = image.resize(2);
+        assert_eq!((resized.width, resized.height), (2, 1));
+        assert_eq!(&resized.pixels[..4], &[128, 128, 128, 255]);
+        assert_eq!(&resized.pixels[4..], &[0, 0, 0, 255]);
+        assert_eq!(image.resize(8), image);
+    }
+
+    #[test]
+    fn other_bit_depths_are_unsupported() {
+        let mut png = encode(&Image {
+            width: 1,
+            height: 1,
+            pixels: vec![0, 0, 0, 255],
+        });
+        // bit depth of the IHDR, ahead of a crc nothing checks
+        png[24] = 16;
+        assert_eq!(decode(&png).unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            decode(b"GIF89a").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -1,17 +1,278 @@
 use async_std::{fs::File, io::ReadExt};
-use serde::Serialize;
-use std::{collections::VecDeque, io::Result};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result};
+use std::{path::Path, time::SystemTime};
 
-#[derive(Serialize, Default)]
+use crate::config::{self, AnchorPlacement, CONFIG};
+use crate::content;
+use crate::diskcache::{DiskCache, Rendered};
+use crate::markdown::{self, escape_html, RenderOptions};
+
+#[derive(Serialize, Default, Clone)]
 pub struct Post {
     slug: String,
     url: String,
     title: String,
     hide_title: bool,
     description: String,
+    date: Option<NaiveDate>,
+    updated: Option<NaiveDate>,
+    was_updated: bool,
+    freshness: Option<Freshness>,
+    /// Fenced or indented code blocks of the post
+    code_blocks: usize,
+    /// How code heavy the post is, unset for posts without code
+    complexity: Option<Complexity>,
+    tags: Vec<String>,
+    featured: bool,
+    aliases: Vec<String>,
+    /// Asks search engines not to index the post, which also keeps it out of the sitemap
+    noindex: bool,
+    /// Drafts (`published: false` or `draft: true`) are only served with a preview token
+    draft: bool,
+    /// Posts dated in the future are scheduled, and hidden like drafts until that day
+    scheduled: bool,
+    /// Last day the post is shown, after which it leaves listings and answers `EXPIRED_STATUS`
+    expires: Option<NaiveDate>,
+    expired: bool,
+    /// Minutes it takes to read the post, estimated from its length unless the
+    /// frontmatter declares `reading_time`
+    reading_time: Option<u32>,
+    /// Emoji or asset path shown next to the post in listings
+    icon: Option<String>,
+    /// Position of the post in listings sorted with `LISTING_SORT=weight`, lower first
+    weight: Option<i64>,
+    /// Url of the original when the post is syndicated from elsewhere
+    canonical: Option<String>,
+    /// Language of the post when it differs from the site's locale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+    /// Text direction of the post, `TEXT_DIR` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dir: Option<String>,
+    /// Extra stylesheets of the post, as urls of files of the assets directory
+    #[serde(rename = "post_styles")]
+    styles: Vec<String>,
+    /// Extra scripts of the post, as urls of files of the assets directory
+    #[serde(rename = "post_scripts")]
+    scripts: Vec<String>,
+    excerpt: Option<String>,
+    /// Text of the first heading of the content, for skip links and landmarks
+    first_heading: Option<String>,
     content: String,
+    /// Ids of the elements of the rendered content that can be linked to
+    anchors: Vec<String>,
+    #[serde(skip)]
+    template: Option<String>,
+    #[serde(skip)]
+    modified: Option<SystemTime>,
+}
+
+/// Listing entry of a post, without its rendered content
+#[derive(Serialize)]
+pub struct Summary<'a> {
+    title: &'a str,
+    url: String,
+    description: &'a str,
+    /// Html fragment shown under the title, without scripts or large headings
+    excerpt: Option<&'a str>,
+    date: Option<String>,
+    tags: &'a [String],
+    reading_time: u32,
+    /// New or updated badge, for posts published or updated within `FRESHNESS_WINDOW`
+    freshness: Option<Freshness>,
+    /// Emoji shown next to the post, or `icon_src` for an image asset
+    icon: Option<String>,
+    icon_src: Option<String>,
+}
+
+impl<'a> From<&'a Post> for Summary<'a> {
+    fn from(post: &'a Post) -> Self {
+        let icon = match &post.icon {
+            Some(icon) => icon.clone(),
+            None => CONFIG.with(|c| c.default_icon.clone()),
+        };
+        let (icon, icon_src) = if icon.contains('/') || icon.contains('.') {
+            (None, Some(icon))
+        } else {
+            (Some(icon), None)
+        };
+        Summary {
+            icon,
+            icon_src,
+            title: post.title(),
+            url: format!("/{}", post.url()),
+            description: post.description(),
+            excerpt: post.excerpt(),
+            date: post.date().map(|date| date.to_string()),
+            tags: post.tags(),
+            reading_time: post.reading_time(),
+            freshness: post.freshness,
+        }
+    }
+}
+
+/// Badge for posts published or updated recently
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    New,
+    Updated,
+}
+
+impl Freshness {
+    /// Computes the badge of a post from its dates, relative to `today`
+    fn compute(
+        date: Option<NaiveDate>,
+        updated: Option<NaiveDate>,
+        today: NaiveDate,
+        window: i64,
+    ) -> Option<Self> {
+        let is_recent = |d: NaiveDate| (0..=window).contains(&(today - d).num_days());
+        if matches!(date, Some(d) if is_recent(d)) {
+            Some(Freshness::New)
+        } else if matches!(updated, Some(d) if is_recent(d)) {
+            Some(Freshness::Updated)
+        } else {
+            None
+        }
+    }
+}
+
+/// Badge for posts with code, from the share of code lines against prose
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Complexity {
+    Light,
+    Moderate,
+    Heavy,
+}
+
+impl Complexity {
+    /// Computes the badge from the lines of each code block and the words of prose,
+    /// counting a line of prose as `WORDS_PER_LINE` words
+    fn compute(code_blocks: &[usize], words: usize) -> Option<Self> {
+        if code_blocks.is_empty() {
+            return None;
+        }
+        let code = code_blocks.iter().sum::<usize>() as f64;
+        let prose = words as f64 / WORDS_PER_LINE;
+        let share = code / (code + prose).max(1.0);
+        if share >= 0.5 {
+            Some(Complexity::Heavy)
+        } else if share >= 0.2 {
+            Some(Complexity::Moderate)
+        } else {
+            Some(Complexity::Light)
+        }
+    }
+}
+
+/// Rewrites the raw markdown of a post before it is parsed, such as expanding includes or
+/// shortcodes. An error fails the read of the post.
+pub type Preprocessor = fn(&str) -> Result<String>;
+
+/// Rewrites the rendered html of a post, such as rewriting links or lazy loading images.
+/// An error fails the read of the post.
+pub type Postprocessor = fn(&str) -> Result<String>;
+
+/// Options controlling how a post is rendered
+pub struct PostOptions {
+    /// Prefixes heading ids with the post slug, generated ones of the content and all
+    /// of those of the excerpt, so that pages rendering several posts don't end up with
+    /// colliding anchors
+    pub prefix_heading_ids: bool,
+    /// Run in order over the markdown once the frontmatter is split off, `includes`,
+    /// `callouts` then `definition_lists` by default
+    pub preprocessors: Vec<Preprocessor>,
+    /// Run in order over the html, rendered or cached, of markdown posts and over html
+    /// pages as they are, before the post footer is appended. `image_cdn` by default.
+    pub postprocessors: Vec<Postprocessor>,
+    /// Links the bare `http(s)://` urls of the text
+    pub autolinks: bool,
+    /// Heading of the footnotes section, `FOOTNOTES_HEADING` by default
+    pub footnotes_heading: String,
+    /// Heading levels given an id and a permalink anchor, `ANCHOR_LEVELS` by default
+    pub anchor_levels: Vec<u8>,
+    /// Text or html of the permalink anchors, `ANCHOR_SYMBOL` by default
+    pub anchor_symbol: String,
+    /// Whether permalink anchors go before or after the heading text, `ANCHOR_PLACEMENT`
+    /// by default
+    pub anchor_placement: AnchorPlacement,
+}
+
+impl Default for PostOptions {
+    fn default() -> Self {
+        PostOptions {
+            prefix_heading_ids: false,
+            preprocessors: vec![includes, callouts, definition_lists],
+            postprocessors: vec![image_cdn],
+            autolinks: true,
+            footnotes_heading: CONFIG.with(|c| c.footnotes_heading.clone()),
+            anchor_levels: CONFIG.with(|c| c.anchor_levels.clone()),
+            anchor_symbol: CONFIG.with(|c| c.anchor_symbol.clone()),
+            anchor_placement: CONFIG.with(|c| c.anchor_placement),
+        }
+    }
+}
+
+thread_local! {
+    /// The configured post footer, converted to html once
+    static FOOTER: Option<String> = CONFIG.with(|c| c.post_footer.as_deref().map(footer_html));
+}
+
+/// Converts a post footer, markdown or the path of a markdown file, into html
+fn footer_html(footer: &str) -> String {
+    let markdown = std::fs::read_to_string(footer).unwrap_or_else(|_| String::from(footer));
+    let options = PostOptions::default();
+    markdown::to_html(
+        &markdown,
+        &RenderOptions {
+            id_prefix: None,
+            autolinks: true,
+            footnotes_heading: &options.footnotes_heading,
+            anchor_levels: &options.anchor_levels,
+            anchor_symbol: &options.anchor_symbol,
+            anchor_placement: options.anchor_placement,
+        },
+    )
 }
 
+/// Marks where the listing excerpt of a post ends
+const MORE_MARKER: &str = "<!-- more -->";
+
+/// Frontmatter keys understood by posts, see `Post::frontmatter`
+const FRONTMATTER_KEYS: [&str; 22] = [
+    "title",
+    "description",
+    "slug",
+    "date",
+    "updated",
+    "hide_title",
+    "template",
+    "tags",
+    "featured",
+    "aliases",
+    "noindex",
+    "published",
+    "draft",
+    "reading_time",
+    "icon",
+    "weight",
+    "canonical",
+    "lang",
+    "dir",
+    "styles",
+    "scripts",
+    "expires",
+];
+
+/// Reading speed the reading time is estimated with
+const WORDS_PER_MINUTE: f64 = 200.0;
+
+/// Words of prose weighed against a line of code by `Complexity`
+const WORDS_PER_LINE: f64 = 12.0;
+
 impl Post {
     pub fn new() -> Self {
         Post {
@@ -20,50 +281,866 @@ impl Post {
     }
 
     pub async fn from_file(path: &str) -> Result<Self> {
+        Post::from_file_with_options(path, &PostOptions::default()).await
+    }
+
+    pub async fn from_file_with_options(path: &str, options: &PostOptions) -> Result<Self> {
         // open markdown file and read to string
         tracing::info!("reading markdown file path {}", path);
-        let url = path.strip_suffix(".md").unwrap();
+        if !CONFIG.with(|c| c.is_content(Path::new(path))) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a markdown or html file", path),
+            ));
+        }
         let mut md_file = File::open(path).await?;
         let mut buf = String::new();
         md_file.read_to_string(&mut buf).await?;
 
         let mut post = Post::new();
-        let slug = url.trim_start_matches("content/posts/");
-        post.slug = String::from(slug);
-        post.url = String::from(post.slug.replacen('-', "/", 3));
-        post.content = buf;
+        post.modified = md_file.metadata().await?.modified().ok();
+        let slug = Path::new(path).file_stem().unwrap_or_default();
+        post.slug = slug.to_string_lossy().into_owned();
+        post.url = post.slug.replacen('-', "/", 3);
+        if CONFIG.with(|c| c.path_lowercase) {
+            // linked as the lowercase url NormalizePath redirects to
+            post.url = post.url.to_lowercase();
+        }
+        post.date = parse_date(&post.slug);
         tracing::info!("creating post with slug {}, url: {}", post.slug, post.url);
 
-        if post.content.starts_with("---\n") {
-            let mut results: VecDeque<&str> = post.content.splitn(3, "---\n").skip(1).collect();
-            let vars = results.pop_front().unwrap();
-            let content = results.pop_front().unwrap();
-
-            tracing::info!("variables declared in markdown {}", vars);
-            for line in vars.lines() {
-                let (k, v) = line.split_once(":").unwrap();
-                let v = String::from(v.trim());
-                match k {
-                    "title" => post.title = v,
-                    "description" => post.description = v,
-                    "slug" => post.slug = v,
-                    "hide_title" => post.hide_title = true,
-                    _ => {}
-                };
+        let is_html = CONFIG.with(|c| c.is_html(Path::new(path)));
+        let (vars, content) = split_frontmatter(&buf, is_html);
+        if let Some(vars) = vars {
+            let unknown = unknown_keys(vars);
+            if !unknown.is_empty() && CONFIG.with(|c| c.strict_frontmatter) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: unknown frontmatter keys {}", path, unknown.join(", ")),
+                ));
             }
+            post.frontmatter(vars);
+        }
+        post.content = String::from(content);
 
-            post.content = String::from(content);
+        // only display updates made well after publishing
+        if let (Some(date), Some(updated)) = (post.date, post.updated) {
+            let threshold = CONFIG.with(|c| c.updated_threshold);
+            post.was_updated = (updated - date).num_days() > threshold;
         }
+        let today = Utc::today().naive_utc();
+        let window = CONFIG.with(|c| c.freshness_window);
+        post.freshness = Freshness::compute(post.date, post.updated, today, window);
+        post.scheduled = matches!(post.date, Some(date) if date > today);
+        post.expired = matches!(post.expires, Some(expires) if expires < today);
 
-        // convert markdown file to html
-        tracing::debug!("parsing markdown into html {}", post.content);
-        let mut options = pulldown_cmark::Options::empty();
-        options.insert(pulldown_cmark::Options::ENABLE_HEADING_ATTRIBUTES);
-        let parser = pulldown_cmark::Parser::new_ext(&post.content, options);
-        let mut html_content = String::new();
-        pulldown_cmark::html::push_html(&mut html_content, parser);
-        post.content = String::from(html_content);
+        if is_html {
+            // injected verbatim, only the excerpt marker is honored
+            if let Some((excerpt, _)) = post.content.split_once(MORE_MARKER) {
+                post.excerpt = Some(String::from(excerpt));
+            }
+            // the markup isn't parsed, `reading_time` in the frontmatter gives a better figure
+            post.reading_time.get_or_insert(1);
+            post.content = process(&options.postprocessors, &post.content, path)?;
+            post.append_footer(path);
+            return Ok(post);
+        }
+
+        post.content = process(&options.preprocessors, &post.content, path)?;
 
+        let slug = post.slug.clone();
+        let id_prefix = if options.prefix_heading_ids {
+            Some(slug.as_str())
+        } else {
+            None
+        };
+        let render = RenderOptions {
+            id_prefix,
+            autolinks: options.autolinks,
+            footnotes_heading: &options.footnotes_heading,
+            anchor_levels: &options.anchor_levels,
+            anchor_symbol: &options.anchor_symbol,
+            anchor_placement: options.anchor_placement,
+        };
+
+        // reuse the render of an unchanged source, before any parsing of the markdown
+        let cache = CONFIG
+            .with(|c| c.render_cache_dir.clone())
+            .map(|dir| DiskCache::new(Path::new(&dir)));
+        let hash = cache.as_ref().map(|_| {
+            let (excerpt_length, description_length, ellipsis) =
+                CONFIG.with(|c| (c.excerpt_length, c.description_length, c.ellipsis.clone()));
+            let (paragraphs, heading_level) =
+                CONFIG.with(|c| (c.excerpt_paragraphs, c.excerpt_heading_level));
+            let options = format!(
+                "{:?} {} {:?} {:?} {:?} {:?} {:?} {} {} {} {} {:?} {:?}",
+                render.id_prefix,
+                render.autolinks,
+                render.footnotes_heading,
+                render.anchor_levels,
+                render.anchor_symbol,
+                render.anchor_placement,
+                post.description,
+                excerpt_length,
+                paragraphs,
+                heading_level,
+                description_length,
+                ellipsis,
+                post.reading_time,
+            );
+            DiskCache::hash(&post.content, &options)
+        });
+        let cached = match (&cache, &hash) {
+            (Some(cache), Some(hash)) => cache.get(&slug, hash).await,
+            _ => None,
+        };
+        match cached {
+            Some(rendered) => {
+                post.content = rendered.content;
+                post.excerpt = rendered.excerpt;
+                post.description = rendered.description;
+                post.reading_time = rendered.reading_time;
+                post.code_blocks = rendered.code_blocks;
+                post.complexity = rendered.complexity;
+                post.first_heading = rendered.first_heading;
+            }
+            None => {
+                post.render(&render);
+                if let (Some(cache), Some(hash)) = (&cache, &hash) {
+                    let rendered = Rendered {
+                        content: post.content.clone(),
+                        excerpt: post.excerpt.clone(),
+                        description: post.description.clone(),
+                        reading_time: post.reading_time,
+                        code_blocks: post.code_blocks,
+                        complexity: post.complexity,
+                        first_heading: post.first_heading.clone(),
+                    };
+                    cache.put(&slug, hash, rendered).await;
+                }
+            }
+        }
+        post.content = process(&options.postprocessors, &post.content, path)?;
+        post.append_footer(path);
+        post.anchors = markdown::html_attributes(&post.content, "id")
+            .map(String::from)
+            .collect();
         Ok(post)
     }
+
+    /// Renders the markdown content to html, deriving the code blocks, first heading and
+    /// complexity, and the excerpt, description and reading time the frontmatter doesn't set
+    fn render(&mut self, render: &RenderOptions) {
+        let code_blocks = markdown::code_block_lines(&self.content);
+        let words = markdown::plain_text(&self.content)
+            .split_whitespace()
+            .count();
+        self.code_blocks = code_blocks.len();
+        self.first_heading = markdown::first_heading(&self.content);
+        self.complexity = Complexity::compute(&code_blocks, words);
+
+        let (paragraphs, heading_level) =
+            CONFIG.with(|c| (c.excerpt_paragraphs, c.excerpt_heading_level));
+        // split off the excerpt when the content declares one
+        if let Some((excerpt, rest)) = self.content.split_once(MORE_MARKER) {
+            tracing::info!("excerpt boundary declared in markdown");
+            let html = markdown::excerpt_html(
+                excerpt,
+                0,
+                heading_level,
+                render.autolinks,
+                render.id_prefix,
+            );
+            self.excerpt = Some(html);
+            self.content = format!("{}{}", excerpt, rest);
+        } else if paragraphs > 0 {
+            let html = markdown::excerpt_html(
+                &self.content,
+                paragraphs,
+                heading_level,
+                render.autolinks,
+                render.id_prefix,
+            );
+            self.excerpt = Some(html).filter(|html| !html.is_empty());
+        }
+
+        // otherwise derive the excerpt and a missing description from the text
+        let text = markdown::plain_text(&self.content);
+        let words = text.split_whitespace().count() as f64;
+        let minutes = (words / WORDS_PER_MINUTE).ceil().max(1.0);
+        self.reading_time.get_or_insert(minutes as u32);
+        let (excerpt_length, description_length, ellipsis) =
+            CONFIG.with(|c| (c.excerpt_length, c.description_length, c.ellipsis.clone()));
+        if self.excerpt.is_none() && !text.is_empty() {
+            let excerpt = markdown::truncate(&text, excerpt_length, &ellipsis);
+            self.excerpt = Some(format!("<p>{}</p>", escape_html(&excerpt)));
+        }
+        if self.description.is_empty() {
+            self.description = markdown::truncate(&text, description_length, &ellipsis);
+        }
+
+        // convert markdown file to html
+        tracing::debug!("parsing markdown into html {}", self.content);
+        self.content = markdown::to_html(&self.content, render);
+    }
+
+    /// Applies the `key: value` lines of a frontmatter block
+    fn frontmatter(&mut self, vars: &str) {
+        tracing::info!("variables declared in frontmatter {}", vars);
+        for (k, v) in frontmatter_vars(vars) {
+            let v = String::from(v);
+            match k {
+                "title" => self.title = v,
+                "description" => self.description = v,
+                "slug" => self.slug = v,
+                "date" => self.date = parse_date(&v).or(self.date),
+                "updated" => self.updated = parse_date(&v),
+                "hide_title" => self.hide_title = true,
+                "template" => self.template = Some(v),
+                "tags" => self.tags = parse_list(&v),
+                "featured" => self.featured = v == "true",
+                "aliases" => self.aliases = parse_list(&v),
+                "noindex" => self.noindex = v == "true",
+                "published" => self.draft = v == "false",
+                "draft" => self.draft = v == "true",
+                "reading_time" => self.reading_time = v.parse().ok().or(self.reading_time),
+                "icon" => self.icon = Some(v),
+                "weight" => self.weight = v.parse().ok(),
+                "canonical" => self.canonical = Some(v),
+                "lang" => self.lang = Some(v),
+                "dir" => self.dir = Some(v).filter(|dir| config::text_dir(dir)),
+                "styles" => self.styles = asset_urls(&v, &["css"]),
+                "scripts" => self.scripts = asset_urls(&v, &["js", "mjs"]),
+                "expires" => self.expires = parse_date(&v),
+                _ => {}
+            };
+        }
+    }
+
+    /// Appends the configured footer to posts read from the posts directory
+    fn append_footer(&mut self, path: &str) {
+        if path.starts_with(&CONFIG.with(|c| c.posts_dir())) {
+            FOOTER.with(|footer| {
+                if let Some(footer) = footer {
+                    self.content.push_str("<footer class=\"post-license\">");
+                    self.content.push_str(footer);
+                    self.content.push_str("</footer>");
+                }
+            });
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Url of the post relative to the site root, without a leading slash
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Listing excerpt of the post, declared with `<!-- more -->` or taken from its start
+    pub fn excerpt(&self) -> Option<&str> {
+        self.excerpt.as_deref()
+    }
+
+    pub fn date(&self) -> Option<NaiveDate> {
+        self.date
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Whether the frontmatter flags the post as `featured: true`
+    pub fn featured(&self) -> bool {
+        self.featured
+    }
+
+    /// Older paths the post should also be reachable from
+    pub fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    /// Whether the frontmatter asks search engines not to index the post
+    pub fn noindex(&self) -> bool {
+        self.noindex
+    }
+
+    /// Whether the post is an unpublished draft, or scheduled for a later day
+    pub fn draft(&self) -> bool {
+        self.draft || self.scheduled
+    }
+
+    /// Whether the post is past its `expires` date
+    pub fn expired(&self) -> bool {
+        self.expired
+    }
+
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    /// Minutes it takes to read the post
+    pub fn reading_time(&self) -> u32 {
+        self.reading_time.unwrap_or(1)
+    }
+
+    /// Position of the post in weighted listings, when the frontmatter sets one
+    pub fn weight(&self) -> Option<i64> {
+        self.weight
+    }
+
+    /// Canonical url the frontmatter points at instead of the site's own
+    pub fn canonical(&self) -> Option<&str> {
+        self.canonical.as_deref()
+    }
+
+    /// Day the post was last updated, when the frontmatter says so
+    pub fn updated(&self) -> Option<NaiveDate> {
+        self.updated
+    }
+
+    /// Whether the rendered content has an element with the given id to link to
+    pub fn has_anchor(&self, id: &str) -> bool {
+        self.anchors.iter().any(|anchor| anchor == id)
+    }
+
+    /// Rendered html content of the post
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Name of the template the post asks to be rendered with, if any
+    pub fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    /// Last modified time of the markdown file the post was read from
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+}
+
+/// Runs content through a chain of pre or postprocessors, naming the file in errors
+fn process(chain: &[fn(&str) -> Result<String>], content: &str, path: &str) -> Result<String> {
+    let mut content = String::from(content);
+    for step in chain {
+        content =
+            step(&content).map_err(|err| Error::new(err.kind(), format!("{}: {}", path, err)))?;
+    }
+    Ok(content)
+}
+
+/// Inlines the `{{include: path}}` fragments of the content directory
+pub fn includes(markdown: &str) -> Result<String> {
+    let content_dir = CONFIG.with(|c| c.content_dir.clone());
+    Ok(markdown::includes(markdown, Path::new(&content_dir)))
+}
+
+/// Renders `> [!NOTE]` style blockquotes as callouts
+pub fn callouts(markdown: &str) -> Result<String> {
+    Ok(markdown::callouts(markdown))
+}
+
+/// Renders `Term` lines followed by `: definition` lines as definition lists
+pub fn definition_lists(markdown: &str) -> Result<String> {
+    Ok(markdown::definition_lists(markdown))
+}
+
+/// Points the `/assets/` images of rendered html at `IMAGE_CDN_BASE`, leaving them local
+/// when it's unset
+pub fn image_cdn(html: &str) -> Result<String> {
+    match CONFIG.with(|c| c.image_cdn_base.clone()) {
+        Some(base) => Ok(cdn_images(html, &base)),
+        None => Ok(String::from(html)),
+    }
+}
+
+/// Points the `src` of the `/assets/` images of html at `base`
+fn cdn_images(html: &str, base: &str) -> String {
+    let local = " src=\"/assets/";
+    let remote = format!(" src=\"{}/assets/", base);
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("<img ") {
+        let end = rest[start..]
+            .find('>')
+            .map(|end| start + end)
+            .unwrap_or(rest.len());
+        output.push_str(&rest[..start]);
+        output.push_str(&rest[start..end].replacen(local, &remote, 1));
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Splits raw content into its frontmatter block, if any, and the body. Markdown declares
+/// frontmatter between `---` lines, or `+++` lines for toml, html pages in a leading comment.
+pub fn split_frontmatter(raw: &str, is_html: bool) -> (Option<&str>, &str) {
+    if is_html {
+        let trimmed = raw.trim_start();
+        if let Some((vars, content)) = trimmed
+            .strip_prefix("<!--")
+            .and_then(|rest| rest.split_once("-->"))
+        {
+            return (Some(vars.trim().trim_matches('-')), content);
+        }
+    } else {
+        for delimiter in ["---\n", "+++\n"] {
+            if let Some(rest) = raw.strip_prefix(delimiter) {
+                let mut results: VecDeque<&str> = rest.splitn(2, delimiter).collect();
+                let vars = results.pop_front().unwrap_or_default();
+                let content = results.pop_front().unwrap_or_default();
+                return (Some(vars), content);
+            }
+        }
+    }
+    (None, raw)
+}
+
+/// The trimmed `key: value` pairs of a frontmatter block, or `key = "value"` pairs of toml
+/// with the quotes removed, skipping comments, tables and lines without a key
+pub fn frontmatter_vars(vars: &str) -> impl Iterator<Item = (&str, &str)> {
+    vars.lines()
+        .filter(|line| !line.trim_start().starts_with(['#', '[']))
+        .filter_map(|line| {
+            let at = line.find([':', '='])?;
+            let (k, v) = (line[..at].trim(), line[at + 1..].trim());
+            if line[at..].starts_with('=') {
+                Some((k, unquote(v)))
+            } else {
+                Some((k, v))
+            }
+        })
+}
+
+/// A toml string without its quotes, other values as they are
+pub fn unquote(value: &str) -> &str {
+    let quoted = value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')));
+    if quoted {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+/// Keys of a frontmatter block that are neither understood nor allowed by `FRONTMATTER_KEYS`
+fn unknown_keys(vars: &str) -> Vec<&str> {
+    frontmatter_vars(vars)
+        .map(|(k, _)| k)
+        .filter(|k| !FRONTMATTER_KEYS.contains(k))
+        .filter(|k| !CONFIG.with(|c| c.frontmatter_keys.iter().any(|allowed| allowed == k)))
+        .collect()
+}
+
+/// Parses a frontmatter list such as `[rust, webdev]`, or `["rust", "webdev"]` in toml
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|item| unquote(item.trim()))
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Urls of the files of a frontmatter list that exist in the assets directory with one of
+/// the given extensions, such as `[demo.js]` or `[/assets/demo.js]`, skipping the others
+fn asset_urls(value: &str, extensions: &[&str]) -> Vec<String> {
+    let assets = CONFIG.with(|c| Path::new(&c.content_dir).join("assets"));
+    assets_in(&assets, value, extensions)
+}
+
+/// Urls of the files of a frontmatter list found in `assets`, see `asset_urls`
+fn assets_in(assets: &Path, value: &str, extensions: &[&str]) -> Vec<String> {
+    parse_list(value)
+        .into_iter()
+        .filter_map(|asset| {
+            let name = asset
+                .trim_start_matches("/assets/")
+                .trim_start_matches("assets/");
+            let extension = Path::new(name).extension().unwrap_or_default();
+            let found = if extensions.iter().any(|ext| extension == *ext) {
+                content::safe_content_path(assets, name).map(|_| ())
+            } else {
+                Err(Error::new(ErrorKind::InvalidInput, "unexpected file type"))
+            };
+            match found {
+                Ok(()) => Some(format!("/assets/{}", name)),
+                Err(err) => {
+                    tracing::warn!("ignoring asset {}: {}", asset, err);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Parses a `YYYY-MM-DD` date from the start of a string (such as a slug or timestamp)
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    let date = value.get(..10)?;
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// Parses markdown as the post file `name` of a temporary directory
+    async fn parse(name: &str, markdown: &str) -> Result<Post> {
+        parse_with(name, markdown, &PostOptions::default()).await
+    }
+
+    async fn parse_with(name: &str, markdown: &str, options: &PostOptions) -> Result<Post> {
+        let dir = std::env::temp_dir().join(format!("post-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, markdown).unwrap();
+        let post = Post::from_file_with_options(&path.to_string_lossy(), options).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        post
+    }
+
+    #[test]
+    fn footers_are_markdown_or_a_markdown_file() {
+        let inline =
+            footer_html("Licensed under [CC BY 4.0](https://creativecommons.org/licenses/by/4.0/)");
+        assert_eq!(
+            inline,
+            "<p>Licensed under \
+             <a href=\"https://creativecommons.org/licenses/by/4.0/\">CC BY 4.0</a></p>\n"
+        );
+        let file = std::env::temp_dir().join(format!("footer-{}.md", std::process::id()));
+        std::fs::write(&file, "*All rights reserved*").unwrap();
+        let from_file = footer_html(&file.to_string_lossy());
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(from_file, "<p><em>All rights reserved</em></p>\n");
+    }
+
+    #[async_std::test]
+    async fn anchors_list_the_ids_of_the_rendered_content() {
+        let markdown = "---\ntitle: Anchors\n---\n\n## Getting Started\n\n\
+                        ## Custom {#custom}\n\n<span id=\"raw\" data-id=\"skip\">x</span>\n";
+        let post = parse("2022-06-26-anchors.md", markdown).await.unwrap();
+        assert!(post.has_anchor("getting-started"));
+        assert!(post.has_anchor("custom"));
+        assert!(post.has_anchor("raw"));
+        assert!(!post.has_anchor("skip"));
+        assert!(!post.has_anchor("Getting Started"));
+        assert!(json!(post)["anchors"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("getting-started")));
+    }
+
+    #[async_std::test]
+    async fn reading_time_is_estimated_unless_the_frontmatter_sets_it() {
+        let words = "word ".repeat(401);
+        let markdown = format!("---\ntitle: Long\n---\n\n{}\n", words);
+        let post = parse("2022-06-26-estimated.md", &markdown).await.unwrap();
+        assert_eq!(post.reading_time(), 3);
+
+        let post = parse("2022-06-26-short.md", "---\ntitle: Short\n---\n\nhi\n").await;
+        assert_eq!(post.unwrap().reading_time(), 1);
+
+        let markdown = format!("---\ntitle: Set\nreading_time: 10\n---\n\n{}\n", words);
+        let post = parse("2022-06-26-set.md", &markdown).await.unwrap();
+        assert_eq!(post.reading_time(), 10);
+        assert_eq!(json!(Summary::from(&post))["reading_time"], 10);
+
+        let markdown = format!("---\ntitle: Bad\nreading_time: soon\n---\n\n{}\n", words);
+        let post = parse("2022-06-26-bad.md", &markdown).await.unwrap();
+        assert_eq!(post.reading_time(), 3);
+    }
+
+    #[test]
+    fn unknown_keys_are_the_ones_neither_known_nor_configured() {
+        let vars = "title: Tide\nseries: rust\nmood: happy\ntags: [rust]\nlayout: wide";
+        assert_eq!(unknown_keys(vars), ["mood", "layout"]);
+        assert!(unknown_keys("title: Tide\ncover_image: tide.png").is_empty());
+    }
+
+    #[async_std::test]
+    async fn unknown_keys_are_tolerated_unless_strict() {
+        let markdown = "---\ntitle: Loose\nmood: happy\n---\n\ntext\n";
+        let post = parse("2022-06-26-loose.md", markdown).await.unwrap();
+        assert_eq!(post.title(), "Loose");
+    }
+
+    #[async_std::test]
+    async fn summaries_show_an_emoji_or_an_image_icon() {
+        let icons = [
+            ("emoji", "icon: 🦀", json!("🦀"), Value::Null),
+            (
+                "image",
+                "icon: /assets/tide.png",
+                Value::Null,
+                json!("/assets/tide.png"),
+            ),
+            (
+                "default",
+                "",
+                json!(CONFIG.with(|c| c.default_icon.clone())),
+                Value::Null,
+            ),
+        ];
+        for (name, frontmatter, icon, icon_src) in icons {
+            let markdown = format!("---\ntitle: Icon\n{}\n---\n\ntext\n", frontmatter);
+            let post = parse(&format!("2022-06-26-{}.md", name), &markdown)
+                .await
+                .unwrap();
+            let summary = json!(Summary::from(&post));
+            assert_eq!(summary["icon"], icon, "{}", name);
+            assert_eq!(summary["icon_src"], icon_src, "{}", name);
+        }
+    }
+
+    #[test]
+    fn toml_frontmatter_reads_like_yaml() {
+        let raw = "+++\n# a comment\ntitle = \"Tide: a server\"\ntags = [\"rust\", 'web']\n\
+                   [extra]\ndraft = false\n+++\nbody\n";
+        let (vars, content) = split_frontmatter(raw, false);
+        assert_eq!(content, "body\n");
+        let vars: Vec<_> = frontmatter_vars(vars.unwrap()).collect();
+        assert_eq!(
+            vars,
+            [
+                ("title", "Tide: a server"),
+                ("tags", "[\"rust\", 'web']"),
+                ("draft", "false"),
+            ]
+        );
+        assert_eq!(parse_list(vars[1].1), ["rust", "web"]);
+    }
+
+    #[async_std::test]
+    async fn toml_posts_parse_their_frontmatter() {
+        let markdown = "+++\ntitle = \"Tide\"\ntags = [\"rust\"]\n+++\n\ntext\n";
+        let post = parse("2022-06-26-toml.md", markdown).await.unwrap();
+        assert_eq!(post.title(), "Tide");
+        assert_eq!(post.tags(), ["rust"]);
+        assert_eq!(post.content().trim(), "<p>text</p>");
+    }
+
+    #[test]
+    fn post_assets_must_exist_with_an_expected_extension() {
+        let dir = std::env::temp_dir().join(format!("assets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["demo.css", "demo.js", "notes.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let styles = assets_in(
+            &dir,
+            "[demo.css, /assets/demo.css, missing.css, demo.js]",
+            &["css"],
+        );
+        let scripts = assets_in(&dir, "[assets/demo.js, notes.txt, ../demo.js]", &["js"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(styles, ["/assets/demo.css", "/assets/demo.css"]);
+        assert_eq!(scripts, ["/assets/demo.js"]);
+    }
+
+    #[test]
+    fn complexity_rates_the_share_of_code_against_prose() {
+        // 12 words of prose weigh as much as a line of code
+        assert_eq!(Complexity::compute(&[], 1000), None);
+        assert_eq!(Complexity::compute(&[1], 1200), Some(Complexity::Light));
+        assert_eq!(
+            Complexity::compute(&[10, 15], 1200),
+            Some(Complexity::Moderate)
+        );
+        assert_eq!(
+            Complexity::compute(&[50, 50], 1200),
+            Some(Complexity::Heavy)
+        );
+        assert_eq!(Complexity::compute(&[3], 0), Some(Complexity::Heavy));
+    }
+
+    #[async_std::test]
+    async fn code_blocks_are_counted_with_their_lines() {
+        let markdown = "---\ntitle: Code\n---\n\nSome `inline` code.\n\n\
+                        ```rust\nfn main() {\n}\n```\n\n    indented\n";
+        assert_eq!(markdown::code_block_lines(markdown), [2, 1]);
+        let post = parse("2022-06-26-code.md", markdown).await.unwrap();
+        assert_eq!(post.code_blocks, 2);
+        assert_eq!(post.complexity, Some(Complexity::Heavy));
+    }
+
+    fn shout(markdown: &str) -> Result<String> {
+        Ok(markdown.replace("hello", "HELLO"))
+    }
+
+    fn exclaim(markdown: &str) -> Result<String> {
+        Ok(markdown.replace("HELLO", "HELLO!"))
+    }
+
+    fn refuse(_: &str) -> Result<String> {
+        Err(Error::new(ErrorKind::InvalidData, "unbalanced shortcode"))
+    }
+
+    #[async_std::test]
+    async fn preprocessors_run_in_order_over_the_markdown() {
+        let markdown = "---\ntitle: Hi\n---\n\nhello *world*\n";
+        let parse = |name: &'static str, preprocessors| async move {
+            let options = PostOptions {
+                preprocessors,
+                ..PostOptions::default()
+            };
+            parse_with(name, markdown, &options).await
+        };
+        let post = parse("2022-06-26-in-order.md", vec![shout, exclaim]).await;
+        assert_eq!(post.unwrap().content.trim(), "<p>HELLO! <em>world</em></p>");
+        let post = parse("2022-06-26-reversed.md", vec![exclaim, shout]).await;
+        assert_eq!(post.unwrap().content.trim(), "<p>HELLO <em>world</em></p>");
+
+        let post = parse("2022-06-26-refused.md", vec![shout, refuse]).await;
+        let err = post.err().expect("the failing preprocessor fails the post");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .ends_with("2022-06-26-refused.md: unbalanced shortcode"));
+    }
+
+    fn lazy_images(html: &str) -> Result<String> {
+        Ok(html.replace("<img ", "<img loading=\"lazy\" "))
+    }
+
+    #[async_std::test]
+    async fn postprocessors_rewrite_the_html_of_markdown_and_html_posts() {
+        let options = PostOptions {
+            postprocessors: vec![lazy_images],
+            ..PostOptions::default()
+        };
+        let markdown = "---\ntitle: Pic\n---\n\n![tide](/assets/tide.png)\n";
+        let post = parse_with("2022-06-26-pic.md", markdown, &options).await;
+        assert!(post
+            .unwrap()
+            .content
+            .contains("<img loading=\"lazy\" src=\"/assets/tide.png\" alt=\"tide\" />"));
+        let html = "<!--\ntitle: Pic\n-->\n<img src=\"/assets/tide.png\">\n";
+        let post = parse_with("2022-06-26-pic.html", html, &options).await;
+        assert!(post
+            .unwrap()
+            .content
+            .contains("<img loading=\"lazy\" src=\"/assets/tide.png\">"));
+
+        let failing = PostOptions {
+            postprocessors: vec![refuse],
+            ..PostOptions::default()
+        };
+        let post = parse_with("2022-06-26-failing.md", markdown, &failing).await;
+        assert_eq!(
+            post.err().map(|err| err.kind()),
+            Some(ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn only_asset_images_move_to_the_cdn() {
+        let html = "<p><img src=\"/assets/a.png\" alt=\"a\"> \
+                    <img src=\"https://elsewhere.example/b.png\"> \
+                    <a href=\"/assets/c.zip\"> src=\"/assets/d.png\"</a></p>";
+        assert_eq!(
+            cdn_images(html, "https://cdn.example"),
+            "<p><img src=\"https://cdn.example/assets/a.png\" alt=\"a\"> \
+             <img src=\"https://elsewhere.example/b.png\"> \
+             <a href=\"/assets/c.zip\"> src=\"/assets/d.png\"</a></p>"
+        );
+        assert_eq!(image_cdn(html).unwrap(), html);
+    }
+
+    #[async_std::test]
+    async fn missing_descriptions_are_derived_from_the_text() {
+        let words = "word ".repeat(100);
+        let markdown = format!("---\ntitle: Long\n---\n\n{}\n", words);
+        let post = parse("2022-06-26-long.md", &markdown).await.unwrap();
+        let (length, ellipsis) = CONFIG.with(|c| (c.description_length, c.ellipsis.clone()));
+        assert!(post.description().ends_with(&ellipsis));
+        assert!(post.description().chars().count() <= length);
+        assert!(post.description().starts_with("word word"));
+
+        let markdown = format!("---\ntitle: Long\ndescription: Given\n---\n\n{}\n", words);
+        let post = parse("2022-06-26-given.md", &markdown).await.unwrap();
+        assert_eq!(post.description(), "Given");
+    }
+
+    #[async_std::test]
+    async fn html_pages_keep_their_markup_verbatim() {
+        let html = "<!--\ntitle: Demo\nreading_time: 3\n-->\n\
+                    <div id=\"demo\"><p>*not markdown*</p></div>\n\
+                    <!-- more -->\n<script>run()</script>\n";
+        let post = parse("2022-06-26-demo.html", html).await.unwrap();
+        assert_eq!(post.title(), "Demo");
+        assert_eq!(post.reading_time, Some(3));
+        assert!(post
+            .content()
+            .contains("<div id=\"demo\"><p>*not markdown*</p></div>"));
+        assert!(post.content().contains("<script>run()</script>"));
+        assert_eq!(
+            post.excerpt(),
+            Some("\n<div id=\"demo\"><p>*not markdown*</p></div>\n")
+        );
+    }
+
+    #[async_std::test]
+    async fn updates_are_noted_past_the_threshold() {
+        let markdown = "---\ntitle: Later\nupdated: 2022-07-10\n---\n\nbody\n";
+        let later = parse("2022-06-26-later.md", markdown).await.unwrap();
+        assert_eq!(later.updated(), NaiveDate::from_ymd_opt(2022, 7, 10));
+        assert!(later.was_updated);
+        assert_eq!(json!(later)["was_updated"], json!(true));
+
+        let markdown = "---\ntitle: Next day\nupdated: 2022-06-27\n---\n\nbody\n";
+        let next_day = parse("2022-06-26-next-day.md", markdown).await.unwrap();
+        assert!(!next_day.was_updated);
+    }
+
+    #[test]
+    fn posts_dated_today_are_new_in_listings() {
+        let today = Utc::today().naive_utc();
+        let post = Post {
+            date: Some(today),
+            freshness: Freshness::compute(Some(today), None, today, 7),
+            ..Post::default()
+        };
+        assert_eq!(post.freshness, Some(Freshness::New));
+        assert_eq!(json!(Summary::from(&post))["freshness"], json!("New"));
+    }
+
+    #[async_std::test]
+    async fn prefixed_heading_ids_keep_excerpts_of_several_posts_apart() {
+        let dir = std::env::temp_dir().join(format!("prefixed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = PostOptions {
+            prefix_heading_ids: true,
+            ..PostOptions::default()
+        };
+        let mut posts = Vec::new();
+        for name in ["2024-01-01-first.md", "2024-01-02-second.md"] {
+            let path = dir.join(name);
+            std::fs::write(&path, "## Setup\n\nintro\n\n<!-- more -->\n\nrest\n").unwrap();
+            let post = Post::from_file_with_options(&path.to_string_lossy(), &options).await;
+            posts.push(post.unwrap());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (first, second) = (&posts[0], &posts[1]);
+        assert!(first.content.contains("<h2 id=\"2024-01-01-first-setup\">"));
+        assert_eq!(first.anchors, vec!["2024-01-01-first-setup"]);
+        let first_excerpt = first.excerpt.as_deref().unwrap();
+        let second_excerpt = second.excerpt.as_deref().unwrap();
+        assert!(first_excerpt.contains("id=\"2024-01-01-first-setup\""));
+        assert!(second_excerpt.contains("id=\"2024-01-02-second-setup\""));
+    }
 }
@@ -0,0 +1,71 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+use crate::{admin::constant_time_eq, config::CONFIG};
+
+/// Token unlocking the preview of a single draft: an hmac of its slug keyed by the secret
+pub fn token(secret: &str, slug: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("hmac takes any key");
+    mac.update(slug.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Whether a `?preview=` token unlocks the draft with the given slug. Drafts can't be
+/// previewed at all without a `PREVIEW_SECRET`.
+pub fn allows(slug: &str, preview: Option<&str>) -> bool {
+    CONFIG.with(|c| allows_with(c.preview_secret.as_deref(), slug, preview))
+}
+
+/// Whether a `?preview=` token unlocks the draft with the given slug under a secret
+fn allows_with(secret: Option<&str>, slug: &str, preview: Option<&str>) -> bool {
+    match (secret, preview) {
+        (Some(secret), Some(preview)) => {
+            constant_time_eq(preview.as_bytes(), token(secret, slug).as_bytes())
+        }
+        _ => false,
+    }
+}
+
+/// The `preview` query parameter of a request url
+pub fn query(url: &tide::http::Url) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == "preview")
+        .map(|(_, value)| value.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_only_unlock_their_own_draft() {
+        let token = token("secret", "2022-06-26-tide");
+        assert_eq!(token.len(), 64);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let allows = |secret, slug, preview| allows_with(secret, slug, preview);
+        assert!(allows(Some("secret"), "2022-06-26-tide", Some(&token)));
+        assert!(!allows(Some("secret"), "2022-06-27-other", Some(&token)));
+        assert!(!allows(Some("rotated"), "2022-06-26-tide", Some(&token)));
+        assert!(!allows(
+            Some("secret"),
+            "2022-06-26-tide",
+            Some(&token[1..])
+        ));
+        assert!(!allows(Some("secret"), "2022-06-26-tide", None));
+        assert!(!allows(None, "2022-06-26-tide", Some(&token)));
+    }
+
+    #[test]
+    fn the_token_comes_from_the_preview_query() {
+        let url =
+            tide::http::Url::parse("http://localhost/2022/06/26/tide?a=1&preview=ab%20c").unwrap();
+        assert_eq!(query(&url).as_deref(), Some("ab c"));
+        let url = tide::http::Url::parse("http://localhost/2022/06/26/tide").unwrap();
+        assert_eq!(query(&url), None);
+    }
+}
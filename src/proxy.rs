@@ -0,0 +1,230 @@
+use std::net::{IpAddr, SocketAddr};
+use tide::Request;
+
+use crate::config::CONFIG;
+
+thread_local! {
+    /// The parsed `TRUSTED_PROXIES`
+    static TRUSTED: Vec<Cidr> = CONFIG.with(|c| parse_cidrs(&c.trusted_proxies));
+}
+
+/// An address range such as `10.0.0.0/8`, or a single address
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (value.parse().ok()?, None),
+        };
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = prefix.unwrap_or(bits);
+        if prefix > bits {
+            return None;
+        }
+        Some(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let mask = |bits: u32| u128::MAX.checked_shl(bits - self.prefix).unwrap_or(0);
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = mask(32) as u32;
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses a list of cidrs, skipping invalid entries with a warning
+pub fn parse_cidrs(values: &[String]) -> Vec<Cidr> {
+    values
+        .iter()
+        .filter_map(|value| {
+            let cidr = Cidr::parse(value);
+            if cidr.is_none() {
+                tracing::warn!("ignoring invalid ip or cidr {}", value);
+            }
+            cidr
+        })
+        .collect()
+}
+
+/// Address of the peer the request came from, the last proxy when there are any
+fn peer_ip(req: &Request<()>) -> Option<IpAddr> {
+    Some(req.peer_addr()?.parse::<SocketAddr>().ok()?.ip())
+}
+
+/// Scheme the client used. When the peer is one of `TRUSTED_PROXIES` it's read from the
+/// `proto=` of the `Forwarded` header, or `X-Forwarded-Proto` without one, otherwise it's
+/// the connection's own. Only `http` and `https` are accepted.
+pub fn scheme(req: &Request<()>) -> String {
+    let own = String::from(req.url().scheme());
+    let trusted = match peer_ip(req) {
+        Some(peer) => TRUSTED.with(|trusted| trusted.iter().any(|c| c.contains(peer))),
+        None => false,
+    };
+    if !trusted {
+        return own;
+    }
+    // the first element or value was set by the proxy facing the client
+    let forwarded = match req.header("Forwarded") {
+        Some(forwarded) => forwarded
+            .iter()
+            .flat_map(|value| value.as_str().split(','))
+            .next()
+            .and_then(|element| forwarded_param(element, "proto")),
+        None => req
+            .header("X-Forwarded-Proto")
+            .and_then(|h| h.as_str().split(',').next()),
+    };
+    match forwarded.map(|proto| proto.trim().to_lowercase()) {
+        Some(proto) if proto == "http" || proto == "https" => proto,
+        _ => own,
+    }
+}
+
+/// Address of the client. When the peer is one of `TRUSTED_PROXIES` the address is read
+/// from the `Forwarded` header, or `X-Forwarded-For` without one, otherwise it's the peer's.
+pub fn client_ip(req: &Request<()>) -> Option<IpAddr> {
+    let peer = peer_ip(req)?;
+    TRUSTED.with(|trusted| {
+        let is_trusted = |ip: IpAddr| trusted.iter().any(|c| c.contains(ip));
+        if !is_trusted(peer) {
+            return Some(peer);
+        }
+        let hops: Vec<&str> = match req.header("Forwarded") {
+            Some(forwarded) => forwarded
+                .iter()
+                .flat_map(|value| value.as_str().split(','))
+                .filter_map(forwarded_for)
+                .collect(),
+            None => req
+                .header("X-Forwarded-For")
+                .map(|h| h.iter().flat_map(|v| v.as_str().split(',')).collect())
+                .unwrap_or_default(),
+        };
+        // walk back through the proxies to the first address we don't trust
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            match hop.trim().parse() {
+                Ok(ip) if is_trusted(client) => client = ip,
+                _ => break,
+            }
+        }
+        Some(client)
+    })
+}
+
+/// Value of a parameter of a `Forwarded` element, such as the `https` of `proto=https`
+fn forwarded_param<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim().trim_matches('"'))
+        } else {
+            None
+        }
+    })
+}
+
+/// Address of the `for=` parameter of a `Forwarded` element, such as `for=192.0.2.60` or
+/// `for="[2001:db8::17]:4711"`
+fn forwarded_for(element: &str) -> Option<&str> {
+    let value = forwarded_param(element, "for")?;
+    if let Some(v6) = value.strip_prefix('[') {
+        return v6.split(']').next();
+    }
+    // an ipv4 address may carry a port, a bare ipv6 address has several colons
+    match value.split_once(':') {
+        Some((v4, port)) if !port.contains(':') => Some(v4),
+        _ => Some(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Url};
+
+    fn cidr(value: &str) -> Cidr {
+        Cidr::parse(value).unwrap()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let url = Url::parse("http://localhost/").unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        req.set_peer_addr(Some(peer));
+        for (name, value) in headers {
+            req.append_header(*name, *value);
+        }
+        req.into()
+    }
+
+    #[test]
+    fn cidrs_match_their_range() {
+        assert!(cidr("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(cidr("192.168.1.7").contains(ip("192.168.1.7")));
+        assert!(!cidr("192.168.1.7").contains(ip("192.168.1.8")));
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(cidr("2001:db8::/32").contains(ip("2001:db8:1::1")));
+        assert!(!cidr("2001:db8::/32").contains(ip("2001:db9::1")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+    }
+
+    #[test]
+    fn invalid_cidrs_are_skipped() {
+        for value in ["10.0.0.0/33", "::/129", "10.0.0.0/x", "localhost", ""] {
+            assert!(Cidr::parse(value).is_none(), "{}", value);
+        }
+        let values = vec![String::from("nope"), String::from("10.0.0.0/8")];
+        assert_eq!(parse_cidrs(&values).len(), 1);
+    }
+
+    #[test]
+    fn forwarded_addresses_drop_ports_and_brackets() {
+        assert_eq!(forwarded_for("for=192.0.2.60"), Some("192.0.2.60"));
+        assert_eq!(forwarded_for("for=192.0.2.60:8080"), Some("192.0.2.60"));
+        assert_eq!(
+            forwarded_for("proto=https; For=\"[2001:db8::17]:4711\""),
+            Some("2001:db8::17")
+        );
+        assert_eq!(forwarded_for("for=2001:db8::1"), Some("2001:db8::1"));
+        assert_eq!(forwarded_for("proto=https"), None);
+    }
+
+    #[test]
+    fn clients_are_read_through_trusted_proxies_only() {
+        // read when this test's thread first loads its config
+        std::env::set_var("TRUSTED_PROXIES", "10.0.0.0/8");
+        let forwarded_for = [("X-Forwarded-For", "198.51.100.1, 203.0.113.7, 10.0.0.2")];
+        let req = request("10.0.0.1:1", &forwarded_for);
+        assert_eq!(client_ip(&req), Some(ip("203.0.113.7")));
+        let req = request("203.0.113.9:1", &forwarded_for);
+        assert_eq!(client_ip(&req), Some(ip("203.0.113.9")));
+
+        let forwarded = [
+            ("Forwarded", "for=203.0.113.7;proto=https"),
+            ("X-Forwarded-For", "198.51.100.1"),
+        ];
+        let req = request("10.0.0.1:1", &forwarded);
+        assert_eq!(client_ip(&req), Some(ip("203.0.113.7")));
+        assert_eq!(scheme(&req), "https");
+    }
+}
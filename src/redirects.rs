@@ -0,0 +1,235 @@
+use serde::Deserialize;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use tide::http::headers::LOCATION;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+use crate::post::Post;
+use crate::routes::{normalize_path, PAGES};
+
+/// A single redirect from an old path to its new location
+#[derive(Deserialize)]
+struct Redirect {
+    from: String,
+    to: String,
+    #[serde(default = "default_status")]
+    status: u16,
+}
+
+fn default_status() -> u16 {
+    301
+}
+
+/// Redirects old paths to their new location before routing
+#[derive(Default)]
+pub struct Redirects {
+    redirects: HashMap<String, (String, StatusCode)>,
+}
+
+impl Redirects {
+    /// Loads redirects from a json file such as
+    /// `[{ "from": "/2019/08/14/old", "to": "/2019/08/14/new", "status": 301 }]`.
+    /// The status defaults to 301 and may be 301 or 302. Redirects that loop back on
+    /// themselves are logged and dropped.
+    pub fn from_file(path: &str) -> Self {
+        let list: Vec<Redirect> = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                tracing::warn!("ignoring invalid redirects {}: {}", path, err);
+                Vec::new()
+            }),
+            Err(_) => {
+                tracing::debug!("no redirects found at {}", path);
+                Vec::new()
+            }
+        };
+
+        let mut redirects = HashMap::new();
+        for redirect in list {
+            let status = match redirect.status {
+                301 => StatusCode::MovedPermanently,
+                302 => StatusCode::Found,
+                other => {
+                    tracing::warn!("ignoring redirect {} with status {}", redirect.from, other);
+                    continue;
+                }
+            };
+            redirects.insert(normalize_path(&redirect.from), (redirect.to, status));
+        }
+
+        let looping: Vec<String> = redirects
+            .keys()
+            .filter(|from| Redirects::loops(&redirects, from))
+            .cloned()
+            .collect();
+        for from in looping {
+            tracing::error!("dropping redirect {} which loops back on itself", from);
+            redirects.remove(&from);
+        }
+        Redirects { redirects }
+    }
+
+    /// Adds a permanent redirect from each post's `aliases` to its url. An alias claimed by
+    /// several posts, or shadowing a page or post url, is logged and dropped; one already declared
+    /// in the redirects file keeps the file's target. The post url is the whole location, so a
+    /// `#section` of the old link is kept by the browser and still lands on its heading.
+    pub fn with_aliases(mut self, posts: &[Post]) -> Self {
+        let mut urls: HashSet<String> = posts.iter().map(|p| format!("/{}", p.url())).collect();
+        urls.extend(PAGES.iter().map(|(path, _)| String::from(*path)));
+        let mut claimed: HashMap<String, Vec<String>> = HashMap::new();
+        for post in posts {
+            for alias in post.aliases() {
+                let alias = normalize_path(&format!("/{}", alias.trim_start_matches('/')));
+                claimed
+                    .entry(alias)
+                    .or_default()
+                    .push(format!("/{}", post.url()));
+            }
+        }
+
+        for (alias, targets) in claimed {
+            if targets.len() > 1 {
+                tracing::error!("dropping alias {} claimed by {:?}", alias, targets);
+            } else if urls.contains(&alias) {
+                tracing::error!(
+                    "dropping alias {} of {}, a page lives there",
+                    alias,
+                    targets[0]
+                );
+            } else if alias != targets[0] {
+                match self.redirects.entry(alias) {
+                    Entry::Occupied(entry) => tracing::warn!(
+                        "alias {} is already a redirect, keeping the redirect",
+                        entry.key()
+                    ),
+                    Entry::Vacant(entry) => {
+                        let to = targets.into_iter().next().unwrap_or_default();
+                        entry.insert((to, StatusCode::MovedPermanently));
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    /// Follows the chain of redirects starting at `from`, reporting whether it revisits a path
+    fn loops(redirects: &HashMap<String, (String, StatusCode)>, from: &str) -> bool {
+        let mut seen = HashSet::new();
+        let mut path = String::from(from);
+        while let Some((to, _)) = redirects.get(&path) {
+            if !seen.insert(path) {
+                return true;
+            }
+            path = normalize_path(to.split(['?', '#']).next().unwrap_or_default());
+        }
+        false
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for Redirects {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let (to, status) = match self.redirects.get(&normalize_path(req.url().path())) {
+            Some(redirect) => redirect,
+            None => return Ok(next.run(req).await),
+        };
+
+        let location = location(to, req.url().query());
+        tracing::info!("redirecting {} to {}", req.url().path(), location);
+        let mut res = Response::new(*status);
+        res.insert_header(LOCATION, location);
+        Ok(res)
+    }
+}
+
+/// Location of a redirect to `to`, carrying over the query of the request. The fragment of
+/// the request never reaches the server, browsers keep it across the redirect as long as the
+/// location declares none, so the location is otherwise left as is: a fragment of `to` stays
+/// last, after the query, and nothing is appended to the path.
+fn location(to: &str, query: Option<&str>) -> String {
+    let (target, fragment) = match to.split_once('#') {
+        Some((target, fragment)) => (target, Some(fragment)),
+        None => (to, None),
+    };
+    let mut location = String::from(target);
+    if let Some(query) = query.filter(|query| !query.is_empty()) {
+        location.push(if location.contains('?') { '&' } else { '?' });
+        location.push_str(query);
+    }
+    if let Some(fragment) = fragment {
+        location.push('#');
+        location.push_str(fragment);
+    }
+    location
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(redirects: Redirects, path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        app.with(redirects);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn locations_keep_the_query_before_the_fragment() {
+        assert_eq!(location("/new", None), "/new");
+        assert_eq!(location("/new", Some("")), "/new");
+        assert_eq!(location("/new", Some("a=1")), "/new?a=1");
+        assert_eq!(location("/new?x=1#part", Some("a=1")), "/new?x=1&a=1#part");
+    }
+
+    #[async_std::test]
+    async fn redirects_file_sets_the_status_and_drops_loops() {
+        let file = std::env::temp_dir().join(format!("redirects-{}.json", std::process::id()));
+        std::fs::write(
+            &file,
+            r#"[
+                { "from": "/2019/08/14/old", "to": "/2019/08/14/new" },
+                { "from": "/moved", "to": "/2019/08/14/new", "status": 302 },
+                { "from": "/teapot", "to": "/", "status": 418 },
+                { "from": "/a", "to": "/b" },
+                { "from": "/b", "to": "/a" }
+            ]"#,
+        )
+        .unwrap();
+        let redirects = || Redirects::from_file(&file.to_string_lossy());
+
+        let res = get(redirects(), "/2019/08/14/old").await;
+        assert_eq!(res.status(), StatusCode::MovedPermanently);
+        assert_eq!(res[LOCATION], "/2019/08/14/new");
+        let res = get(redirects(), "/moved").await;
+        assert_eq!(res.status(), StatusCode::Found);
+        for path in ["/teapot", "/a", "/b"] {
+            assert_eq!(get(redirects(), path).await.status(), StatusCode::NotFound);
+        }
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[async_std::test]
+    async fn aliases_redirect_permanently_to_the_bare_post_url() {
+        let dir = std::env::temp_dir().join(format!("aliases-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("2022-06-26-tide.md");
+        std::fs::write(
+            &file,
+            "---\ntitle: Tide\naliases: [tide, about]\n---\n\ntext\n",
+        )
+        .unwrap();
+        let post = Post::from_file(&file.to_string_lossy()).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let redirects = || Redirects::default().with_aliases(std::slice::from_ref(&post));
+        let res = get(redirects(), "/tide?ref=feed").await;
+        assert_eq!(res.status(), StatusCode::MovedPermanently);
+        assert_eq!(res[LOCATION], "/2022/06/26/tide?ref=feed");
+        // an alias shadowing a page is dropped
+        let res = get(redirects(), "/about").await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+}
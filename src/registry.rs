@@ -1,24 +1,167 @@
 use handlebars::Handlebars;
 use serde::Serialize;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::fs::DirEntry;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tide::Body;
-use tide::Response;
+use tide::{Response, StatusCode};
+
+use crate::{bots, config::CONFIG, feed, icons::IconHelper, locale, manifest, theme};
+
+/// Id of the element holding the content of every page, the target of the skip link
+pub const CONTENT_ID: &str = "main-content";
+
+/// Produces a base context object merged into every render.
+type ContextProvider = Arc<dyn Fn() -> Value + Send + Sync>;
 
 #[derive(Clone)]
 pub struct State {
     registry: Handlebars<'static>,
+    providers: Vec<ContextProvider>,
 }
 
 impl State {
     pub fn default() -> Self {
+        let dir = CONFIG.with(|c| c.template_dir.clone());
+        // main checks the templates load before serving, so this only fails if they were removed since
+        State::from_dir(&dir).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Loads the templates of a built client directory along with any theme sets, failing
+    /// with an error naming the directory when it is missing or lacks `post.html`
+    pub fn from_dir(dir: &str) -> std::io::Result<Self> {
+        State::from_dirs(dir, &CONFIG.with(|c| c.static_dir.clone()))
+    }
+
+    /// Like `from_dir`, with the stylesheets taken from `static_dir`, the directory
+    /// served at `/static`, rather than from the templates
+    pub fn from_dirs(dir: &str, static_dir: &str) -> std::io::Result<Self> {
         let mut state = State {
             registry: Handlebars::new(),
+            providers: Vec::new(),
         };
-        state.template("post.html", "client/dist/post.html");
         state
+            .registry
+            .set_strict_mode(CONFIG.with(|c| c.strict_templates));
+        state.templates(dir)?;
+        let icons = IconHelper::from_dir(&Path::new(dir).join("icons"));
+        state.registry.register_helper("icon", Box::new(icons));
+        if !state.has_template("post.html") {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "no post.html template in {}, build the client or point TEMPLATE_DIR at its output",
+                    dir
+                ),
+            ));
+        }
+        for theme in &theme::THEMES {
+            let themes_dir = format!("{}/themes/{}", dir, theme.name);
+            if Path::new(&themes_dir).is_dir() {
+                state.themed_templates(theme.name, &themes_dir)?;
+            }
+        }
+        let styles = stylesheets(static_dir, CONFIG.with(|c| c.inline_css_limit));
+        state.context_provider(move || json!({ "styles": styles }));
+        state.context_provider(|| json!({ "version": env!("CARGO_PKG_VERSION") }));
+        let feeds = json!([feed::site_link()]);
+        state.context_provider(move || json!({ "feeds": feeds }));
+        state.context_provider(
+            || json!({ "content_id": CONTENT_ID, "skip_link": CONFIG.with(|c| c.skip_link) }),
+        );
+        state.context_provider(|| json!({ "livereload": CONFIG.with(|c| c.is_dev()) }));
+        CONFIG.with(|c| manifest::context_provider(&mut state, c));
+        state.context_provider(|| {
+            let theme = theme::current();
+            json!({ "theme": theme.name, "highlight_theme": theme.highlight })
+        });
+        state.context_provider(|| json!({ "is_bot": bots::current() }));
+        state.context_provider(|| {
+            let locale = locale::current().unwrap_or_else(|| CONFIG.with(|c| c.locale.clone()));
+            json!({ "locale": locale, "dir": CONFIG.with(|c| c.text_dir.clone()) })
+        });
+        Ok(state)
+    }
+
+    pub fn template(&mut self, name: &str, path: &str) -> std::io::Result<()> {
+        self.registry
+            .register_template_file(name, path)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}: {}", path, err)))
+    }
+
+    /// Registers every `.html` and `.xml` file in a directory as a template named after the file
+    pub fn templates(&mut self, dir: &str) -> std::io::Result<()> {
+        for entry in read_templates_dir(dir)? {
+            let path = entry.path();
+            let ext = path.extension();
+            if ext == Some(OsStr::new("html")) || ext == Some(OsStr::new("xml")) {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                tracing::debug!("registering template {}", name);
+                self.template(&name, &path.to_string_lossy())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers the templates of a theme set, which override same-named templates
+    /// while that theme is selected
+    pub fn themed_templates(&mut self, theme: &str, dir: &str) -> std::io::Result<()> {
+        for entry in read_templates_dir(dir)? {
+            let path = entry.path();
+            if path.extension() == Some(OsStr::new("html")) {
+                let name = format!("{}/{}", theme, entry.file_name().to_string_lossy());
+                tracing::debug!("registering template {}", name);
+                self.template(&name, &path.to_string_lossy())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a template has been registered under the given name
+    pub fn has_template(&self, name: &str) -> bool {
+        self.registry.has_template(name)
+    }
+
+    /// Registers a provider of site-wide variables (build version, nav links, etc).
+    /// Providers are merged in registration order, followed by the per-page data.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use serde_json::json;
+    /// use crate::registry::State;
+    ///
+    /// let mut state = State::default();
+    /// state.context_provider(|| json!({ "year": 2024 }));
+    /// ```
+    pub fn context_provider<F>(&mut self, provider: F)
+    where
+        F: Fn() -> Value + Send + Sync + 'static,
+    {
+        self.providers.push(Arc::new(provider));
     }
 
-    pub fn template(&mut self, name: &str, path: &str) {
-        self.registry.register_template_file(name, path).unwrap();
+    /// Builds the full render context from the registered providers and the page data.
+    fn context<T: Serialize>(&self, data: &T) -> Value {
+        let mut context = json!({});
+        let values = self
+            .providers
+            .iter()
+            .map(|provider| provider())
+            .chain(std::iter::once(json!(data)));
+        for value in values {
+            match value {
+                Value::Object(map) => context.as_object_mut().unwrap().extend(map),
+                Value::Null => {}
+                other => tracing::warn!("ignoring non-object render context {}", other),
+            }
+        }
+        context
     }
 
     /// Renders a simple response given serialized data and a template name.
@@ -54,13 +197,246 @@ impl State {
     /// state.render_body(response, "post.html", &json!({ "content": "hello world" }));
     /// ```
     pub fn render_body<T: Serialize>(&self, response: &mut Response, name: &str, data: &T) {
-        let body = self.registry.render(name, data).unwrap();
+        let themed = format!("{}/{}", theme::current().name, name);
+        let name = if self.has_template(&themed) {
+            &themed
+        } else {
+            name
+        };
+        let body = match self.registry.render(name, &self.context(data)) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::error!("unable to render {}: {}", name, err);
+                response.set_status(StatusCode::InternalServerError);
+                // developers get to see why, visitors the error page
+                if CONFIG.with(|c| c.is_dev()) {
+                    response.set_body(format!("unable to render {}: {}", name, err));
+                }
+                return;
+            }
+        };
+        // rendered in full, so the body has a known length and is sent with a
+        // Content-Length rather than chunked
         let mut body = Body::from_string(body);
         body.set_mime("text/html");
         response.set_body(body);
     }
 }
 
+/// Stylesheets of the static directory, sorted by name. Those no larger than `limit`
+/// bytes are read in to be inlined, the rest are linked from `/static`.
+fn stylesheets(dir: &str, limit: u64) -> Vec<Value> {
+    let mut paths: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension() == Some(OsStr::new("css")))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let size = path.metadata().map(|m| m.len()).unwrap_or(u64::MAX);
+            let href = || json!({ "href": format!("/static/{}", name) });
+            if size > limit {
+                return href();
+            }
+            match std::fs::read_to_string(path) {
+                Ok(css) => json!({ "inline": css }),
+                Err(_) => href(),
+            }
+        })
+        .collect()
+}
+
+/// Lists a template directory, naming it in the error when it can't be read
+fn read_templates_dir(dir: &str) -> std::io::Result<impl Iterator<Item = DirEntry>> {
+    let entries = std::fs::read_dir(dir).map_err(|err| {
+        Error::new(
+            err.kind(),
+            format!("unable to read template directory {}: {}", dir, err),
+        )
+    })?;
+    Ok(entries.flatten())
+}
+
+/// Bumped by `reload`, each thread reloads its templates the next time it renders
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
 thread_local! {
-    pub static REGISTRY: State = State::default();
+    static REGISTRY: RefCell<(usize, State)> = RefCell::new((0, State::default()));
+}
+
+/// Runs `f` with this thread's template registry, reloading it first when a reload was requested
+pub fn with<F, R>(f: F) -> R
+where
+    F: FnOnce(&State) -> R,
+{
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        let generation = GENERATION.load(Ordering::SeqCst);
+        if registry.0 != generation {
+            let dir = CONFIG.with(|c| c.template_dir.clone());
+            match State::from_dir(&dir) {
+                Ok(state) => registry.1 = state,
+                Err(err) => tracing::error!("keeping previous templates: {}", err),
+            }
+            registry.0 = generation;
+        }
+        f(&registry.1)
+    })
+}
+
+/// Number of reloads so far, anything derived from the templates is stale once it changes
+pub fn generation() -> usize {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// Reloads the templates from disk on every thread, after checking they still load
+pub fn reload() -> std::io::Result<usize> {
+    State::from_dir(&CONFIG.with(|c| c.template_dir.clone()))?;
+    Ok(GENERATION.fetch_add(1, Ordering::SeqCst) + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::{Post, Summary};
+
+    #[test]
+    fn providers_merge_in_order_before_the_page_data() {
+        let mut state = State::default();
+        state.context_provider(|| json!({ "year": 2024, "nav": "first" }));
+        state.context_provider(|| json!({ "nav": "second", "title": "Site" }));
+        state.context_provider(|| Value::Null);
+        let context = state.context(&json!({ "title": "Page" }));
+        assert_eq!(context["year"], json!(2024));
+        assert_eq!(context["nav"], json!("second"));
+        assert_eq!(context["title"], json!("Page"));
+    }
+
+    #[test]
+    fn provided_variables_render() {
+        let mut state = State::default();
+        state.context_provider(|| json!({ "year": 2024 }));
+        state
+            .registry
+            .register_template_string("year.html", "<p>{{year}}</p>")
+            .unwrap();
+        let rendered = state
+            .registry
+            .render("year.html", &state.context(&json!({})));
+        assert_eq!(rendered.unwrap(), "<p>2024</p>");
+    }
+
+    #[test]
+    fn context_exposes_the_site_feed() {
+        let state = State::default();
+        let context = state.context(&json!({}));
+        let href = CONFIG.with(|c| c.absolute_url("/feed.xml"));
+        assert_eq!(context["feeds"][0]["href"], json!(href));
+    }
+
+    fn strict() -> State {
+        let mut state = State::default();
+        state.registry.set_strict_mode(true);
+        state
+    }
+
+    #[test]
+    fn strict_mode_fails_renders_of_missing_variables() {
+        let mut state = strict();
+        state
+            .registry
+            .register_template_string("missing.html", "<p>{{missing}}</p>")
+            .unwrap();
+        let mut res = Response::new(StatusCode::Ok);
+        state.render_body(&mut res, "missing.html", &json!({}));
+        assert_eq!(res.status(), StatusCode::InternalServerError);
+    }
+
+    #[async_std::test]
+    async fn pages_render_in_strict_mode() {
+        let state = strict();
+        let post = Post::from_file("content/posts/2022-06-26-tide.md")
+            .await
+            .unwrap();
+        let pages = [
+            json!(post),
+            json!({ "content": "<p>gone</p>" }),
+            json!({ "title": "Notes", "posts": [Summary::from(&post)] }),
+            json!({ "title": "Notes", "featured_posts": [Summary::from(&post)] }),
+        ];
+        for page in &pages {
+            let rendered = state.registry.render("post.html", &state.context(page));
+            assert!(rendered.is_ok(), "{:?}", rendered);
+        }
+    }
+
+    #[test]
+    fn missing_templates_fail_naming_the_directory() {
+        let dir = std::env::temp_dir().join(format!("no-templates-{}", std::process::id()));
+        let name = dir.to_string_lossy().into_owned();
+        let missing = State::from_dir(&name).err().unwrap();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+        assert!(missing.to_string().contains(&name));
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("other.html"), "<p></p>").unwrap();
+        let without_post = State::from_dir(&name).err().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(without_post.kind(), ErrorKind::NotFound);
+        assert!(without_post.to_string().contains("no post.html template"));
+        assert!(without_post.to_string().contains(&name));
+    }
+
+    #[test]
+    fn only_small_stylesheets_are_inlined() {
+        let dir = std::env::temp_dir().join(format!("stylesheets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.css"), "p{}").unwrap();
+        std::fs::write(dir.join("b.css"), "body{margin:0}").unwrap();
+        std::fs::write(dir.join("c.txt"), "").unwrap();
+
+        let styles = stylesheets(&dir.to_string_lossy(), 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            styles,
+            vec![
+                json!({ "inline": "p{}" }),
+                json!({ "href": "/static/b.css" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn stylesheets_are_linked_from_the_static_dir() {
+        let dir = std::env::temp_dir().join(format!("static-dir-{}", std::process::id()));
+        let templates = dir.join("templates");
+        let static_dir = dir.join("static");
+        std::fs::create_dir_all(&templates).unwrap();
+        std::fs::create_dir_all(&static_dir).unwrap();
+        std::fs::write(
+            templates.join("post.html"),
+            "<head>{{#each styles}}<link href=\"{{href}}\" rel=\"stylesheet\">{{/each}}</head>",
+        )
+        .unwrap();
+        std::fs::write(templates.join("stale.css"), "p{}").unwrap();
+        std::fs::write(static_dir.join("app.css"), "body{margin:0}").unwrap();
+
+        let state = State::from_dirs(&templates.to_string_lossy(), &static_dir.to_string_lossy());
+        std::fs::remove_dir_all(&dir).unwrap();
+        let state = state.unwrap();
+        let rendered = state
+            .registry
+            .render("post.html", &state.context(&json!({})))
+            .unwrap();
+        assert_eq!(
+            rendered,
+            "<head><link href=\"/static/app.css\" rel=\"stylesheet\"></head>"
+        );
+    }
 }
@@ -1,21 +1,190 @@
-use serde_json::json;
-use tide::{Request, Response, StatusCode};
+use async_lock::{Semaphore, SemaphoreGuard};
+use async_std::future;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::Duration;
+use tide::http::headers::{ALLOW, CACHE_CONTROL, RETRY_AFTER};
+use tide::http::{Method, Url};
+use tide::{Redirect, Request, Response, StatusCode};
 use tracing::Instrument;
 
-use crate::{post::Post, registry::REGISTRY};
+use crate::config::{Config, HomePage, NotFoundBehavior, CONFIG};
+use crate::{
+    api, bots, comments, content, csp, feed, headers, og,
+    post::{frontmatter_vars, split_frontmatter, Post, Summary},
+    preview, proxy, registry, shortlinks, sitemap, tags, views,
+};
 
+/// Top level pages and the content file each one renders
+pub const PAGES: [(&str, &str); 3] = [
+    ("/", "index.md"),
+    ("/about", "about.md"),
+    ("/todo", "todo.md"),
+];
+
+/// Registers the application routes. HEAD requests fall back to the GET handlers,
+/// async-h1 then writes the same status and headers without the body. OPTIONS requests
+/// are answered with the allowed methods.
 pub fn configure(app: &mut tide::Server<()>) {
-    app.at("/").get(index);
-    app.at("/health_check").get(health_check);
-    app.at("/about").get(about);
-    app.at("/todo").get(todo);
-    app.at("/:year/:month/:day/:id").get(get_post);
-    app.at("/:year/:month/:day/:id/").get(get_post);
+    app.at("/").get(index).options(options(READ));
+    app.at("/health_check")
+        .get(health_check)
+        .options(options(READ));
+    app.at("/version").get(version).options(options(READ));
+    app.at("/about").get(about).options(options(READ));
+    app.at("/todo").get(todo).options(options(READ));
+    app.at("/api/posts").get(api::posts).options(options(READ));
+    app.at("/api/posts/batch")
+        .post(api::batch)
+        .options(options("POST"));
+    app.at("/api/tags").get(api::tags).options(options(READ));
+    app.at("/api/posts/:year/:month/:day/:id")
+        .get(api::post)
+        .options(options(READ));
+    app.at(csp::REPORT_PATH)
+        .post(csp::report)
+        .options(options("POST"));
+    app.at("/feed.xml").get(feed::site).options(options(READ));
+    app.at("/og/:file").get(og::image).options(options(READ));
+    app.at("/sitemap.xml")
+        .get(sitemap::sitemap)
+        .options(options(READ));
+    app.at("/n/:id")
+        .get(shortlinks::redirect)
+        .options(options(READ));
+    app.at("/tags/:tag").get(tags::page).options(options(READ));
+    app.at("/tags/:tag/feed.xml")
+        .get(feed::tag)
+        .options(options(READ));
+    app.at("/:year/:month/:day/:id")
+        .get(get_post)
+        .options(options(READ));
+    app.at("/:year/:month/:day/:id/")
+        .get(get_post)
+        .options(options(READ));
+}
+
+/// Methods of the routes that only serve content
+pub const READ: &str = "GET, HEAD";
+
+/// Answers `OPTIONS` requests with the methods a route allows
+pub fn options(allow: &'static str) -> impl tide::Endpoint<()> {
+    move |_req: Request<()>| async move {
+        let mut res = Response::new(StatusCode::NoContent);
+        res.insert_header(ALLOW, allow);
+        Ok(res)
+    }
+}
+
+/// Strips the trailing slash so `/a/b/` and `/a/b` are looked up the same way
+pub fn normalize_path(path: &str) -> String {
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        String::from("/")
+    } else {
+        String::from(path)
+    }
+}
+
+/// Path of a file within the content directory, a 404 when it is missing or escapes it
+fn content_path(name: &str) -> tide::Result<String> {
+    let content_dir = CONFIG.with(|c| c.content_dir.clone());
+    match content::safe_content_path(Path::new(&content_dir), name) {
+        Ok(path) => Ok(path.to_string_lossy().into_owned()),
+        Err(err) => {
+            tracing::warn!("refusing content path {}: {}", name, err);
+            Err(tide::Error::new(StatusCode::NotFound, err))
+        }
+    }
+}
+
+/// Bounds how many markdown renders run at once, see `RENDER_CONCURRENCY`
+static RENDERS: Lazy<Semaphore> =
+    Lazy::new(|| Semaphore::new(CONFIG.with(|c| c.render_concurrency)));
+
+/// Template of the stripped-down reader variant of posts, served for `?reader=1`
+const READER_TEMPLATE: &str = "reader.html";
+
+/// Queues behind the running renders, giving up once the wait gets longer than `wait`
+async fn render_slot(renders: &Semaphore, wait: Duration) -> Option<SemaphoreGuard<'_>> {
+    future::timeout(wait, renders.acquire()).await.ok()
 }
 
 async fn render_markdown(url: &str) -> tide::Result<Response> {
+    render_markdown_with(url, json!({}), None, false).await
+}
+
+/// Renders a markdown file with extra template data merged over the post. Drafts
+/// answer as not found unless `preview` holds their token. The `reader` variant renders
+/// with `reader.html` instead of the post's template.
+async fn render_markdown_with(
+    url: &str,
+    extra: Value,
+    preview: Option<&str>,
+    reader: bool,
+) -> tide::Result<Response> {
+    let wait = CONFIG.with(|c| c.render_queue_timeout);
+    let _permit = match render_slot(&RENDERS, wait).await {
+        Some(permit) => permit,
+        None => {
+            tracing::warn!("no render slot within {:?}, refusing {}", wait, url);
+            let mut res = Response::new(StatusCode::ServiceUnavailable);
+            res.insert_header(RETRY_AFTER, "1");
+            return Ok(res);
+        }
+    };
     let post = Post::from_file(url).await?;
-    REGISTRY.with(|c| c.render("post.html", &json!(post)))
+    if post.draft() && !preview::allows(post.slug(), preview) {
+        return Ok(not_found(post.slug()));
+    }
+    if post.expired() {
+        return Ok(Response::new(CONFIG.with(|c| c.expired_status)));
+    }
+    let mut res = registry::with(|c| {
+        let template = match post.template() {
+            _ if reader && c.has_template(READER_TEMPLATE) => READER_TEMPLATE,
+            _ if reader => {
+                tracing::warn!(
+                    "template {} is not registered, using post.html",
+                    READER_TEMPLATE
+                );
+                "post.html"
+            }
+            Some(name) if c.has_template(name) => name,
+            Some(name) => {
+                tracing::warn!("template {} is not registered, using post.html", name);
+                "post.html"
+            }
+            None => "post.html",
+        };
+        let mut data = json!(post);
+        if let (Some(data), Value::Object(extra)) = (data.as_object_mut(), extra) {
+            data.extend(extra);
+        }
+        let path = PAGES
+            .iter()
+            .find(|(_, file)| Path::new(url).ends_with(file))
+            .map(|(path, _)| String::from(*path))
+            .unwrap_or_else(|| String::from(post.url()));
+        data["edit_url"] = json!(CONFIG.with(|c| c.edit_url(Path::new(url))));
+        data["canonical"] = match post.canonical() {
+            Some(canonical) => json!(canonical),
+            None => json!(CONFIG.with(|c| c.absolute_url(&path))),
+        };
+        if post.draft() {
+            // previews are shared privately, keep them out of search engines
+            data["noindex"] = json!(true);
+            data["comments_enabled"] = json!(false);
+        }
+        c.render(template, &data)
+    })?;
+    headers::validators(&mut res, post.modified()).await?;
+    if post.draft() {
+        res.insert_header(CACHE_CONTROL, "private, no-store");
+    }
+    Ok(res)
 }
 
 // Returns a simple 200 OK response
@@ -23,32 +192,479 @@ async fn health_check(_req: Request<()>) -> tide::Result<Response> {
     Ok(Response::new(StatusCode::Ok))
 }
 
-/// Renders the index markdown root file
+/// Reports the running build: crate version, git commit and build time
+async fn version(req: Request<()>) -> tide::Result<Response> {
+    let built_at = env!("BUILT_AT").parse().unwrap_or_default();
+    let built_at = NaiveDateTime::from_timestamp(built_at, 0);
+    api::json(
+        &req,
+        &json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "commit": env!("GIT_COMMIT"),
+            "built_at": DateTime::<Utc>::from_utc(built_at, Utc).to_rfc3339(),
+        }),
+    )
+}
+
+/// Renders the home page according to `HOME_PAGE`: the index markdown root file listing
+/// the newest posts flagged `featured: true`, or the list of the first `HOME_POST_COUNT`
+/// posts in the `LISTING_SORT` order
 async fn index(_req: Request<()>) -> tide::Result<Response> {
-    render_markdown("content/index.md").await
+    let (posts_dir, limit, home_page, count) = CONFIG.with(|c| {
+        (
+            c.posts_dir(),
+            c.featured_limit,
+            c.home_page,
+            c.home_post_count,
+        )
+    });
+    let mut posts = content::posts(&posts_dir).await;
+    if home_page == HomePage::Listing {
+        content::sort(&mut posts, CONFIG.with(|c| c.listing_sort));
+        return listing(&posts, count).await;
+    }
+    let mut data = home_metadata().await;
+    data["featured_posts"] = json!(featured(&posts, limit));
+    render_markdown_with(&content_path("index.md")?, data, None, false).await
+}
+
+/// Renders the home page as the list of the first `count` posts
+async fn listing(posts: &[Post], count: usize) -> tide::Result<Response> {
+    let summaries: Vec<Summary> = posts.iter().take(count).map(Summary::from).collect();
+    let mut data = home_metadata().await;
+    data["canonical"] = json!(CONFIG.with(|c| c.absolute_url("/")));
+    data["posts"] = json!(summaries);
+    registry::with(|c| c.render("post.html", &data))
+}
+
+/// Summaries of the first `limit` posts flagged `featured: true`, newest first as posts come
+fn featured(posts: &[Post], limit: usize) -> Vec<Summary<'_>> {
+    posts
+        .iter()
+        .filter(|post| post.featured())
+        .take(limit)
+        .map(Summary::from)
+        .collect()
+}
+
+/// Title and description of the home page, from the frontmatter of `index.md` rather than
+/// its text, with `SITE_NAME` standing in for the ones it leaves empty
+async fn home_metadata() -> Value {
+    let raw = match content_path("index.md") {
+        Ok(path) => async_std::fs::read_to_string(path)
+            .await
+            .unwrap_or_default(),
+        Err(_) => String::new(),
+    };
+    let site_name = CONFIG.with(|c| c.site_name.clone());
+    let (mut title, mut description) = (site_name.as_str(), site_name.as_str());
+    if let (Some(vars), _) = split_frontmatter(&raw, false) {
+        for (k, v) in frontmatter_vars(vars).filter(|(_, v)| !v.is_empty()) {
+            match k {
+                "title" => title = v,
+                "description" => description = v,
+                _ => {}
+            }
+        }
+    }
+    json!({ "title": title, "description": description })
 }
 
 /// Renders the about markdown root file
 async fn about(_req: Request<()>) -> tide::Result<Response> {
-    render_markdown("content/about.md").await
+    render_markdown(&content_path("about.md")?).await
 }
 
 /// Renders the todo markdown root file
 async fn todo(_req: Request<()>) -> tide::Result<Response> {
-    render_markdown("content/todo.md").await
+    render_markdown(&content_path("todo.md")?).await
+}
+
+/// Answers a post url that doesn't resolve according to `NOT_FOUND_BEHAVIOR`
+fn not_found(slug: &str) -> Response {
+    CONFIG.with(|c| not_found_with(c, slug))
+}
+
+/// Answers a post url that doesn't resolve, redirecting to the `SEARCH_URL` with the slug
+/// as the `q` query when configured to
+fn not_found_with(config: &Config, slug: &str) -> Response {
+    let search_url = match (config.not_found_behavior, &config.search_url) {
+        (NotFoundBehavior::RedirectSearch, Some(url)) => url,
+        _ => return Response::new(StatusCode::NotFound),
+    };
+    match Url::parse(search_url) {
+        Ok(mut search) => {
+            search.query_pairs_mut().append_pair("q", slug);
+            Redirect::new(search).into()
+        }
+        Err(err) => {
+            tracing::warn!("invalid SEARCH_URL {}: {}", search_url, err);
+            Response::new(StatusCode::NotFound)
+        }
+    }
+}
+
+/// Whether a request url asks for the reader variant of a post with `?reader=1`
+fn reader_query(url: &Url) -> bool {
+    url.query_pairs()
+        .any(|(key, value)| key == "reader" && value == "1")
+}
+
+/// Slug of the post addressed by the `:year/:month/:day/:id` params, rejecting with a 400
+/// params that aren't digits of the right length or, for the id, a plain slug
+pub fn post_slug(req: &Request<()>) -> tide::Result<String> {
+    let digits = |name: &str, len: usize| -> tide::Result<String> {
+        let value = req.param(name)?;
+        if value.len() == len && value.chars().all(|c| c.is_ascii_digit()) {
+            Ok(String::from(value))
+        } else {
+            Err(tide::Error::from_str(
+                StatusCode::BadRequest,
+                format!("invalid {} {:?}", name, value),
+            ))
+        }
+    };
+    let (year, month, day) = (digits("year", 4)?, digits("month", 2)?, digits("day", 2)?);
+    let id = req.param("id")?;
+    let is_slug = !id.is_empty()
+        && id.len() <= 128
+        && !id.starts_with('-')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_slug {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("invalid id {:?}", id),
+        ));
+    }
+    Ok(format!("{}-{}-{}-{}", year, month, day, id))
 }
 
 /// Renders a post based on the given path
 async fn get_post(req: Request<()>) -> tide::Result<Response> {
-    // open up file based on request (fallback to not found)
-    let url = format!(
-        "content/posts/{}-{}-{}-{}.md",
-        req.param("year")?,
-        req.param("month")?,
-        req.param("day")?,
-        req.param("id")?
-    );
+    // find the file based on request, including linked post directories (fallback to not found)
+    let slug = post_slug(&req)?;
+    let posts_dir = CONFIG.with(|c| c.posts_dir());
+    let path = match content::find(&posts_dir, &slug).await {
+        Some(path) => path,
+        None => return Ok(not_found(&slug)),
+    };
+    // the url may differ in case from the file, comments and views go by the file
+    let slug = path.file_stem().unwrap_or_default().to_string_lossy();
 
     let span = tracing::info_span!("rendering markdown");
-    render_markdown(&url).instrument(span).await
+    let preview = preview::query(req.url());
+    let reader = reader_query(req.url());
+    let extra = json!({
+        "comments": comments::list(&slug).await,
+        "comments_enabled": CONFIG.with(|c| c.comments_dir.is_some()),
+        "og_image": CONFIG.with(|c| c.absolute_url(&og::path(&slug))),
+        "views": views::count(&slug),
+    });
+    let res = render_markdown_with(&path.to_string_lossy(), extra, preview.as_deref(), reader)
+        .instrument(span)
+        .await?;
+    // only views of the published post count, crawlers and HEAD requests aren't readers
+    if res.status() == StatusCode::Ok && req.method() == Method::Get && !bots::current() {
+        views::record(proxy::client_ip(&req), &slug);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::headers::LOCATION;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        configure(&mut app);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn rendered_posts_have_a_known_length() {
+        let mut res = get("/2022/06/26/tide").await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let len = res.len().expect("a Content-Length rather than chunked");
+        assert_eq!(res.body_string().await.unwrap().len(), len);
+    }
+
+    #[async_std::test]
+    async fn renders_wait_for_a_slot_until_the_timeout() {
+        let renders = Semaphore::new(1);
+        let wait = Duration::from_millis(20);
+        let held = render_slot(&renders, wait).await.expect("a free slot");
+        assert!(render_slot(&renders, wait).await.is_none());
+
+        let (slot, _) = futures_util::join!(render_slot(&renders, Duration::from_secs(5)), async {
+            async_std::task::sleep(wait).await;
+            drop(held);
+        });
+        assert!(slot.is_some());
+    }
+
+    #[async_std::test]
+    async fn the_newest_featured_posts_are_listed_up_to_the_limit() {
+        let dir = std::env::temp_dir().join(format!("featured-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (day, flag) in [(1, "true"), (2, "false"), (3, "true"), (4, "true")] {
+            let markdown = format!("---\ntitle: Day {}\nfeatured: {}\n---\n\ntext\n", day, flag);
+            std::fs::write(dir.join(format!("2022-06-0{}-day.md", day)), markdown).unwrap();
+        }
+        let posts = content::posts(&dir.to_string_lossy()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let titles = |limit| -> Vec<String> {
+            featured(&posts, limit)
+                .iter()
+                .map(|summary| json!(summary)["title"].as_str().unwrap().to_owned())
+                .collect()
+        };
+        assert_eq!(titles(2), ["Day 4", "Day 3"]);
+        assert_eq!(titles(5), ["Day 4", "Day 3", "Day 1"]);
+        assert!(titles(0).is_empty());
+    }
+
+    #[async_std::test]
+    async fn drafts_are_not_found_without_a_preview_token() {
+        let dir = std::env::temp_dir().join(format!("drafts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("2022-06-26-draft.md");
+        std::fs::write(&file, "---\ntitle: Draft\ndraft: true\n---\n\ntext\n").unwrap();
+        let file = file.to_string_lossy();
+        let res = render_markdown_with(&file, json!({}), Some("guess"), false).await;
+        let listed = content::posts(&dir.to_string_lossy()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(res.unwrap().status(), StatusCode::NotFound);
+        assert!(listed.is_empty());
+    }
+
+    #[async_std::test]
+    async fn the_listing_home_page_links_the_posts() {
+        let posts = content::posts(&CONFIG.with(|c| c.posts_dir())).await;
+        let mut res = listing(&posts, posts.len()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        let body = res.take_body().into_string().await.unwrap();
+        let canonical = CONFIG.with(|c| c.absolute_url("/"));
+        assert!(body.contains(&format!("<link rel=\"canonical\" href=\"{}\">", canonical)));
+        for post in &posts {
+            assert!(
+                body.contains(&format!("<a href=\"/{}\">", post.url())),
+                "{}",
+                post.url()
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn the_listing_home_page_stops_at_the_post_count() {
+        let posts = content::posts(&CONFIG.with(|c| c.posts_dir())).await;
+        let mut res = listing(&posts, 2).await.unwrap();
+        let body = res.take_body().into_string().await.unwrap();
+        let linked = |post: &Post| body.contains(&format!("<a href=\"/{}\">", post.url()));
+        assert!(posts[..2].iter().all(linked));
+        assert!(!posts[2..].iter().any(linked));
+    }
+
+    #[async_std::test]
+    async fn options_requests_list_the_allowed_methods() {
+        let mut app = tide::new();
+        configure(&mut app);
+        for (path, allow) in [
+            ("/", READ),
+            ("/2022/06/26/tide", READ),
+            ("/api/posts/batch", "POST"),
+        ] {
+            let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+            let res: HttpResponse = app
+                .respond(HttpRequest::new(Method::Options, url))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::NoContent, "{}", path);
+            assert_eq!(res[ALLOW].as_str(), allow, "{}", path);
+        }
+    }
+
+    #[async_std::test]
+    async fn the_canonical_url_is_the_frontmatter_one_or_the_post_url() {
+        let dir = std::env::temp_dir().join(format!("canonical-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut bodies = Vec::new();
+        for (name, frontmatter) in [("moved", "canonical: https://dev.to/tide"), ("own", "")] {
+            let file = dir.join(format!("2022-06-26-{}.md", name));
+            let markdown = format!("---\ntitle: {}\n{}\n---\n\ntext\n", name, frontmatter);
+            std::fs::write(&file, markdown).unwrap();
+            let mut res = render_markdown(&file.to_string_lossy()).await.unwrap();
+            bodies.push(res.take_body().into_string().await.unwrap());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let link = |href: &str| format!("<link rel=\"canonical\" href=\"{}\">", href);
+        assert!(bodies[0].contains(&link("https://dev.to/tide")));
+        let own = CONFIG.with(|c| c.absolute_url("2022/06/26/own"));
+        assert!(bodies[1].contains(&link(&own)));
+    }
+
+    #[async_std::test]
+    async fn pages_take_their_lang_and_dir_from_the_frontmatter() {
+        let dir = std::env::temp_dir().join(format!("lang-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pages = [
+            ("arabic", "lang: ar\ndir: rtl"),
+            ("sideways", "dir: sideways"),
+        ];
+        let mut bodies = Vec::new();
+        for (name, frontmatter) in pages {
+            let file = dir.join(format!("2022-06-26-{}.md", name));
+            let markdown = format!("---\ntitle: {}\n{}\n---\n\ntext\n", name, frontmatter);
+            std::fs::write(&file, markdown).unwrap();
+            let mut res = render_markdown(&file.to_string_lossy()).await.unwrap();
+            bodies.push(res.take_body().into_string().await.unwrap());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(bodies[0].contains("<html lang=\"ar\" dir=\"rtl\""));
+        let default = CONFIG.with(|c| c.text_dir.clone());
+        assert!(bodies[1].contains(&format!(" dir=\"{}\"", default)));
+    }
+
+    #[async_std::test]
+    async fn the_home_page_is_titled_from_the_index_frontmatter() {
+        let site_name = CONFIG.with(|c| c.site_name.clone());
+        // index.md sets a title but leaves its description empty
+        assert_eq!(
+            home_metadata().await,
+            json!({ "title": "Tom's Notes", "description": site_name })
+        );
+        let body = get("/").await.body_string().await.unwrap();
+        assert!(body.contains("<title>Tom&#x27;s Notes</title>"));
+    }
+
+    #[async_std::test]
+    async fn pages_skip_to_a_main_labelled_with_the_first_heading() {
+        let body = get("/2022/06/26/tide").await.body_string().await.unwrap();
+        let id = registry::CONTENT_ID;
+        assert!(body.contains(&format!("<a href=\"#{}\" class=\"skip-link\">", id)));
+        let main = format!("<main id=\"{}\" tabindex=\"-1\" aria-label=\"", id);
+        assert!(body.contains(&main), "{}", main);
+    }
+
+    #[async_std::test]
+    async fn expired_posts_are_gone_and_unlisted() {
+        let dir = std::env::temp_dir().join(format!("expires-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let today = chrono::Utc::today().naive_utc();
+        let mut statuses = Vec::new();
+        for (name, expires) in [("expired", today.pred()), ("lasting", today)] {
+            let file = dir.join(format!("2022-06-26-{}.md", name));
+            let markdown = format!("---\ntitle: {}\nexpires: {}\n---\n\ntext\n", name, expires);
+            std::fs::write(&file, markdown).unwrap();
+            let res = render_markdown(&file.to_string_lossy()).await.unwrap();
+            statuses.push(res.status());
+        }
+        let listed = content::posts(&dir.to_string_lossy()).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(statuses, [StatusCode::Gone, StatusCode::Ok]);
+        let titles: Vec<&str> = listed.iter().map(Post::title).collect();
+        assert_eq!(titles, ["lasting"]);
+    }
+
+    #[test]
+    fn only_reader_1_asks_for_the_reader_variant() {
+        let asks = |query: &str| {
+            let url = Url::parse("http://localhost/2022/06/26/tide").unwrap();
+            reader_query(&url.join(query).unwrap())
+        };
+        assert!(asks("?reader=1"));
+        assert!(asks("?theme=dark&reader=1"));
+        assert!(!asks("?reader=0"));
+        assert!(!asks("?reader"));
+        assert!(!asks(""));
+    }
+
+    #[async_std::test]
+    async fn the_reader_variant_drops_the_site_chrome() {
+        let mut full = get("/2022/06/26/tide").await;
+        let mut reader = get("/2022/06/26/tide?reader=1").await;
+        assert_eq!(reader.status(), StatusCode::Ok);
+        let (full, reader) = (
+            full.body_string().await.unwrap(),
+            reader.body_string().await.unwrap(),
+        );
+        assert!(full.contains("aria-label=\"Site\""));
+        assert!(!reader.contains("aria-label=\"Site\""));
+        assert!(reader.contains("<a href=\"/2022/06/26/tide\">Full post</a>"));
+        assert!(reader.contains("<a href=\"https://rust-lang.org\">rust-lang</a>"));
+    }
+
+    #[async_std::test]
+    async fn posts_render_with_their_template_if_registered() {
+        let dir = std::env::temp_dir().join(format!("templates-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut bodies = Vec::new();
+        for (name, template) in [("reader", "reader.html"), ("missing", "missing.html")] {
+            let file = dir.join(format!("2022-06-26-{}.md", name));
+            let markdown = format!(
+                "---\ntitle: {}\ntemplate: {}\n---\n\ntext\n",
+                name, template
+            );
+            std::fs::write(&file, markdown).unwrap();
+            let mut res = render_markdown(&file.to_string_lossy()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::Ok);
+            bodies.push(res.take_body().into_string().await.unwrap());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // reader.html has no site navigation, the post.html fallback does
+        assert!(!bodies[0].contains("<nav aria-label=\"Site\""));
+        assert!(bodies[1].contains("<nav aria-label=\"Site\""));
+    }
+
+    #[test]
+    fn unresolved_posts_redirect_to_the_search_url() {
+        let config = Config {
+            not_found_behavior: NotFoundBehavior::RedirectSearch,
+            search_url: Some(String::from("https://search.example/?site=nyxtom.dev")),
+            ..Config::default()
+        };
+        let res = not_found_with(&config, "2022-06-26-tide");
+        assert_eq!(res.status(), StatusCode::Found);
+        assert_eq!(
+            res[LOCATION],
+            "https://search.example/?site=nyxtom.dev&q=2022-06-26-tide"
+        );
+
+        let res = not_found_with(&Config::default(), "2022-06-26-tide");
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn content_paths_outside_the_content_directory_are_not_found() {
+        assert!(content_path("about.md").is_ok());
+        let err = content_path("../Cargo.toml").unwrap_err();
+        assert_eq!(err.status(), StatusCode::NotFound);
+    }
+
+    #[async_std::test]
+    async fn malformed_post_params_are_bad_requests() {
+        for path in [
+            "/20x2/06/26/tide",
+            "/2022/6/26/tide",
+            "/2022/06/261/tide",
+            "/2022/06/26/..tide",
+            "/2022/06/26/-tide",
+            "/2022/06/26/ti%2Fde",
+            "/api/posts/2022/06/26/ti%2E%2Ede",
+        ] {
+            assert_eq!(get(path).await.status(), StatusCode::BadRequest, "{}", path);
+        }
+        assert_eq!(get("/2022/06/26/nope").await.status(), StatusCode::NotFound);
+    }
 }
@@ -0,0 +1,112 @@
+use async_lock::Mutex;
+use async_std::fs;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use tide::http::headers::LOCATION;
+use tide::{Request, Response, StatusCode};
+
+use crate::config::CONFIG;
+use crate::content;
+use crate::post::Post;
+
+/// Serializes the read-modify-write of the id map
+static WRITE: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Reads the id of each post from `POST_IDS_PATH`, a json object such as
+/// `{ "2019-08-14-noise": 1 }`. A missing file holds no ids, an invalid one is an error
+/// rather than overwritten.
+async fn load(path: &str) -> std::io::Result<BTreeMap<String, u64>> {
+    let raw = match fs::read_to_string(path).await {
+        Ok(raw) => raw,
+        Err(_) => return Ok(BTreeMap::new()),
+    };
+    serde_json::from_str(&raw).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid post ids {}: {}", path, err),
+        )
+    })
+}
+
+/// Gives each post without an id the next one, oldest posts first and undated ones last,
+/// and saves the map so ids stay the same across restarts. Ids are never reused, even once
+/// their post is gone.
+pub async fn assign(posts: &[Post]) -> std::io::Result<BTreeMap<String, u64>> {
+    let path = CONFIG.with(|c| c.post_ids_path.clone());
+    let _guard = WRITE.lock().await;
+    let mut ids = load(&path).await?;
+    let mut new: Vec<&Post> = posts
+        .iter()
+        .filter(|post| !ids.contains_key(post.slug()))
+        .collect();
+    if new.is_empty() {
+        return Ok(ids);
+    }
+    new.sort_by(|a, b| match (a.date(), b.date()) {
+        (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.slug().cmp(b.slug())),
+        (x, y) => y.cmp(&x),
+    });
+    let mut next = ids.values().max().copied().unwrap_or_default();
+    for post in new {
+        next += 1;
+        tracing::info!("assigning id {} to {}", next, post.slug());
+        ids.insert(String::from(post.slug()), next);
+    }
+    fs::write(&path, serde_json::to_vec_pretty(&ids)?).await?;
+    Ok(ids)
+}
+
+/// Redirects (301) `/n/:id` to the url of the post with that id, 404-ing for unknown ids.
+/// Ids are only read here, they are assigned to new posts at startup.
+pub async fn redirect(req: Request<()>) -> tide::Result<Response> {
+    let id: u64 = match req.param("id")?.parse() {
+        Ok(id) => id,
+        Err(_) => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    let (posts_dir, path) = CONFIG.with(|c| (c.posts_dir(), c.post_ids_path.clone()));
+    let ids = load(&path).await?;
+    let posts = content::posts(&posts_dir).await;
+    let post = posts.iter().find(|post| ids.get(post.slug()) == Some(&id));
+    match post {
+        Some(post) => {
+            let mut res = Response::new(StatusCode::MovedPermanently);
+            res.insert_header(LOCATION, format!("/{}", post.url()));
+            Ok(res)
+        }
+        None => Ok(Response::new(StatusCode::NotFound)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        app.at("/n/:id").get(redirect);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn redirects_only_read_the_ids() {
+        let path = std::env::temp_dir().join(format!("ids-{}.json", std::process::id()));
+        let ids = r#"{ "2022-06-26-tide": 7 }"#;
+        std::fs::write(&path, ids).unwrap();
+        std::env::set_var("POST_IDS_PATH", &path);
+
+        let found = get("/n/7").await;
+        let unknown = get("/n/8").await;
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(found.status(), StatusCode::MovedPermanently);
+        assert_eq!(found[LOCATION], "/2022/06/26/tide");
+        assert_eq!(unknown.status(), StatusCode::NotFound);
+        assert_eq!(saved, ids);
+    }
+}
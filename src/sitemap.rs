@@ -0,0 +1,94 @@
+use serde::Serialize;
+use serde_json::json;
+use tide::{Request, Response};
+
+use crate::{config::CONFIG, content, post::Post, registry, routes::PAGES};
+
+/// A page listed in the sitemap
+#[derive(Serialize)]
+struct Url {
+    loc: String,
+    lastmod: Option<String>,
+}
+
+impl From<&Post> for Url {
+    fn from(post: &Post) -> Self {
+        Url {
+            loc: CONFIG.with(|c| c.absolute_url(post.url())),
+            lastmod: post
+                .updated()
+                .or_else(|| post.date())
+                .map(|d| d.to_string()),
+        }
+    }
+}
+
+/// Renders the sitemap of the pages and posts, leaving out the `noindex` ones
+pub async fn sitemap(_req: Request<()>) -> tide::Result<Response> {
+    let (content_dir, posts_dir) = CONFIG.with(|c| (c.content_dir.clone(), c.posts_dir()));
+    let urls = urls(&content_dir, &posts_dir).await;
+    let mut res = registry::with(|c| c.render("sitemap.xml", &json!({ "urls": urls })))?;
+    res.set_content_type("application/xml");
+    Ok(res)
+}
+
+/// Urls of the pages of `content_dir` and the posts of `posts_dir` that aren't `noindex`
+async fn urls(content_dir: &str, posts_dir: &str) -> Vec<Url> {
+    let mut urls = Vec::new();
+    for (path, file) in PAGES {
+        if let Ok(page) = Post::from_file(&format!("{}/{}", content_dir, file)).await {
+            if !page.noindex() {
+                urls.push(Url {
+                    loc: CONFIG.with(|c| c.absolute_url(path)),
+                    lastmod: None,
+                });
+            }
+        }
+    }
+    let posts = content::posts(posts_dir).await;
+    urls.extend(posts.iter().filter(|post| !post.noindex()).map(Url::from));
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn noindex_pages_and_posts_are_left_out() {
+        let dir = std::env::temp_dir().join(format!("sitemap-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("posts")).unwrap();
+        let files = [
+            ("about.md", "title: About\nnoindex: true"),
+            ("todo.md", "title: Todo"),
+            ("posts/2022-06-26-hidden.md", "title: Hidden\nnoindex: true"),
+            (
+                "posts/2022-06-27-listed.md",
+                "title: Listed\nupdated: 2022-07-01",
+            ),
+        ];
+        for (file, frontmatter) in files {
+            let markdown = format!("---\n{}\n---\n\ntext\n", frontmatter);
+            std::fs::write(dir.join(file), markdown).unwrap();
+        }
+        let content_dir = dir.to_string_lossy();
+        let urls = urls(&content_dir, &format!("{}/posts", content_dir)).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let urls: Vec<_> = urls
+            .iter()
+            .map(|url| (url.loc.as_str(), url.lastmod.as_deref()))
+            .collect();
+        let absolute_url = |path| CONFIG.with(|c| c.absolute_url(path));
+        assert_eq!(
+            urls,
+            [
+                (absolute_url("/todo").as_str(), None),
+                (
+                    absolute_url("2022/06/27/listed").as_str(),
+                    Some("2022-07-01")
+                ),
+            ]
+        );
+    }
+}
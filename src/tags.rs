@@ -0,0 +1,140 @@
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use tide::{Request, Response, StatusCode};
+
+use crate::config::CONFIG;
+use crate::markdown::{escape_html, slugify};
+use crate::post::{frontmatter_vars, unquote, Summary};
+use crate::{content, feed, registry};
+
+/// Intro copy of a tag page, from a table of `TAGS_PATH`
+#[derive(Serialize, Default)]
+struct TagInfo {
+    title: Option<String>,
+    description: Option<String>,
+}
+
+/// Reads the tag tables of `TAGS_PATH`, such as
+/// `[rust]` followed by `title = "Rust"` and `description = "Notes on rust"` lines.
+/// A missing file configures no tags.
+async fn load() -> HashMap<String, TagInfo> {
+    let path = CONFIG.with(|c| c.tags_path.clone());
+    match async_std::fs::read_to_string(&path).await {
+        Ok(raw) => parse(&raw),
+        Err(_) => {
+            tracing::debug!("no tag descriptions found at {}", path);
+            HashMap::new()
+        }
+    }
+}
+
+fn parse(raw: &str) -> HashMap<String, TagInfo> {
+    let mut tables: Vec<(String, String)> = Vec::new();
+    for line in raw.lines() {
+        let trimmed = line.trim();
+        match trimmed.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            Some(name) => tables.push((slugify(unquote(name.trim())), String::new())),
+            None => match tables.last_mut() {
+                Some((_, body)) => {
+                    body.push_str(line);
+                    body.push('\n');
+                }
+                None if !trimmed.is_empty() && !trimmed.starts_with('#') => {
+                    tracing::warn!("ignoring tag description line outside a table: {}", line);
+                }
+                None => {}
+            },
+        }
+    }
+    tables
+        .into_iter()
+        .map(|(slug, body)| {
+            let mut info = TagInfo::default();
+            for (k, v) in frontmatter_vars(&body) {
+                match k {
+                    "title" => info.title = Some(String::from(v)),
+                    "description" => info.description = Some(String::from(v)),
+                    _ => tracing::warn!("ignoring unknown key {} of tag {}", k, slug),
+                }
+            }
+            (slug, info)
+        })
+        .collect()
+}
+
+/// Renders the posts carrying a tag under its configured title and description,
+/// 404-ing for unknown tags. Tags without a table are titled with their slug.
+pub async fn page(req: Request<()>) -> tide::Result<Response> {
+    let tag = slugify(req.param("tag")?);
+    let (posts_dir, order) = CONFIG.with(|c| (c.posts_dir(), c.listing_sort));
+    let mut posts: Vec<_> = content::posts(&posts_dir)
+        .await
+        .into_iter()
+        .filter(|post| post.tags().iter().any(|t| slugify(t) == tag))
+        .collect();
+    if posts.is_empty() {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+    content::sort(&mut posts, order);
+    let summaries: Vec<Summary> = posts.iter().map(Summary::from).collect();
+
+    let info = load().await.remove(&tag).unwrap_or_default();
+    let title = info.title.unwrap_or_else(|| format!("#{}", tag));
+    let content = info
+        .description
+        .as_ref()
+        .map(|description| format!("<p>{}</p>", escape_html(description)));
+    let data = json!({
+        "title": title,
+        "description": info.description,
+        "content": content,
+        "canonical": CONFIG.with(|c| c.absolute_url(&format!("/tags/{}", tag))),
+        "posts": summaries,
+        "feeds": [feed::site_link(), feed::tag_link(&tag)],
+    });
+    registry::with(|c| c.render("post.html", &data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(path: &str) -> HttpResponse {
+        let mut app = tide::new();
+        app.at("/tags/:tag").get(page);
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn tag_tables_hold_a_title_and_description() {
+        let raw = "# tags\nstray = \"line\"\n[rust]\ntitle = \"Rust\"\n\
+                   description = \"Notes on rust\"\n\n\
+                   [\"Web Dev\"]\ntitle = \"The web\"\nicon = \"x\"\n";
+        let tags = parse(raw);
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags["rust"].title.as_deref(), Some("Rust"));
+        assert_eq!(tags["rust"].description.as_deref(), Some("Notes on rust"));
+        assert_eq!(tags["web-dev"].title.as_deref(), Some("The web"));
+        assert_eq!(tags["web-dev"].description, None);
+    }
+
+    #[async_std::test]
+    async fn tag_pages_list_the_tagged_posts() {
+        let mut res = get("/tags/Rust").await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let body = res.body_string().await.unwrap();
+        assert!(body.contains("<a href=\"/2022/06/26/tide\">"));
+        let canonical = CONFIG.with(|c| c.absolute_url("/tags/rust"));
+        assert!(body.contains(&format!("<link rel=\"canonical\" href=\"{}\">", canonical)));
+
+        assert_eq!(
+            get("/tags/no-such-tag").await.status(),
+            StatusCode::NotFound
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::cell::Cell;
+use tide::http::Cookie;
+use tide::{Middleware, Next, Request};
+
+use crate::headers;
+
+/// A selectable site theme and the highlight.js style it pairs with
+pub struct Theme {
+    pub name: &'static str,
+    pub highlight: &'static str,
+}
+
+/// Themes a request can select with `?theme=<name>`
+pub const THEMES: [Theme; 2] = [
+    Theme {
+        name: "light",
+        highlight: "nord",
+    },
+    Theme {
+        name: "dark",
+        highlight: "github-dark",
+    },
+];
+
+/// Cookie remembering the selected theme between requests
+const COOKIE: &str = "theme";
+
+async_std::task_local! {
+    /// Theme selected by the request currently being handled
+    static CURRENT: Cell<&'static Theme> = Cell::new(&THEMES[0]);
+}
+
+/// Looks up a registered theme by name
+pub fn find(name: &str) -> Option<&'static Theme> {
+    THEMES.iter().find(|theme| theme.name == name)
+}
+
+/// Theme of the request being handled, or the default outside of a request
+pub fn current() -> &'static Theme {
+    CURRENT.try_with(|theme| theme.get()).unwrap_or(&THEMES[0])
+}
+
+/// Selects the theme from `?theme=<name>`, persisting it in a cookie, or from a previously
+/// set cookie. Unknown names fall back to the default theme.
+pub struct ThemeSelector {
+    default: &'static Theme,
+}
+
+impl ThemeSelector {
+    pub fn new(default: &str) -> Self {
+        let default = find(default).unwrap_or_else(|| {
+            tracing::warn!(
+                "unknown default theme {}, using {}",
+                default,
+                THEMES[0].name
+            );
+            &THEMES[0]
+        });
+        ThemeSelector { default }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for ThemeSelector {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        let requested = req
+            .url()
+            .query_pairs()
+            .find(|(key, _)| key == "theme")
+            .and_then(|(_, name)| find(&name));
+        let theme = requested
+            .or_else(|| req.cookie(COOKIE).and_then(|c| find(c.value())))
+            .unwrap_or(self.default);
+
+        CURRENT.with(|current| current.set(theme));
+        let mut res = next.run(req).await;
+        if res.content_type().map(|mime| mime.essence() == "text/html") == Some(true) {
+            // pages render with the theme picked by the cookie
+            headers::vary(&mut res, "Cookie");
+        }
+        if let Some(theme) = requested {
+            let mut cookie = Cookie::new(COOKIE, theme.name);
+            cookie.set_path("/");
+            res.insert_cookie(cookie);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::headers::{COOKIE as COOKIE_HEADER, SET_COOKIE};
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+    use tide::Response;
+
+    async fn get(path: &str, cookie: Option<&str>) -> (HttpResponse, String) {
+        let mut app = tide::new();
+        app.with(ThemeSelector::new("light"));
+        app.at("/").get(|_| async {
+            let mut res = Response::new(200);
+            res.set_body(current().name);
+            Ok(res)
+        });
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if let Some(cookie) = cookie {
+            req.insert_header(COOKIE_HEADER, cookie);
+        }
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        let body = res.body_string().await.unwrap();
+        (res, body)
+    }
+
+    #[async_std::test]
+    async fn themes_are_selected_by_query_then_cookie() {
+        let (res, theme) = get("/", None).await;
+        assert_eq!(theme, "light");
+        assert!(res.header(SET_COOKIE).is_none());
+
+        let (res, theme) = get("/?theme=dark", None).await;
+        assert_eq!(theme, "dark");
+        assert!(res[SET_COOKIE].as_str().starts_with("theme=dark"));
+
+        let (_, theme) = get("/", Some("theme=dark")).await;
+        assert_eq!(theme, "dark");
+        let (res, theme) = get("/?theme=sepia", Some("theme=dark")).await;
+        assert_eq!(theme, "dark");
+        assert!(res.header(SET_COOKIE).is_none());
+        assert_eq!(ThemeSelector::new("sepia").default.name, "light");
+    }
+}
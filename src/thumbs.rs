@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tide::http::headers::CACHE_CONTROL;
+use tide::{Body, Request, Response, StatusCode};
+
+use crate::config::Config;
+use crate::png;
+
+/// Thumbnails never change for a given source file, so they're cached for a year
+const THUMB_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Thumbnails by width and source path, with the modification time of the source
+type Cache = HashMap<(u32, PathBuf), (SystemTime, Vec<u8>)>;
+
+/// Png assets resized on demand, kept until their source file changes
+struct Thumbnails {
+    dir: PathBuf,
+    widths: Vec<u32>,
+    cache: Mutex<Cache>,
+}
+
+/// Serves `/assets/thumb/:w/:path`, the png at `path` of the assets scaled down to one
+/// of the `THUMB_WIDTHS`
+pub fn configure(app: &mut tide::Server<()>, config: &Config) {
+    let thumbs = Arc::new(Thumbnails {
+        dir: Path::new(&config.content_dir).join("assets"),
+        widths: config.thumb_widths.clone(),
+        cache: Mutex::new(HashMap::new()),
+    });
+    app.at("/assets/thumb/:w/*path")
+        .get(move |req: Request<()>| {
+            let thumbs = thumbs.clone();
+            async move { thumbs.serve(&req) }
+        });
+}
+
+impl Thumbnails {
+    fn serve(&self, req: &Request<()>) -> tide::Result<Response> {
+        let width = match req.param("w")?.parse() {
+            Ok(width) if self.widths.contains(&width) => width,
+            _ => return Ok(Response::new(StatusCode::BadRequest)),
+        };
+        let name = Path::new(req.param("path")?);
+        if !name.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Ok(Response::new(StatusCode::NotFound));
+        }
+        if name.extension().and_then(|ext| ext.to_str()) != Some("png") {
+            return Ok(Response::new(StatusCode::UnsupportedMediaType));
+        }
+        let path = self.dir.join(name);
+        let modified = match std::fs::metadata(&path).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return Ok(Response::new(StatusCode::NotFound)),
+        };
+
+        let key = (width, path);
+        let cached = self.cache.lock().unwrap().get(&key).cloned();
+        let thumb = match cached {
+            Some((at, thumb)) if at == modified => thumb,
+            _ => {
+                let image = match png::decode(&std::fs::read(&key.1)?) {
+                    Ok(image) => image,
+                    Err(err) => {
+                        tracing::warn!("can't make a thumbnail of {}: {}", key.1.display(), err);
+                        let status = match err.kind() {
+                            ErrorKind::Unsupported => StatusCode::UnsupportedMediaType,
+                            _ => StatusCode::UnprocessableEntity,
+                        };
+                        return Ok(Response::new(status));
+                    }
+                };
+                let thumb = png::encode(&image.resize(width));
+                let mut cache = self.cache.lock().unwrap();
+                cache.insert(key, (modified, thumb.clone()));
+                thumb
+            }
+        };
+
+        let mut body = Body::from_bytes(thumb);
+        body.set_mime("image/png");
+        let mut res = Response::new(StatusCode::Ok);
+        res.insert_header(CACHE_CONTROL, THUMB_CACHE_CONTROL);
+        res.set_body(body);
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(app: &tide::Server<()>, path: &str) -> HttpResponse {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn thumbnails_are_scaled_down_to_allowed_widths() {
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("assets/covers")).unwrap();
+        let cover = dir.join("assets/covers/cover.png");
+        std::fs::copy("content/assets/window.png", &cover).unwrap();
+        std::fs::write(dir.join("assets/covers/cover.gif"), "GIF89a").unwrap();
+        let config = Config {
+            content_dir: dir.to_string_lossy().into_owned(),
+            thumb_widths: vec![160, 320],
+            ..Config::default()
+        };
+        let mut app = tide::new();
+        configure(&mut app, &config);
+
+        let mut res = get(&app, "/assets/thumb/320/covers/cover.png").await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.content_type().unwrap().essence(), "image/png");
+        assert_eq!(res[CACHE_CONTROL], THUMB_CACHE_CONTROL);
+        let thumb = png::decode(&res.body_bytes().await.unwrap()).unwrap();
+        assert_eq!((thumb.width, thumb.height), (320, 320));
+        let source = png::decode(&std::fs::read(&cover).unwrap()).unwrap();
+        assert_eq!(thumb, source.resize(320));
+
+        for (path, status) in [
+            ("/assets/thumb/300/covers/cover.png", StatusCode::BadRequest),
+            (
+                "/assets/thumb/160/covers/cover.gif",
+                StatusCode::UnsupportedMediaType,
+            ),
+            ("/assets/thumb/160/covers/missing.png", StatusCode::NotFound),
+        ] {
+            assert_eq!(get(&app, path).await.status(), status, "{}", path);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use serde_json::json;
+use std::collections::HashSet;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+use crate::{registry, routes::normalize_path};
+
+/// Answers legally restricted urls with a 451 before they reach the post handlers
+#[derive(Default)]
+pub struct Unavailable {
+    restricted: HashSet<String>,
+    blocked_by: Option<String>,
+}
+
+impl Unavailable {
+    /// Loads the list of restricted urls from a json file such as
+    /// `["/2019/08/14/old", "/about"]`. A missing or invalid file restricts nothing.
+    pub fn from_file(path: &str, blocked_by: Option<&str>) -> Self {
+        let restricted: Vec<String> = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                tracing::warn!("ignoring invalid legal restrictions {}: {}", path, err);
+                Vec::new()
+            }),
+            Err(_) => {
+                tracing::debug!("no legal restrictions found at {}", path);
+                Vec::new()
+            }
+        };
+        Unavailable {
+            restricted: restricted.iter().map(|p| normalize_path(p)).collect(),
+            blocked_by: blocked_by.map(String::from),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for Unavailable {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        if !self.restricted.contains(&normalize_path(req.url().path())) {
+            return Ok(next.run(req).await);
+        }
+
+        let status = StatusCode::UnavailableForLegalReasons;
+        let content = format!("{} {}", status as u16, status.canonical_reason());
+        let mut res = Response::new(status);
+        // names the entity implementing the block, as RFC 7725 suggests
+        if let Some(blocked_by) = &self.blocked_by {
+            res.insert_header("Link", format!("<{}>; rel=\"blocked-by\"", blocked_by));
+        }
+        registry::with(|c| c.render_body(&mut res, "post.html", &json!({ "content": content })));
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    async fn get(app: &tide::Server<()>, path: &str) -> HttpResponse {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn restricted_urls_are_unavailable_for_legal_reasons() {
+        let file = std::env::temp_dir().join(format!("legal-{}.json", std::process::id()));
+        std::fs::write(&file, r#"["/2019/08/14/old/", "/about"]"#).unwrap();
+        let unavailable =
+            Unavailable::from_file(&file.to_string_lossy(), Some("https://lex.example"));
+        std::fs::remove_file(&file).unwrap();
+        let mut app = tide::new();
+        app.with(unavailable);
+        app.at("/*").get(|_| async { Ok("served") });
+
+        for path in ["/2019/08/14/old", "/about/"] {
+            let mut res = get(&app, path).await;
+            assert_eq!(
+                res.status(),
+                StatusCode::UnavailableForLegalReasons,
+                "{}",
+                path
+            );
+            assert_eq!(
+                res["Link"].as_str(),
+                "<https://lex.example>; rel=\"blocked-by\""
+            );
+            assert!(res
+                .body_string()
+                .await
+                .unwrap()
+                .contains("451 Unavailable For Legal Reasons"));
+        }
+        assert_eq!(get(&app, "/todo").await.status(), StatusCode::Ok);
+    }
+
+    #[test]
+    fn missing_or_invalid_lists_restrict_nothing() {
+        assert!(Unavailable::from_file("/nonexistent/legal.json", None)
+            .restricted
+            .is_empty());
+        let file = std::env::temp_dir().join(format!("legal-bad-{}.json", std::process::id()));
+        std::fs::write(&file, "{\"not\": \"a list\"}").unwrap();
+        let unavailable = Unavailable::from_file(&file.to_string_lossy(), None);
+        std::fs::remove_file(&file).unwrap();
+        assert!(unavailable.restricted.is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::CONFIG;
+
+/// Views of each post since the server started
+#[derive(Default)]
+struct Views {
+    counts: HashMap<String, u64>,
+    /// When each client last viewed each post, within the dedup window
+    recent: HashMap<(IpAddr, String), Instant>,
+}
+
+static VIEWS: Lazy<Mutex<Views>> = Lazy::new(|| Mutex::new(Views::default()));
+
+/// Views of a post counted so far
+pub fn count(slug: &str) -> u64 {
+    let views = VIEWS.lock().unwrap();
+    views.counts.get(slug).copied().unwrap_or_default()
+}
+
+/// Counts a view of a post. A client viewing the same post again within
+/// `VIEW_DEDUP_WINDOW` isn't counted twice, views from an unknown client always are.
+/// Counts are kept in memory and start over with the server.
+pub fn record(client: Option<IpAddr>, slug: &str) {
+    let window = CONFIG.with(|c| c.view_dedup_window);
+    let mut views = VIEWS.lock().unwrap();
+    let now = Instant::now();
+    // forget the views past the window, so the map only holds recent clients
+    views
+        .recent
+        .retain(|_, at| now.duration_since(*at) < window);
+    if let Some(ip) = client {
+        if views.recent.contains_key(&(ip, String::from(slug))) {
+            return;
+        }
+        views.recent.insert((ip, String::from(slug)), now);
+    }
+    *views.counts.entry(String::from(slug)).or_default() += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_views_of_a_client_count_once() {
+        let slug = "2020-01-01-views";
+        let client = "192.0.2.1".parse().ok();
+        record(client, slug);
+        record(client, slug);
+        assert_eq!(count(slug), 1);
+        record("192.0.2.2".parse().ok(), slug);
+        assert_eq!(count(slug), 2);
+        record(None, slug);
+        record(None, slug);
+        assert_eq!(count(slug), 4);
+    }
+}